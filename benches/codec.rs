@@ -0,0 +1,107 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use futures::executor::block_on;
+use futures::io::Cursor;
+use futures::stream::StreamExt;
+use simple_message_channels::codec::decode_frame;
+use simple_message_channels::{Message, Reader, Writer};
+
+const SIZES: [usize; 3] = [64, 4096, 1024 * 1024];
+
+// The total size of the messages in each iteration.
+const TOTAL: usize = 4 * 1024 * 1024;
+
+fn messages(size: usize) -> Vec<Message> {
+    (0..TOTAL / size)
+        .map(|i| Message::new(i as u64 % 16, 1, vec![i as u8; size]))
+        .collect()
+}
+
+fn encoded(messages: &[Message]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    for message in messages {
+        message.encode_into(&mut buf).unwrap();
+    }
+    buf
+}
+
+fn encode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("encode");
+    for size in SIZES {
+        let messages = messages(size);
+        let mut buf = Vec::with_capacity(TOTAL * 2);
+        group.throughput(Throughput::Bytes(TOTAL as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(size),
+            &messages,
+            |b, messages| {
+                b.iter(|| {
+                    buf.clear();
+                    for message in messages {
+                        message.encode_into(&mut buf).unwrap();
+                    }
+                    black_box(&buf);
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+fn decode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decode");
+    for size in SIZES {
+        let buf = encoded(&messages(size));
+        group.throughput(Throughput::Bytes(buf.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &buf, |b, buf| {
+            b.iter(|| {
+                let mut buf = &buf[..];
+                while let Some((message, len)) = decode_frame(buf).unwrap() {
+                    black_box(message);
+                    buf = &buf[len..];
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+fn reader(c: &mut Criterion) {
+    let mut group = c.benchmark_group("reader");
+    for size in SIZES {
+        let buf = encoded(&messages(size));
+        group.throughput(Throughput::Bytes(buf.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &buf, |b, buf| {
+            b.iter(|| {
+                let mut reader = Reader::new(Cursor::new(&buf[..]));
+                block_on(async {
+                    while let Some(Ok(message)) = reader.next().await {
+                        black_box(message);
+                    }
+                })
+            })
+        });
+    }
+    group.finish();
+}
+
+fn writer(c: &mut Criterion) {
+    let mut group = c.benchmark_group("writer");
+    for size in SIZES {
+        let messages = messages(size);
+        group.throughput(Throughput::Bytes(TOTAL as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(size),
+            &messages,
+            |b, messages| {
+                b.iter(|| {
+                    let mut writer = Writer::new(Cursor::new(Vec::with_capacity(TOTAL * 2)));
+                    block_on(writer.send_batch(messages)).unwrap();
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, encode, decode, reader, writer);
+criterion_main!(benches);
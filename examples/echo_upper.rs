@@ -12,8 +12,8 @@ fn main() {
 }
 
 async fn echo() -> Result<(), io::Error> {
-    let stdin = io::stdin().lock().await;
-    let stdout = io::stdout().lock().await;
+    let stdin = io::stdin();
+    let stdout = io::stdout();
     let mut reader = Reader::new(stdin);
     let mut writer = Writer::new(stdout);
     while let Some(msg) = reader.next().await {
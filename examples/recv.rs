@@ -12,7 +12,7 @@ fn main() {
 }
 
 async fn recv() -> Result<(), io::Error> {
-    let stdin = io::stdin().lock().await;
+    let stdin = io::stdin();
     let mut reader = Reader::new(stdin);
     while let Some(msg) = reader.next().await {
         let msg = msg?;
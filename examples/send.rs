@@ -6,7 +6,7 @@ fn main() {
 }
 
 async fn send() -> io::Result<()> {
-    let stdout = io::stdout().lock().await;
+    let stdout = io::stdout();
     let mut writer = Writer::new(stdout);
     for i in 0..3 {
         let message = Message::new(i, 1, "hi".as_bytes().to_vec());
@@ -0,0 +1,165 @@
+//! smc-cat
+//!
+//! Frames the lines of stdin as SMC messages, or prints the messages of an SMC stream.
+//! Use it to debug other implementations of SMC by hand.
+//!
+//! Usage:
+//!
+//! echo hello | cargo run --example smc-cat -- encode --channel 1 --typ 2 > frames
+//! cargo run --example smc-cat -- decode < frames
+//!
+//! With `--connect ADDR` or `--listen ADDR`, the messages are sent to or read from a
+//! TCP connection instead of stdio:
+//!
+//! cargo run --example smc-cat -- decode --listen 127.0.0.1:8080
+//! echo hello | cargo run --example smc-cat -- encode --connect 127.0.0.1:8080
+//!
+//! With `--encrypt KEY`, the stream is encrypted with ChaCha20, with a key of 64 hex
+//! digits and a nonce of zeros. This is only meant for debugging, and needs the
+//! `chacha20` feature:
+//!
+//! cargo run --example smc-cat --features chacha20 -- decode --encrypt 0101...01
+
+use async_std::io::{self, BufReader};
+use async_std::net::{TcpListener, TcpStream};
+use async_std::prelude::*;
+use async_std::task;
+use futures::io::{AsyncRead, AsyncWrite};
+use simple_message_channels::{Message, Reader, Writer};
+use std::env;
+use std::error::Error;
+use std::process::exit;
+
+#[cfg(feature = "chacha20")]
+type Cipher = Option<chacha20::ChaCha20>;
+#[cfg(not(feature = "chacha20"))]
+type Cipher = simple_message_channels::NoCipher;
+
+type Result<T> = std::result::Result<T, Box<dyn Error>>;
+
+fn usage() -> ! {
+    eprintln!(
+        "usage: cargo run --example smc-cat -- [encode|decode] [options]
+
+options:
+    --channel N       the channel of encoded messages (default 0)
+    --typ N           the typ of encoded messages (default 1)
+    --encrypt KEY     encrypt with ChaCha20, with a key of 64 hex digits
+    --connect ADDR    send to or read from a TCP connection to ADDR
+    --listen ADDR     send to or read from the first TCP connection on ADDR"
+    );
+    exit(1);
+}
+
+#[derive(Default)]
+struct Options {
+    channel: u64,
+    typ: u8,
+    key: Option<String>,
+    connect: Option<String>,
+    listen: Option<String>,
+}
+
+fn parse_options(mut args: impl Iterator<Item = String>) -> Options {
+    let mut options = Options {
+        typ: 1,
+        ..Default::default()
+    };
+    while let Some(arg) = args.next() {
+        let value = args.next().unwrap_or_else(|| usage());
+        match arg.as_ref() {
+            "--channel" => options.channel = value.parse().unwrap_or_else(|_| usage()),
+            "--typ" => options.typ = value.parse().unwrap_or_else(|_| usage()),
+            "--encrypt" => options.key = Some(value),
+            "--connect" => options.connect = Some(value),
+            "--listen" => options.listen = Some(value),
+            _ => usage(),
+        }
+    }
+    options
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let mode = args.next().unwrap_or_else(|| usage());
+    let options = parse_options(args);
+    task::block_on(async move {
+        let result = match mode.as_ref() {
+            "encode" => encode(options).await,
+            "decode" => decode(options).await,
+            _ => usage(),
+        };
+        if let Err(e) = result {
+            eprintln!("error: {}", e);
+            exit(1);
+        }
+    });
+}
+
+async fn encode(options: Options) -> Result<()> {
+    let cipher = cipher(options.key.as_deref())?;
+    let (_, writer) = open(&options).await?;
+    let mut writer = Writer::new(writer).with_cipher(cipher);
+    let mut lines = BufReader::new(io::stdin()).lines();
+    while let Some(line) = lines.next().await {
+        let message = Message::new(options.channel, options.typ, line?.into_bytes());
+        writer.send(message).await?;
+    }
+    writer.close().await?;
+    Ok(())
+}
+
+async fn decode(options: Options) -> Result<()> {
+    let cipher = cipher(options.key.as_deref())?;
+    let (reader, _) = open(&options).await?;
+    let mut reader = Reader::new(reader).with_cipher(cipher);
+    while let Some(message) = reader.next().await {
+        println!("{}", message?);
+    }
+    Ok(())
+}
+
+type Transport = (
+    Box<dyn AsyncRead + Unpin + Send>,
+    Box<dyn AsyncWrite + Unpin + Send>,
+);
+
+// Open the TCP connection of the options, or stdio.
+async fn open(options: &Options) -> Result<Transport> {
+    let stream = if let Some(address) = &options.connect {
+        TcpStream::connect(address).await?
+    } else if let Some(address) = &options.listen {
+        let listener = TcpListener::bind(address).await?;
+        eprintln!("Listening on {}", listener.local_addr()?);
+        let (stream, peer_addr) = listener.accept().await?;
+        eprintln!("new connection from {}", peer_addr);
+        stream
+    } else {
+        return Ok((Box::new(io::stdin()), Box::new(io::stdout())));
+    };
+    Ok((Box::new(stream.clone()), Box::new(stream)))
+}
+
+#[cfg(feature = "chacha20")]
+fn cipher(key: Option<&str>) -> Result<Cipher> {
+    use simple_message_channels::KeyedCipher;
+
+    let key = match key {
+        Some(key) => key,
+        None => return Ok(None),
+    };
+    let key = (0..key.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(key.get(i..i + 2).unwrap_or("invalid"), 16))
+        .collect::<std::result::Result<Vec<u8>, _>>()
+        .map_err(|_| "the key is not hex")?;
+    Ok(Some(chacha20::ChaCha20::with_key_nonce(&key, &[0; 12])?))
+}
+
+#[cfg(not(feature = "chacha20"))]
+fn cipher(key: Option<&str>) -> Result<Cipher> {
+    match key {
+        Some(_) => Err("--encrypt needs the chacha20 feature".into()),
+        None => Ok(simple_message_channels::NoCipher),
+    }
+}
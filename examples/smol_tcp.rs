@@ -0,0 +1,84 @@
+//! TCP example with smol
+//!
+//! This is the TCP example, using the smol runtime. smol IO types implement the
+//! `futures::io` traits, so they work with simple-message-channels directly.
+//!
+//! Usage:
+//! In one terminal run
+//!
+//! cargo run --example smol_tcp -- server 127.0.0.1:8080
+//!
+//! and in another
+//!
+//! cargo run --example smol_tcp -- client 127.0.0.1:8080
+
+use futures::stream::TryStreamExt;
+use simple_message_channels::{Channel, Message};
+use smol::net::{TcpListener, TcpStream};
+use std::env;
+use std::io::Result;
+
+fn usage() -> ! {
+    println!("usage: cargo run --example smol_tcp -- [client|server] [address]");
+    std::process::exit(1);
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let (mode, address) = match (args.next(), args.next()) {
+        (Some(mode), Some(address)) => (mode, address),
+        _ => usage(),
+    };
+
+    smol::block_on(async move {
+        let result = match mode.as_ref() {
+            "server" => tcp_server(address).await,
+            "client" => tcp_client(address).await,
+            _ => usage(),
+        };
+        if let Err(e) = result {
+            eprintln!("error: {}", e);
+        }
+    });
+}
+
+async fn tcp_server(address: String) -> Result<()> {
+    let listener = TcpListener::bind(&address).await?;
+    println!("Listening on {}", listener.local_addr()?);
+    loop {
+        let (stream, peer_addr) = listener.accept().await?;
+        eprintln!("new connection from {}", peer_addr);
+        smol::spawn(async move {
+            if let Err(e) = handle_incoming(stream).await {
+                eprintln!("connection closed from {}: {}", peer_addr, e);
+            }
+        })
+        .detach();
+    }
+}
+
+async fn tcp_client(address: String) -> Result<()> {
+    let stream = TcpStream::connect(&address).await?;
+    let (mut reader, mut writer) = Channel::new(stream).split();
+    writer.send(Message::new(1, 1, b"hi".to_vec())).await?;
+    while let Some(msg) = reader.try_next().await? {
+        eprintln!(
+            "received: chan {} typ {} msg {:?}",
+            msg.channel, msg.typ, msg.message
+        );
+    }
+    Ok(())
+}
+
+async fn handle_incoming(stream: TcpStream) -> Result<()> {
+    let (mut reader, mut writer) = Channel::new(stream).split();
+    while let Some(msg) = reader.try_next().await? {
+        eprintln!(
+            "received: chan {} typ {} msg {:?}",
+            msg.channel, msg.typ, msg.message
+        );
+        let resp = Message::new(msg.channel, 2, msg.message.to_ascii_uppercase());
+        writer.send(resp).await?;
+    }
+    Ok(())
+}
@@ -17,16 +17,13 @@
 use async_std::net::{TcpListener, TcpStream};
 use async_std::prelude::*;
 use async_std::task;
-use async_std::task::{Context, Poll};
-use futures::io::{AsyncRead, AsyncWrite};
+use futures::io::{ReadHalf, WriteHalf};
 use futures::stream::TryStreamExt;
-use simple_message_channels::{Message, Reader, Writer};
+use simple_message_channels::{Channel, Message, Reader, Writer};
 use std::env;
 use std::io::{ErrorKind, Result};
-use std::pin::Pin;
-use std::sync::Arc;
 
-fn usage() {
+fn usage() -> ! {
     println!("usage: cargo run --example tcp -- [client|server] [address]");
     std::process::exit(1);
 }
@@ -43,7 +40,7 @@ fn main() {
         let result = match mode.as_ref() {
             "server" => tcp_server(address).await,
             "client" => tcp_client(address).await,
-            _ => panic!(usage()),
+            _ => usage(),
         };
         if let Err(e) = result {
             eprintln!("error: {}", e);
@@ -107,11 +104,10 @@ async fn handle_outgoing(stream: TcpStream) -> Result<()> {
     Ok(())
 }
 
-fn create_from_stream(tcp_stream: TcpStream) -> (Reader<CloneableStream>, Writer<CloneableStream>) {
-    let stream = CloneableStream(Arc::new(tcp_stream));
-    let reader = Reader::new(stream.clone());
-    let writer = Writer::new(stream.clone());
-    (reader, writer)
+fn create_from_stream(
+    tcp_stream: TcpStream,
+) -> (Reader<ReadHalf<TcpStream>>, Writer<WriteHalf<TcpStream>>) {
+    Channel::new(tcp_stream).split()
 }
 
 fn format_msg(msg: &Message) -> String {
@@ -127,22 +123,3 @@ fn to_upper(bytes: &[u8]) -> Vec<u8> {
     let string = String::from_utf8(bytes.to_vec()).unwrap();
     string.to_uppercase().as_bytes().to_vec()
 }
-
-#[derive(Clone)]
-struct CloneableStream(Arc<TcpStream>);
-impl AsyncRead for CloneableStream {
-    fn poll_read(self: Pin<&mut Self>, cx: &mut Context, buf: &mut [u8]) -> Poll<Result<usize>> {
-        Pin::new(&mut &*self.0).poll_read(cx, buf)
-    }
-}
-impl AsyncWrite for CloneableStream {
-    fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<Result<usize>> {
-        Pin::new(&mut &*self.0).poll_write(cx, buf)
-    }
-    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<()>> {
-        Pin::new(&mut &*self.0).poll_flush(cx)
-    }
-    fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<()>> {
-        Pin::new(&mut &*self.0).poll_close(cx)
-    }
-}
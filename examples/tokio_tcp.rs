@@ -0,0 +1,90 @@
+//! TCP example with tokio
+//!
+//! This is the TCP example, using the tokio runtime. It needs the `tokio` feature,
+//! which adapts tokio IO types to the `futures::io` traits.
+//!
+//! Usage:
+//! In one terminal run
+//!
+//! cargo run --features tokio --example tokio_tcp -- server 127.0.0.1:8080
+//!
+//! and in another
+//!
+//! cargo run --features tokio --example tokio_tcp -- client 127.0.0.1:8080
+
+use futures::stream::TryStreamExt;
+use simple_message_channels::{Message, TokioReader, TokioWriter};
+use std::env;
+use std::io::Result;
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpListener, TcpStream};
+
+fn usage() -> ! {
+    println!("usage: cargo run --features tokio --example tokio_tcp -- [client|server] [address]");
+    std::process::exit(1);
+}
+
+#[tokio::main]
+async fn main() {
+    let mut args = env::args().skip(1);
+    let (mode, address) = match (args.next(), args.next()) {
+        (Some(mode), Some(address)) => (mode, address),
+        _ => usage(),
+    };
+    let result = match mode.as_ref() {
+        "server" => tcp_server(address).await,
+        "client" => tcp_client(address).await,
+        _ => usage(),
+    };
+    if let Err(e) = result {
+        eprintln!("error: {}", e);
+    }
+}
+
+async fn tcp_server(address: String) -> Result<()> {
+    let listener = TcpListener::bind(&address).await?;
+    println!("Listening on {}", listener.local_addr()?);
+    loop {
+        let (stream, peer_addr) = listener.accept().await?;
+        eprintln!("new connection from {}", peer_addr);
+        tokio::spawn(async move {
+            if let Err(e) = handle_incoming(stream).await {
+                eprintln!("connection closed from {}: {}", peer_addr, e);
+            }
+        });
+    }
+}
+
+async fn tcp_client(address: String) -> Result<()> {
+    let stream = TcpStream::connect(&address).await?;
+    let (mut reader, mut writer) = split(stream);
+    writer.send(Message::new(1, 1, b"hi".to_vec())).await?;
+    while let Some(msg) = reader.try_next().await? {
+        eprintln!(
+            "received: chan {} typ {} msg {:?}",
+            msg.channel, msg.typ, msg.message
+        );
+    }
+    Ok(())
+}
+
+async fn handle_incoming(stream: TcpStream) -> Result<()> {
+    let (mut reader, mut writer) = split(stream);
+    while let Some(msg) = reader.try_next().await? {
+        eprintln!(
+            "received: chan {} typ {} msg {:?}",
+            msg.channel, msg.typ, msg.message
+        );
+        let resp = Message::new(msg.channel, 2, msg.message.to_ascii_uppercase());
+        writer.send(resp).await?;
+    }
+    Ok(())
+}
+
+fn split(stream: TcpStream) -> (TokioReader<OwnedReadHalf>, TokioWriter<OwnedWriteHalf>) {
+    let (reader, writer) = stream.into_split();
+    (
+        TokioReader::from_tokio(reader),
+        TokioWriter::from_tokio(writer),
+    )
+}
@@ -0,0 +1,13 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use simple_message_channels::codec::decode_frame;
+
+// Decode all frames in the input, until an error or an incomplete frame.
+fuzz_target!(|data: &[u8]| {
+    let mut buf = data;
+    while let Ok(Some((_message, len))) = decode_frame(buf) {
+        assert!(len > 0 && len <= buf.len());
+        buf = &buf[len..];
+    }
+});
@@ -0,0 +1,10 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use simple_message_channels::testing::assert_roundtrip;
+use simple_message_channels::Message;
+
+// Arbitrary messages encode and decode back to the same message.
+fuzz_target!(|message: Message| {
+    assert_roundtrip(&message);
+});
@@ -0,0 +1,18 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use simple_message_channels::codec::decode_frame;
+
+// Messages that decode successfully encode back to the same message.
+fuzz_target!(|data: &[u8]| {
+    if let Ok(Some((message, _))) = decode_frame(data) {
+        let buf = message.encode().expect("decoded message encodes");
+        let (decoded, len) = decode_frame(&buf)
+            .expect("encoded message decodes")
+            .expect("encoded message is complete");
+        assert_eq!(len, buf.len());
+        assert_eq!(decoded.channel, message.channel);
+        assert_eq!(decoded.typ, message.typ);
+        assert_eq!(decoded.message, message.message);
+    }
+});
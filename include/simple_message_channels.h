@@ -0,0 +1,94 @@
+#ifndef SIMPLE_MESSAGE_CHANNELS_H
+#define SIMPLE_MESSAGE_CHANNELS_H
+
+/* Generated with cbindgen from src/ffi.rs, do not edit. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// The result of a function of the bindings.
+typedef enum SmcStatus {
+  // The function succeeded.
+  SMC_STATUS_OK = 0,
+  // The decoder needs more bytes to decode the next message.
+  SMC_STATUS_PENDING = 1,
+  // The output buffer is too small. The needed length is written to `out_len`.
+  SMC_STATUS_BUFFER_TOO_SMALL = 2,
+  // A pointer is null.
+  SMC_STATUS_NULL_POINTER = 3,
+  // The message can't be encoded, e.g. because its typ is larger than 15.
+  SMC_STATUS_INVALID_MESSAGE = 4,
+  // The stream can't be decoded. The decoder yields no more messages.
+  SMC_STATUS_INVALID_DATA = 5,
+} SmcStatus;
+
+// An incremental decoder, see [`Decoder`].
+//
+// Create it with [`smc_decoder_new`], and free it with [`smc_decoder_free`].
+typedef struct SmcDecoder SmcDecoder;
+
+// A decoded message, which borrows its payload from a [`SmcDecoder`].
+typedef struct SmcMessage {
+  // The channel of the message.
+  uint64_t channel;
+  // The typ of the message.
+  uint8_t typ;
+  // The payload of the message, valid until the next call with the decoder.
+  const uint8_t *data;
+  // The length of the payload.
+  size_t len;
+} SmcMessage;
+
+// Encode a message into `out`, and write the length of the encoded message to
+// `out_len`.
+//
+// If `out_cap` is too small, nothing is written to `out`, the needed length is written
+// to `out_len`, and this returns [`SmcStatus::BufferTooSmall`].
+//
+// # Safety
+//
+// `data` must point to `len` readable bytes, `out` to `out_cap` writable bytes, and
+// `out_len` to a writable `size_t`. `data` and `out` may be null if their length is 0.
+SmcStatus smc_encode(uint64_t channel,
+                     uint8_t typ,
+                     const uint8_t *data,
+                     size_t len,
+                     uint8_t *out,
+                     size_t out_cap,
+                     size_t *out_len);
+
+// Create a new decoder.
+SmcDecoder *smc_decoder_new(void);
+
+// Free a decoder, and the payload of the last message it returned.
+//
+// # Safety
+//
+// `decoder` must be returned by [`smc_decoder_new`] and not be freed yet, or be null.
+void smc_decoder_free(SmcDecoder *decoder);
+
+// Add `len` bytes at `data` to the decoder.
+//
+// This does not decode the bytes, take the messages out with [`smc_decoder_next`].
+//
+// # Safety
+//
+// `decoder` must be a valid decoder, and `data` must point to `len` readable bytes. `data`
+// may be null if `len` is 0.
+SmcStatus smc_decoder_push(SmcDecoder *decoder, const uint8_t *data, size_t len);
+
+// Take the next complete message out of the decoder, and write it to `out`.
+//
+// Returns [`SmcStatus::Pending`] if more bytes are needed. The payload of the message
+// is owned by the decoder, and is valid until the next call with the decoder.
+// Keepalive messages are skipped.
+//
+// # Safety
+//
+// `decoder` must be a valid decoder, and `out` must point to a writable [`SmcMessage`].
+SmcStatus smc_decoder_next(SmcDecoder *decoder, SmcMessage *out);
+
+#endif /* SIMPLE_MESSAGE_CHANNELS_H */
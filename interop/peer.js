@@ -0,0 +1,32 @@
+// Echo peer for the interop tests, see tests/interop.rs.
+//
+// Decodes the SMC frames on stdin with the reference implementation, and encodes the
+// messages of every chunk again with `sendBatch` on stdout. An empty message with typ 15
+// on channel 0 asks for a keepalive instead, which is written as a single zero byte.
+const SMC = require('simple-message-channels')
+
+let batch = []
+
+const smc = new SMC({
+  onmessage (channel, type, message) {
+    if (channel === 0 && type === 15 && message.length === 0) {
+      flush()
+      process.stdout.write(Buffer.from([0]))
+      return
+    }
+    batch.push({ channel, type, message })
+  }
+})
+
+function flush () {
+  if (batch.length) process.stdout.write(smc.sendBatch(batch))
+  batch = []
+}
+
+process.stdin.on('data', function (data) {
+  if (!smc.recv(data)) {
+    console.error('peer.js:', smc.error)
+    process.exit(1)
+  }
+  flush()
+})
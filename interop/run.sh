@@ -0,0 +1,7 @@
+#!/bin/sh
+# Install the JS implementation, and run the interop tests against it.
+set -e
+cd "$(dirname "$0")"
+npm install --no-audit --no-fund
+cd ..
+cargo test --test interop -- --ignored "$@"
@@ -0,0 +1,224 @@
+//! Python bindings for simple-message-channels.
+//!
+//! The `smc` module has `Message`, `Encoder` and `Decoder` classes over the codec of
+//! the crate, which does no IO. Build and install it into the current virtualenv with
+//! [maturin](https://github.com/PyO3/maturin):
+//!
+//! ```sh
+//! cd python && maturin develop
+//! ```
+//!
+//! Then, in Python:
+//!
+//! ```python
+//! import smc
+//!
+//! encoder, decoder = smc.Encoder(), smc.Decoder()
+//! data = encoder.encode(smc.Message(1, 2, b"hello"))
+//! for message in decoder.push(data):
+//!     print(message.channel, message.typ, message.payload)
+//! ```
+
+use pyo3::create_exception;
+use pyo3::exceptions::PyException;
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+
+use simple_message_channels::codec;
+
+create_exception!(
+    smc,
+    SmcError,
+    PyException,
+    "An error when encoding or decoding SMC messages."
+);
+
+fn to_py_err(error: simple_message_channels::SmcError) -> PyErr {
+    SmcError::new_err(error.to_string())
+}
+
+/// A message with a channel, a typ and a payload.
+#[pyclass(module = "smc", eq)]
+#[derive(Clone, PartialEq)]
+struct Message {
+    /// The channel of the message.
+    #[pyo3(get, set)]
+    channel: u64,
+    /// The typ of the message, at most 15.
+    #[pyo3(get, set)]
+    typ: u8,
+    payload: Vec<u8>,
+}
+
+impl Message {
+    fn as_message(&self) -> simple_message_channels::Message<&[u8]> {
+        simple_message_channels::Message::borrowed(self.channel, self.typ, &self.payload)
+    }
+}
+
+impl From<simple_message_channels::Message> for Message {
+    fn from(message: simple_message_channels::Message) -> Self {
+        Self {
+            channel: message.channel,
+            typ: message.typ,
+            payload: message.message,
+        }
+    }
+}
+
+#[pymethods]
+impl Message {
+    #[new]
+    #[pyo3(signature = (channel, typ, payload = None))]
+    fn new(channel: u64, typ: u8, payload: Option<&[u8]>) -> Self {
+        Self {
+            channel,
+            typ,
+            payload: payload.unwrap_or_default().to_vec(),
+        }
+    }
+
+    /// A keepalive message, which the decoder skips by default.
+    #[staticmethod]
+    fn keepalive() -> Self {
+        simple_message_channels::Message::<Vec<u8>>::keepalive().into()
+    }
+
+    /// The payload of the message.
+    #[getter]
+    fn payload<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new_bound(py, &self.payload)
+    }
+
+    #[setter]
+    fn set_payload(&mut self, payload: &[u8]) {
+        self.payload = payload.to_vec();
+    }
+
+    /// Encode the message as a frame, with its length prefix.
+    fn encode<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyBytes>> {
+        let frame = self.as_message().encode().map_err(to_py_err)?;
+        Ok(PyBytes::new_bound(py, &frame))
+    }
+
+    /// Decode a single frame, with its length prefix.
+    #[staticmethod]
+    fn decode(frame: &[u8]) -> PyResult<Self> {
+        match codec::decode_frame(frame).map_err(to_py_err)? {
+            Some((message, len)) if len == frame.len() => Ok(message.into()),
+            _ => Err(SmcError::new_err("Not a single complete frame")),
+        }
+    }
+
+    fn __repr__(&self, py: Python<'_>) -> PyResult<String> {
+        let payload = PyBytes::new_bound(py, &self.payload).repr()?;
+        Ok(format!(
+            "Message(channel={}, typ={}, payload={})",
+            self.channel, self.typ, payload
+        ))
+    }
+}
+
+/// Encodes messages into bytes, see `simple_message_channels::Encoder`.
+#[pyclass(module = "smc")]
+struct Encoder {
+    encoder: codec::Encoder,
+}
+
+#[pymethods]
+impl Encoder {
+    #[new]
+    fn new() -> Self {
+        Self {
+            encoder: codec::Encoder::new(),
+        }
+    }
+
+    /// Encode a message.
+    fn encode<'py>(&mut self, py: Python<'py>, message: &Message) -> PyResult<Bound<'py, PyBytes>> {
+        let mut buf = Vec::new();
+        self.encoder
+            .encode_into(&message.as_message(), &mut buf)
+            .map_err(to_py_err)?;
+        Ok(PyBytes::new_bound(py, &buf))
+    }
+
+    /// Encode a batch of messages into a single buffer.
+    fn encode_all<'py>(
+        &mut self,
+        py: Python<'py>,
+        messages: Vec<PyRef<'py, Message>>,
+    ) -> PyResult<Bound<'py, PyBytes>> {
+        let mut buf = Vec::new();
+        for message in messages {
+            self.encoder
+                .encode_into(&message.as_message(), &mut buf)
+                .map_err(to_py_err)?;
+        }
+        Ok(PyBytes::new_bound(py, &buf))
+    }
+}
+
+/// Decodes messages from chunks of bytes, see `simple_message_channels::Decoder`.
+///
+/// Iterating over the decoder yields the messages that are complete.
+#[pyclass(module = "smc")]
+struct Decoder {
+    decoder: codec::Decoder,
+}
+
+#[pymethods]
+impl Decoder {
+    #[new]
+    #[pyo3(signature = (keepalives = false))]
+    fn new(keepalives: bool) -> Self {
+        Self {
+            decoder: codec::Decoder::new().with_keepalives(keepalives),
+        }
+    }
+
+    /// The number of bytes that are buffered but not yet decoded.
+    #[getter]
+    fn buffered(&self) -> usize {
+        self.decoder.buffered()
+    }
+
+    /// Add bytes to the decoder, without decoding them.
+    fn extend(&mut self, data: &[u8]) {
+        self.decoder.extend(data);
+    }
+
+    /// Add bytes to the decoder, and return all messages that are complete.
+    fn push(&mut self, data: &[u8]) -> PyResult<Vec<Message>> {
+        let messages = self.decoder.push(data).map_err(to_py_err)?;
+        Ok(messages.into_iter().map(Message::from).collect())
+    }
+
+    /// Take the next complete message out of the decoder, or `None` if more bytes are
+    /// needed.
+    fn next_message(&mut self) -> PyResult<Option<Message>> {
+        let message = self.decoder.poll_message().map_err(to_py_err)?;
+        Ok(message.map(Message::from))
+    }
+
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&mut self) -> PyResult<Option<Message>> {
+        self.next_message()
+    }
+}
+
+#[pymodule]
+fn smc(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<Message>()?;
+    m.add_class::<Encoder>()?;
+    m.add_class::<Decoder>()?;
+    m.add("SmcError", m.py().get_type_bound::<SmcError>())?;
+    m.add(
+        "MAX_MESSAGE_SIZE",
+        simple_message_channels::MAX_MESSAGE_SIZE,
+    )?;
+    Ok(())
+}
@@ -0,0 +1,188 @@
+//! A blocking bridge to an async [`Channel`].
+//!
+//! A [`Bridge`] runs a channel on a dedicated thread, and sends and receives its
+//! messages with blocking calls. Use it to embed SMC where async code can't run, e.g.
+//! in the synchronous callbacks of a plugin or behind an FFI boundary. For blocking
+//! [`std::io`] streams, see [`crate::sync`] instead, which needs no thread.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! use simple_message_channels::blocking::Bridge;
+//! use simple_message_channels::{Channel, Message};
+//!
+//! let stream = async_std::task::block_on(async_std::net::TcpStream::connect("127.0.0.1:8000"))?;
+//! let bridge = Bridge::new(Channel::new(stream));
+//! bridge.send(Message::new(1, 0, b"hello".to_vec()))?;
+//! if let Some(message) = bridge.recv() {
+//!     println!("{:?}", message?);
+//! }
+//! bridge.close()?;
+//! # Ok(())
+//! # }
+//! ```
+
+use futures::channel::mpsc;
+use futures::executor::block_on;
+use futures::future::poll_fn;
+use futures::io::{AsyncRead, AsyncWrite};
+use futures::ready;
+use futures::sink::Sink;
+use futures::stream::StreamExt;
+use futures::task::{Context, Poll};
+use std::collections::VecDeque;
+use std::io::{Error, ErrorKind};
+use std::pin::Pin;
+use std::sync::mpsc::{sync_channel, SyncSender};
+use std::thread::{self, JoinHandle};
+
+use crate::{Channel, FrameCipher, Message, SmcError};
+
+/// A request from a [`Bridge`] to its thread.
+enum Command {
+    Send(Message, SyncSender<Result<(), SmcError>>),
+    Recv(SyncSender<Option<Result<Message, SmcError>>>),
+    Close(SyncSender<Result<(), SmcError>>),
+}
+
+/// A [`Channel`] that runs on a dedicated thread, with blocking send and receive.
+///
+/// The thread drives the channel with [`futures::executor::block_on`], so the transport
+/// must not depend on the reactor of another runtime, e.g. a tokio stream needs to be
+/// created inside a tokio runtime. Transports of async-std or smol work.
+///
+/// The methods take `&self`, so a bridge can be shared between threads, e.g. with an
+/// [`std::sync::Arc`]. A message is only read from the channel while a thread waits in
+/// [`Bridge::recv`], and a thread that waits for a message does not block the threads
+/// that send.
+///
+/// Dropping the bridge stops the thread once the message that is being sent is written.
+/// Use [`Bridge::close`] to also flush and close the channel, and wait for the thread.
+pub struct Bridge {
+    commands: mpsc::UnboundedSender<Command>,
+    thread: JoinHandle<()>,
+}
+
+impl Bridge {
+    /// Run `channel` on a new thread.
+    pub fn new<T, C>(channel: Channel<T, C>) -> Self
+    where
+        T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+        C: FrameCipher + Unpin + Send + 'static,
+    {
+        let (commands, receiver) = mpsc::unbounded();
+        let mut driver = Driver {
+            channel,
+            commands: receiver,
+            sending: None,
+            closing: None,
+            receivers: VecDeque::new(),
+        };
+        let thread = thread::Builder::new()
+            .name("smc-bridge".into())
+            .spawn(move || block_on(poll_fn(|cx| driver.poll(cx))))
+            .expect("failed to spawn the bridge thread");
+        Self { commands, thread }
+    }
+
+    /// Send a message, and block until it is written and flushed.
+    ///
+    /// See [`Writer::send`](crate::Writer::send).
+    pub fn send(&self, message: Message) -> Result<(), SmcError> {
+        let (reply, result) = sync_channel(1);
+        self.request(Command::Send(message, reply))?;
+        result.recv().unwrap_or_else(|_| Err(stopped()))
+    }
+
+    /// Block until the next message is received.
+    ///
+    /// Returns `None` at the end of the stream, after an error and if the thread
+    /// stopped.
+    pub fn recv(&self) -> Option<Result<Message, SmcError>> {
+        let (reply, result) = sync_channel(1);
+        self.request(Command::Recv(reply)).ok()?;
+        result.recv().ok()?
+    }
+
+    /// Flush and close the channel, and wait for the thread to stop.
+    ///
+    /// Threads that wait in [`Bridge::recv`] get `None`.
+    pub fn close(self) -> Result<(), SmcError> {
+        let (reply, result) = sync_channel(1);
+        self.request(Command::Close(reply))?;
+        let result = result.recv().unwrap_or_else(|_| Err(stopped()));
+        drop(self.commands);
+        self.thread.join().map_err(|_| stopped())?;
+        result
+    }
+
+    fn request(&self, command: Command) -> Result<(), SmcError> {
+        self.commands.unbounded_send(command).map_err(|_| stopped())
+    }
+}
+
+// The error when the thread of a bridge stopped, e.g. because it panicked.
+fn stopped() -> SmcError {
+    Error::new(ErrorKind::BrokenPipe, "Bridge thread stopped").into()
+}
+
+/// The future that runs on the thread of a [`Bridge`].
+struct Driver<T, C> {
+    channel: Channel<T, C>,
+    commands: mpsc::UnboundedReceiver<Command>,
+    // The message that is being sent.
+    sending: Option<(Message, SyncSender<Result<(), SmcError>>)>,
+    // The thread that waits for the channel to close.
+    closing: Option<SyncSender<Result<(), SmcError>>>,
+    // The threads that wait for a message, in order.
+    receivers: VecDeque<SyncSender<Option<Result<Message, SmcError>>>>,
+}
+
+impl<T, C> Driver<T, C>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+    C: FrameCipher + Unpin,
+{
+    fn poll(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        self.poll_recv(cx);
+        loop {
+            if let Some((message, reply)) = &self.sending {
+                let result = ready!(self.channel.writer().poll_send(cx, message));
+                // The sender may have given up, e.g. if it panicked.
+                let _ = reply.send(result);
+                self.sending = None;
+            }
+            if let Some(reply) = &self.closing {
+                let writer = Pin::new(self.channel.writer());
+                let result = ready!(Sink::<Message>::poll_close(writer, cx));
+                let _ = reply.send(result);
+                return Poll::Ready(());
+            }
+            match self.commands.poll_next_unpin(cx) {
+                Poll::Ready(Some(Command::Send(message, reply))) => {
+                    self.sending = Some((message, reply));
+                }
+                Poll::Ready(Some(Command::Recv(reply))) => {
+                    self.receivers.push_back(reply);
+                    self.poll_recv(cx);
+                }
+                Poll::Ready(Some(Command::Close(reply))) => self.closing = Some(reply),
+                Poll::Ready(None) => return Poll::Ready(()),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+
+    // Read messages for the threads that wait for one.
+    fn poll_recv(&mut self, cx: &mut Context<'_>) {
+        while !self.receivers.is_empty() {
+            let next = match self.channel.reader().poll_next_message(cx) {
+                Poll::Ready(next) => next,
+                Poll::Pending => return,
+            };
+            let reply = self.receivers.pop_front().expect("a receiver is waiting");
+            let _ = reply.send(next);
+        }
+    }
+}
@@ -0,0 +1,298 @@
+use futures::io::{AsyncRead, AsyncReadExt, AsyncWrite, ReadHalf, WriteHalf};
+use futures::sink::Sink;
+use futures::stream::Stream;
+use futures::task::{Context, Poll};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use crate::debug::Direction;
+use crate::lifecycle::ConnectionEvents;
+use crate::{
+    CipherStart, FlushPolicy, FrameCipher, Message, NoCipher, Reader, SeekableCipher, SessionState,
+    SmcError, Writer,
+};
+
+/// The reading half of a [`Channel`].
+pub type ChannelReader<T, C = NoCipher> = Reader<ReadHalf<T>, Vec<u8>, C>;
+
+/// The writing half of a [`Channel`].
+pub type ChannelWriter<T, C = NoCipher> = Writer<WriteHalf<T>, C>;
+
+/// A duplex SMC channel.
+///
+/// Owns both directions of a single transport (e.g. a TCP stream). The channel is a
+/// [`futures::stream::Stream`] of incoming [`Message`]s and a [`futures::sink::Sink`]
+/// for outgoing messages. Use [`Channel::split`] to get separate [`Reader`] and
+/// [`Writer`] halves, e.g. to move them to different tasks. The halves are owned, and
+/// keep working after the channel is dropped.
+///
+/// The channel can be encrypted with [`Channel::with_cipher`], and upgrade its ciphers
+/// after a handshake with [`Channel::with_cipher_upgrade`], which keeps working after
+/// the channel is split.
+pub struct Channel<T, C = NoCipher> {
+    reader: ChannelReader<T, C>,
+    writer: ChannelWriter<T, C>,
+}
+
+impl<T> Channel<T>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    /// Create a new channel from any transport that is both
+    /// [`futures::io::AsyncRead`] and [`futures::io::AsyncWrite`].
+    pub fn new(stream: T) -> Self {
+        let (reader, writer) = stream.split();
+        Self {
+            reader: Reader::new(reader),
+            writer: Writer::new(writer),
+        }
+    }
+}
+
+impl<T, C> Channel<T, C>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+    C: FrameCipher,
+{
+    /// Encrypt the channel, with `rx` for incoming and `tx` for outgoing messages.
+    ///
+    /// See [`Reader::with_cipher`] and [`Writer::with_cipher`].
+    pub fn with_cipher<D: FrameCipher>(self, rx: D, tx: D) -> Channel<T, D> {
+        Channel {
+            reader: self.reader.with_cipher(rx),
+            writer: self.writer.with_cipher(tx),
+        }
+    }
+
+    /// Start the ciphers of both directions at `start`, see
+    /// [`Reader::cipher_start_after`].
+    pub fn cipher_start_after(self, start: CipherStart) -> Self {
+        Channel {
+            reader: self.reader.cipher_start_after(start),
+            writer: self.writer.cipher_start_after(start),
+        }
+    }
+
+    /// Measure the timers of both halves with `clock`, see [`Reader::with_clock`].
+    #[cfg(feature = "sim")]
+    pub fn with_clock<K: crate::Clock + Clone + 'static>(self, clock: K) -> Self {
+        Channel {
+            reader: self.reader.with_clock(clock.clone()),
+            writer: self.writer.with_clock(clock),
+        }
+    }
+
+    /// Set when sends flush, see [`Writer::with_flush_policy`].
+    pub fn with_flush_policy(self, policy: FlushPolicy) -> Self {
+        Channel {
+            reader: self.reader,
+            writer: self.writer.with_flush_policy(policy),
+        }
+    }
+
+    /// Upgrade the ciphers of both directions once a handshake completes.
+    ///
+    /// `upgrade` is called with every incoming message. If it returns a pair of ciphers
+    /// `(rx, tx)`, `rx` decrypts the incoming messages after this one, and `tx`
+    /// encrypts the outgoing messages that are sent from now on. This also works after
+    /// the channel is split and its halves are used on different tasks. See
+    /// [`Reader::with_cipher_hook`] and [`Writer::cipher_handle`].
+    pub fn with_cipher_upgrade<F>(mut self, mut upgrade: F) -> Self
+    where
+        F: FnMut(&Message<&[u8]>) -> Option<(C, C)> + Send + 'static,
+        C: Send + 'static,
+    {
+        let tx_cipher = self.writer.cipher_handle();
+        self.reader = self.reader.with_upgrade_hook(move |message, rx_cipher| {
+            if let Some((rx, tx)) = upgrade(message) {
+                *rx_cipher = rx;
+                tx_cipher.set(tx);
+                true
+            } else {
+                false
+            }
+        });
+        self
+    }
+
+    /// Send a message.
+    ///
+    /// See [`Writer::send`].
+    pub async fn send<B: AsRef<[u8]>>(&mut self, message: Message<B>) -> Result<(), SmcError> {
+        self.writer.send(message).await
+    }
+
+    /// Flush the queued messages.
+    ///
+    /// See [`Writer::flush`].
+    pub async fn flush(&mut self) -> Result<(), SmcError> {
+        self.writer.flush().await
+    }
+
+    /// Close the writing half, and keep reading until the peer closes its side.
+    ///
+    /// See [`Writer::close`].
+    pub async fn close(&mut self) -> Result<(), SmcError> {
+        self.writer.close().await
+    }
+
+    /// A stream of the [`ConnectionEvent`](crate::ConnectionEvent)s of the channel, e.g.
+    /// to react when the handshake completes or the peer closes the connection.
+    ///
+    /// This also works after the channel is split. Calling this again replaces the
+    /// previous stream, which ends.
+    pub fn events(&mut self) -> ConnectionEvents {
+        let (events, sender) = ConnectionEvents::new();
+        self.reader.set_events(sender.clone());
+        self.writer.set_events(sender);
+        events
+    }
+
+    /// Call `observer` with every message that is read or sent on the channel.
+    ///
+    /// See [`Reader::set_frame_observer`] and [`Writer::set_frame_observer`]. The
+    /// observer is shared by both halves, also after [`Channel::split`].
+    pub fn set_frame_observer<F>(&mut self, observer: F)
+    where
+        F: FnMut(Direction, &Message<&[u8]>) + Send + 'static,
+    {
+        let observer = Arc::new(Mutex::new(observer));
+        let read = observer.clone();
+        self.reader.set_frame_observer(move |direction, message| {
+            (read.lock().expect("observer lock poisoned"))(direction, message)
+        });
+        self.writer.set_frame_observer(move |direction, message| {
+            (observer.lock().expect("observer lock poisoned"))(direction, message)
+        });
+    }
+
+    /// Remove the observer set with [`Channel::set_frame_observer`].
+    pub fn clear_frame_observer(&mut self) {
+        self.reader.clear_frame_observer();
+        self.writer.clear_frame_observer();
+    }
+
+    /// When the last message was read or written on `channel`.
+    ///
+    /// This needs stats, see [`Reader::with_stats`] and [`Writer::with_stats`], and is
+    /// `None` if no message was read or written on the channel with stats enabled.
+    pub fn last_activity(&self, channel: u64) -> Option<Instant> {
+        let read = self.reader.channel_stats(channel).map(|s| s.last_activity);
+        let written = self.writer.channel_stats(channel).map(|s| s.last_activity);
+        read.max(written)
+    }
+
+    /// Get a mutable reference to the reading half.
+    pub fn reader(&mut self) -> &mut ChannelReader<T, C> {
+        &mut self.reader
+    }
+
+    /// Get a mutable reference to the writing half.
+    pub fn writer(&mut self) -> &mut ChannelWriter<T, C> {
+        &mut self.writer
+    }
+
+    /// Consume the channel, returning the transport and the bytes that were read from it
+    /// but not parsed yet.
+    ///
+    /// See [`Reader::into_inner`] and [`Writer::into_inner`].
+    pub fn into_inner(self) -> (T, Vec<u8>) {
+        let (reader, buffered) = self.reader.into_inner();
+        let writer = self.writer.into_inner();
+        let stream = reader.reunite(writer).expect("halves of the same stream");
+        (stream, buffered)
+    }
+
+    /// Export the state of the session, to resume it on another transport with
+    /// [`Channel::import_state`].
+    ///
+    /// The state contains the bytes of a partially read frame, the bytes that were read
+    /// ahead, and the queued messages, so the channel doesn't need to be flushed first.
+    /// These bytes are then part of the state, and this channel stops: it reads no more
+    /// messages, and sending and flushing fail with [`SmcError::Closed`]. Drop it, or
+    /// take the transport with [`Channel::into_inner`].
+    ///
+    /// Fails with [`SmcError::InvalidSession`] if the reader stopped after an error or
+    /// is skipping a frame, and with [`SmcError::Closed`] if the writer was closed. The
+    /// channel is then left as it was.
+    pub fn export_state(&mut self) -> Result<SessionState, SmcError>
+    where
+        C: SeekableCipher,
+    {
+        if self.writer.is_closed() {
+            return Err(SmcError::Closed);
+        }
+        // Exporting the reader doesn't change it, and the writer can't fail anymore.
+        let read = self.reader.export_state()?;
+        let write = self.writer.export_state()?;
+        self.reader.finish_export();
+        Ok(SessionState { read, write })
+    }
+
+    /// Resume the session of `state`, which was exported with [`Channel::export_state`].
+    ///
+    /// Call this on a new channel, once its ciphers are set up with the same keys as the
+    /// exported channel, e.g. with [`Channel::with_cipher`]. The ciphers seek to the
+    /// positions in the state, and the queued messages of the state are sent with the
+    /// next flush. Fails with [`SmcError::InvalidSession`] if the channel was used, or
+    /// if a position is past the end of the keystream of its cipher.
+    pub fn import_state(mut self, state: &SessionState) -> Result<Self, SmcError>
+    where
+        C: SeekableCipher,
+    {
+        // Check both halves before changing either.
+        self.reader.check_import(&state.read)?;
+        self.writer.check_import(&state.write)?;
+        self.reader.import_state(&state.read)?;
+        self.writer.import_state(&state.write)?;
+        Ok(self)
+    }
+
+    /// Split the channel into its reading and writing halves.
+    ///
+    /// The halves can be moved to different tasks, and can be put back together with
+    /// [`ReadHalf::reunite`] on their underlying halves.
+    pub fn split(self) -> (ChannelReader<T, C>, ChannelWriter<T, C>) {
+        (self.reader, self.writer)
+    }
+}
+
+impl<T, C> Stream for Channel<T, C>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+    C: FrameCipher + Unpin,
+{
+    type Item = Result<Message, SmcError>;
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Message, SmcError>>> {
+        Pin::new(&mut self.reader).poll_next(cx)
+    }
+}
+
+impl<T, B, C> Sink<Message<B>> for Channel<T, C>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+    B: AsRef<[u8]>,
+    C: FrameCipher + Unpin,
+{
+    type Error = SmcError;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), SmcError>> {
+        Sink::<Message<B>>::poll_ready(Pin::new(&mut self.writer), cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, message: Message<B>) -> Result<(), SmcError> {
+        Pin::new(&mut self.writer).start_send(message)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), SmcError>> {
+        Sink::<Message<B>>::poll_flush(Pin::new(&mut self.writer), cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), SmcError>> {
+        Sink::<Message<B>>::poll_close(Pin::new(&mut self.writer), cx)
+    }
+}
@@ -0,0 +1,73 @@
+use crate::codec::{encode_frame_into, MAX_HEADER_LEN};
+use crate::varint;
+use crate::{Message, SmcError};
+
+/// The length of a checksum trailer (in bytes).
+pub(crate) const CHECKSUM_LEN: usize = 4;
+
+/// A checksum that is appended to every frame.
+///
+/// For transports without integrity checks of their own, e.g. serial links. Enable
+/// the same checksum on both ends, with [`Writer::with_checksum`](crate::Writer::with_checksum)
+/// and [`Reader::with_checksum`](crate::Reader::with_checksum). The checksum of the header
+/// and the payload is appended to the frame as 4 bytes in little endian, and counts
+/// towards the length of the frame. Keepalives have no checksum.
+///
+/// Enabled with the `checksum` feature.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Checksum {
+    /// CRC-32 (IEEE).
+    Crc32,
+    /// xxHash32 with seed 0.
+    Xxh32,
+}
+
+impl Checksum {
+    /// The checksum of `buf`.
+    pub fn compute(&self, buf: &[u8]) -> u32 {
+        match self {
+            Checksum::Crc32 => crc32fast::hash(buf),
+            Checksum::Xxh32 => xxhash_rust::xxh32::xxh32(buf, 0),
+        }
+    }
+
+    // Encode `message` with a checksum trailer into `buf`.
+    pub(crate) fn encode_into<B: AsRef<[u8]>>(
+        &self,
+        message: &Message<B>,
+        buf: &mut Vec<u8>,
+    ) -> Result<(), SmcError> {
+        if message.is_keepalive() {
+            return message.encode_into(buf);
+        }
+        let payload = message.message.as_ref();
+        let mut header = [0u8; MAX_HEADER_LEN];
+        let len = message.encode_header_into(&mut header)?;
+        // The encoded header starts with the length prefix of the frame without the
+        // checksum, which is replaced.
+        let (_, prefix) = varint::decode(&header)?;
+        let mut frame = Vec::with_capacity(len - prefix + payload.len() + CHECKSUM_LEN);
+        frame.extend_from_slice(&header[prefix..len]);
+        frame.extend_from_slice(payload);
+        let checksum = self.compute(&frame);
+        frame.extend_from_slice(&checksum.to_le_bytes());
+        encode_frame_into(&frame, buf)
+    }
+
+    // Check the trailer of `frame`, returning the frame without it.
+    pub(crate) fn verify<'a>(&self, frame: &'a [u8]) -> Result<&'a [u8], SmcError> {
+        if frame.is_empty() {
+            return Ok(frame);
+        }
+        if frame.len() < CHECKSUM_LEN {
+            return Err(SmcError::ChecksumMismatch);
+        }
+        let (frame, trailer) = frame.split_at(frame.len() - CHECKSUM_LEN);
+        let mut expected = [0u8; CHECKSUM_LEN];
+        expected.copy_from_slice(trailer);
+        if self.compute(frame) != u32::from_le_bytes(expected) {
+            return Err(SmcError::ChecksumMismatch);
+        }
+        Ok(frame)
+    }
+}
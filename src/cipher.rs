@@ -0,0 +1,205 @@
+//! Encryption of the byte stream.
+
+use crate::SmcError;
+
+/// A stream cipher that is applied to all bytes of a stream of messages.
+///
+/// A [`Writer`](crate::Writer) applies it to encoded messages before they are
+/// written, and a [`Reader`](crate::Reader) to the bytes it reads, before decoding
+/// them. The cipher keeps its position in the stream, so every byte is passed to
+/// [`FrameCipher::apply`] exactly once, in order.
+///
+/// With the `salsa20` and `chacha20` features enabled, this is implemented for
+/// `salsa20::XSalsa20` and `chacha20::ChaCha20`.
+pub trait FrameCipher {
+    /// Encrypt or decrypt `buf` in place.
+    fn apply(&mut self, buf: &mut [u8]);
+
+    /// The number of bytes the cipher can still encrypt before its keystream is
+    /// exhausted, or `None` if it is unlimited.
+    ///
+    /// The readers and writers fail with [`SmcError::RekeyNeeded`] instead of passing
+    /// more bytes to the cipher. Defaults to `None`.
+    fn remaining(&self) -> Option<u64> {
+        None
+    }
+}
+
+/// A [`FrameCipher`] that is created from a key and a nonce.
+///
+/// This is implemented for the same ciphers as [`FrameCipher`]. Long-lived connections
+/// can rotate their keys before the keystream is exhausted, see
+/// [`Writer::with_rekey_threshold`](crate::Writer::with_rekey_threshold).
+pub trait KeyedCipher: FrameCipher + Sized {
+    /// Create a cipher from `key` and `nonce`.
+    ///
+    /// Fails with [`SmcError::InvalidKeyLength`] if the key or the nonce don't have the
+    /// length the cipher needs.
+    fn with_key_nonce(key: &[u8], nonce: &[u8]) -> Result<Self, SmcError>;
+
+    /// Replace the key and the nonce, and start at the beginning of the new keystream.
+    ///
+    /// Both peers have to rekey at the same position of the stream.
+    fn rekey(&mut self, key: &[u8], nonce: &[u8]) -> Result<(), SmcError> {
+        *self = Self::with_key_nonce(key, nonce)?;
+        Ok(())
+    }
+}
+
+/// Where the cipher of a reader or writer starts, see
+/// [`Reader::cipher_start_after`](crate::Reader::cipher_start_after).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CipherStart {
+    /// After the next `n` messages, which are not encrypted. Keepalives are not counted.
+    Messages(u64),
+    /// At this offset of the stream, see [`Reader::offset`](crate::Reader::offset).
+    Bytes(u64),
+}
+
+// Apply `cipher` to the bytes of `buf` from the stream offset `start` on, where `buf`
+// is at the stream offset `offset`.
+#[cfg(feature = "std")]
+pub(crate) fn try_apply_from<C: FrameCipher>(
+    cipher: &mut C,
+    start: u64,
+    offset: u64,
+    buf: &mut [u8],
+) -> Result<(), SmcError> {
+    let skip = start.saturating_sub(offset).min(buf.len() as u64) as usize;
+    try_apply(cipher, &mut buf[skip..])
+}
+
+// Apply `cipher` to `buf`, or fail if its keystream is too short.
+pub(crate) fn try_apply<C: FrameCipher>(cipher: &mut C, buf: &mut [u8]) -> Result<(), SmcError> {
+    match cipher.remaining() {
+        Some(remaining) if remaining < buf.len() as u64 => Err(SmcError::RekeyNeeded),
+        _ => {
+            cipher.apply(buf);
+            Ok(())
+        }
+    }
+}
+
+// Whether `cipher` can seek to `position`, before the end of its keystream.
+#[cfg(feature = "std")]
+pub(crate) fn can_seek<C: SeekableCipher>(cipher: &C, position: u64) -> bool {
+    match cipher.remaining() {
+        Some(remaining) => position <= cipher.position().saturating_add(remaining),
+        None => true,
+    }
+}
+
+/// A cipher that does not encrypt, used by default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoCipher;
+
+impl FrameCipher for NoCipher {
+    fn apply(&mut self, _buf: &mut [u8]) {}
+}
+
+/// A [`FrameCipher`] that can seek to any position of the stream.
+///
+/// This allows to encrypt parts of the stream independently, e.g. in parallel with
+/// [`Writer::with_parallel_cipher`](crate::Writer::with_parallel_cipher). It is
+/// implemented for the same ciphers as [`FrameCipher`].
+pub trait SeekableCipher: FrameCipher {
+    /// The position of the cipher in the stream, in bytes.
+    fn position(&self) -> u64;
+
+    /// Seek to `position`, in bytes from the start of the stream.
+    ///
+    /// Fails with [`SmcError::RekeyNeeded`], and keeps its position, if `position` is
+    /// past the end of the keystream.
+    fn seek(&mut self, position: u64) -> Result<(), SmcError>;
+}
+
+impl SeekableCipher for NoCipher {
+    fn position(&self) -> u64 {
+        0
+    }
+
+    fn seek(&mut self, _position: u64) -> Result<(), SmcError> {
+        Ok(())
+    }
+}
+
+/// An optional cipher, which does not encrypt while it is `None`.
+///
+/// Use this to start encrypting a stream after a handshake.
+impl<C: FrameCipher> FrameCipher for Option<C> {
+    fn apply(&mut self, buf: &mut [u8]) {
+        if let Some(cipher) = self {
+            cipher.apply(buf);
+        }
+    }
+
+    fn remaining(&self) -> Option<u64> {
+        self.as_ref().and_then(C::remaining)
+    }
+}
+
+#[cfg(feature = "salsa20")]
+impl FrameCipher for salsa20::XSalsa20 {
+    fn apply(&mut self, buf: &mut [u8]) {
+        salsa20::cipher::StreamCipher::apply_keystream(self, buf)
+    }
+
+    fn remaining(&self) -> Option<u64> {
+        // A 64 bit block counter, which stops before it wraps, with blocks of 64 bytes.
+        let position: u128 = salsa20::cipher::StreamCipherSeek::current_pos(self);
+        Some((u64::MAX as u128 * 64 - position).min(u64::MAX as u128) as u64)
+    }
+}
+
+#[cfg(feature = "salsa20")]
+impl KeyedCipher for salsa20::XSalsa20 {
+    fn with_key_nonce(key: &[u8], nonce: &[u8]) -> Result<Self, SmcError> {
+        salsa20::cipher::KeyIvInit::new_from_slices(key, nonce)
+            .map_err(|_| SmcError::InvalidKeyLength)
+    }
+}
+
+#[cfg(feature = "salsa20")]
+impl SeekableCipher for salsa20::XSalsa20 {
+    fn position(&self) -> u64 {
+        salsa20::cipher::StreamCipherSeek::current_pos(self)
+    }
+
+    fn seek(&mut self, position: u64) -> Result<(), SmcError> {
+        salsa20::cipher::StreamCipherSeek::try_seek(self, position)
+            .map_err(|_| SmcError::RekeyNeeded)
+    }
+}
+
+#[cfg(feature = "chacha20")]
+impl FrameCipher for chacha20::ChaCha20 {
+    fn apply(&mut self, buf: &mut [u8]) {
+        chacha20::cipher::StreamCipher::apply_keystream(self, buf)
+    }
+
+    fn remaining(&self) -> Option<u64> {
+        // A 32 bit block counter, which stops before it wraps, with blocks of 64 bytes.
+        let position: u64 = chacha20::cipher::StreamCipherSeek::current_pos(self);
+        Some(u32::MAX as u64 * 64 - position)
+    }
+}
+
+#[cfg(feature = "chacha20")]
+impl KeyedCipher for chacha20::ChaCha20 {
+    fn with_key_nonce(key: &[u8], nonce: &[u8]) -> Result<Self, SmcError> {
+        chacha20::cipher::KeyIvInit::new_from_slices(key, nonce)
+            .map_err(|_| SmcError::InvalidKeyLength)
+    }
+}
+
+#[cfg(feature = "chacha20")]
+impl SeekableCipher for chacha20::ChaCha20 {
+    fn position(&self) -> u64 {
+        chacha20::cipher::StreamCipherSeek::current_pos(self)
+    }
+
+    fn seek(&mut self, position: u64) -> Result<(), SmcError> {
+        chacha20::cipher::StreamCipherSeek::try_seek(self, position)
+            .map_err(|_| SmcError::RekeyNeeded)
+    }
+}
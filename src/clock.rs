@@ -0,0 +1,187 @@
+use futures::future::Future;
+use futures_timer::Delay;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// A source of time for timeouts, deadlines, keepalives and rate limits.
+///
+/// Readers and writers use the [`SystemClock`] by default. With the `sim` feature, they
+/// can use another clock, e.g. a [`SimClock`] that only advances when a test advances
+/// it, or a clock that sleeps with `tokio::time::sleep` under `tokio::time::pause`.
+pub trait Clock: Send + Sync {
+    /// The current time.
+    fn now(&self) -> Instant;
+
+    /// A future that completes once `duration` passed on this clock.
+    fn sleep(&self, duration: Duration) -> Sleep;
+}
+
+/// The future returned by [`Clock::sleep`].
+pub type Sleep = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// The clock of the system, with timers of `futures-timer`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) -> Sleep {
+        Box::pin(Delay::new(duration))
+    }
+}
+
+/// A clock shared by a reader or a writer and its timers.
+pub(crate) type SharedClock = Arc<dyn Clock>;
+
+pub(crate) fn system_clock() -> SharedClock {
+    Arc::new(SystemClock)
+}
+
+#[cfg(feature = "sim")]
+pub use sim::SimClock;
+
+#[cfg(feature = "sim")]
+mod sim {
+    use futures::future::Future;
+    use futures::task::{Context, Poll, Waker};
+    use std::collections::BTreeMap;
+    use std::pin::Pin;
+    use std::sync::{Arc, Mutex, MutexGuard};
+    use std::time::{Duration, Instant};
+
+    use super::{Clock, Sleep};
+
+    /// A virtual clock, which only advances with [`SimClock::advance`].
+    ///
+    /// Sleeps complete once the clock is advanced past their deadline, independent of
+    /// the executor and of the time of the system, so tests of timeouts and keepalives
+    /// are reproducible. The clock is cheap to clone, and all clones share the same time.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use futures::stream::StreamExt;
+    /// use futures::task::{noop_waker, Context};
+    /// use simple_message_channels::{PipeStream, Reader, SimClock};
+    /// use std::io::ErrorKind;
+    /// use std::time::Duration;
+    ///
+    /// let clock = SimClock::new();
+    /// let (stream, _peer) = PipeStream::pair(1024);
+    /// let mut reader = Reader::new(stream)
+    ///     .with_clock(clock.clone())
+    ///     .with_timeout(Duration::from_secs(30));
+    ///
+    /// let waker = noop_waker();
+    /// let mut cx = Context::from_waker(&waker);
+    /// assert!(reader.poll_next_unpin(&mut cx).is_pending());
+    /// clock.advance(Duration::from_secs(30));
+    /// match reader.poll_next_unpin(&mut cx) {
+    ///     std::task::Poll::Ready(Some(Err(error))) => assert_eq!(error.kind(), ErrorKind::TimedOut),
+    ///     _ => unreachable!(),
+    /// }
+    /// ```
+    #[derive(Clone)]
+    pub struct SimClock {
+        inner: Arc<Mutex<Inner>>,
+    }
+
+    struct Inner {
+        start: Instant,
+        elapsed: Duration,
+        next_id: u64,
+        // The wakers of pending sleeps, by id.
+        sleeps: BTreeMap<u64, Waker>,
+    }
+
+    impl SimClock {
+        /// Create a clock, which starts at the current time of the system.
+        pub fn new() -> Self {
+            let inner = Inner {
+                start: Instant::now(),
+                elapsed: Duration::ZERO,
+                next_id: 0,
+                sleeps: BTreeMap::new(),
+            };
+            Self {
+                inner: Arc::new(Mutex::new(inner)),
+            }
+        }
+
+        fn lock(&self) -> MutexGuard<'_, Inner> {
+            self.inner.lock().expect("clock lock poisoned")
+        }
+
+        /// The time that passed on this clock since it was created.
+        pub fn elapsed(&self) -> Duration {
+            self.lock().elapsed
+        }
+
+        /// Advance the clock by `duration`, and wake the sleeps that are due.
+        pub fn advance(&self, duration: Duration) {
+            let wakers = {
+                let mut inner = self.lock();
+                inner.elapsed += duration;
+                std::mem::take(&mut inner.sleeps)
+            };
+            // Sleeps that are not due yet register again when they are polled.
+            wakers.into_values().for_each(Waker::wake);
+        }
+    }
+
+    impl Default for SimClock {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl Clock for SimClock {
+        fn now(&self) -> Instant {
+            let inner = self.lock();
+            inner.start + inner.elapsed
+        }
+
+        fn sleep(&self, duration: Duration) -> Sleep {
+            let mut inner = self.lock();
+            let id = inner.next_id;
+            inner.next_id += 1;
+            Box::pin(SimSleep {
+                clock: self.clone(),
+                id,
+                deadline: inner.elapsed + duration,
+            })
+        }
+    }
+
+    struct SimSleep {
+        clock: SimClock,
+        id: u64,
+        deadline: Duration,
+    }
+
+    impl Future for SimSleep {
+        type Output = ();
+
+        fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+            let mut inner = self.clock.lock();
+            if inner.elapsed >= self.deadline {
+                inner.sleeps.remove(&self.id);
+                return Poll::Ready(());
+            }
+            inner.sleeps.insert(self.id, cx.waker().clone());
+            Poll::Pending
+        }
+    }
+
+    impl Drop for SimSleep {
+        fn drop(&mut self) {
+            if let Ok(mut inner) = self.clock.inner.lock() {
+                inner.sleeps.remove(&self.id);
+            }
+        }
+    }
+}
@@ -0,0 +1,395 @@
+//! Encoding and decoding of SMC messages.
+//!
+//! This is the core of the protocol, without any IO. It only needs `alloc`, and is
+//! available without the `std` feature, e.g. for embedded or WASM targets.
+
+use alloc::vec::Vec;
+
+use crate::cipher::{try_apply, FrameCipher, NoCipher};
+use crate::varint;
+use crate::{Message, Payload, SmcError, Typ, MAX_CHANNEL, MAX_FRAME_LEN, MAX_MESSAGE_SIZE};
+
+/// Decode a message from `buf` (bytes).
+///
+/// Note: `buf` has to have a valid length, and the length prefixed
+/// has to be removed already.
+pub fn decode_message(buf: &[u8]) -> Result<Message, SmcError> {
+    if buf.is_empty() {
+        return Ok(Message::keepalive());
+    }
+    let (channel, typ, headerlen) = decode_header(buf)?;
+    Ok(Message::new(channel, typ, buf[headerlen..].to_vec()))
+}
+
+/// Decode a length-prefixed message from the start of `buf`.
+///
+/// Returns the message and the number of bytes it was encoded in, or `None` if `buf`
+/// does not contain a complete message yet. This never panics, whatever `buf`
+/// contains, and is the entry point for fuzzing the decoder.
+///
+/// Fails with [`SmcError::MessageTooLong`] if the body after the length prefix is longer
+/// than [`MAX_MESSAGE_SIZE`], see [`encode_header_into`] for the limit of the encoder.
+pub fn decode_frame(buf: &[u8]) -> Result<Option<(Message, usize)>, SmcError> {
+    let (len, prefixlen) = match varint::decode(buf) {
+        Ok(varint) => varint,
+        Err(varint::VarintError::Incomplete) => return Ok(None),
+        Err(error) => return Err(error.into()),
+    };
+    if len > MAX_FRAME_LEN {
+        return Err(SmcError::MessageTooLong(len));
+    }
+    let end = prefixlen + len as usize;
+    if buf.len() < end {
+        return Ok(None);
+    }
+    let message = decode_message(&buf[prefixlen..end])?;
+    Ok(Some((message, end)))
+}
+
+/// Decode a message from `buf` without copying the payload.
+///
+/// The payload of the returned message borrows from `buf`.
+pub fn decode_borrowed(buf: &[u8]) -> Result<Message<&[u8]>, SmcError> {
+    if buf.is_empty() {
+        return Ok(Message::keepalive());
+    }
+    let (channel, typ, headerlen) = decode_header(buf)?;
+    Ok(Message::new(channel, typ, &buf[headerlen..]))
+}
+
+/// Decode a message from an owned frame buffer.
+///
+/// This reuses the allocation of `buf` for the payload.
+pub fn decode_frame_buf<B: Payload>(buf: Vec<u8>) -> Result<Message<B>, SmcError> {
+    if buf.is_empty() {
+        return Ok(Message::keepalive());
+    }
+    let (channel, typ, headerlen) = decode_header(&buf)?;
+    Ok(Message::new(channel, typ, B::from_frame(buf, headerlen)))
+}
+
+/// Decode a message from `buf` without copying the payload.
+///
+/// Note: `buf` has to have a valid length, and the length prefixed
+/// has to be removed already.
+#[cfg(feature = "bytes")]
+pub fn decode_bytes(buf: bytes::Bytes) -> Result<Message<bytes::Bytes>, SmcError> {
+    if buf.is_empty() {
+        return Ok(Message::keepalive());
+    }
+    let (channel, typ, headerlen) = decode_header(&buf)?;
+    Ok(Message::new(channel, typ, buf.slice(headerlen..)))
+}
+
+/// Decode the header varint, returning channel, typ and the header length.
+pub(crate) fn decode_header(buf: &[u8]) -> Result<(u64, u8, usize), SmcError> {
+    let (header, headerlen) = varint::decode(buf)?;
+    let channel = header >> 4;
+    let typ = header & 0b1111;
+    Ok((channel, typ as u8, headerlen))
+}
+
+/// Encode a message body into a buffer.
+pub fn encode_message<B: AsRef<[u8]>>(msg: &Message<B>) -> Result<Vec<u8>, SmcError> {
+    let mut buf = Vec::new();
+    encode_message_into(msg, &mut buf)?;
+    Ok(buf)
+}
+
+/// The max length of the header of an encoded message.
+///
+/// The header is the length prefix, followed by the channel and typ.
+pub const MAX_HEADER_LEN: usize = 2 * varint::MAX_VARINT_LEN;
+
+/// Encode a message body and append it to `buf`.
+pub fn encode_message_into<B: AsRef<[u8]>>(
+    msg: &Message<B>,
+    buf: &mut Vec<u8>,
+) -> Result<(), SmcError> {
+    let mut header = [0u8; MAX_HEADER_LEN];
+    let len_header = encode_header_into(msg, &mut header)?;
+    buf.reserve(len_header + msg.message.as_ref().len());
+    buf.extend_from_slice(&header[..len_header]);
+    buf.extend_from_slice(msg.message.as_ref());
+    Ok(())
+}
+
+/// Encode a raw frame and append it to `buf`.
+///
+/// A raw frame is `payload` with a length prefix, without the channel and typ of a
+/// message.
+pub fn encode_frame_into(payload: &[u8], buf: &mut Vec<u8>) -> Result<(), SmcError> {
+    let len_prefix = varint::length(payload.len() as u64);
+    let len = len_prefix + payload.len();
+    if len as u64 > MAX_MESSAGE_SIZE {
+        return Err(SmcError::MessageTooLong(len as u64));
+    }
+    let start = buf.len();
+    buf.resize(start + len_prefix, 0);
+    varint::encode(payload.len() as u64, &mut buf[start..]);
+    buf.extend_from_slice(payload);
+    Ok(())
+}
+
+/// Encode the header of a message into `buf`, returning the length of the header.
+///
+/// The encoded message is the header followed by the payload. This allows writing
+/// the payload without copying it into the same buffer as the header, e.g. with
+/// vectored writes. `buf` has to be at least [`MAX_HEADER_LEN`] bytes long.
+///
+/// Fails with [`SmcError::ChannelTooLarge`] if the channel is larger than
+/// [`MAX_CHANNEL`](crate::MAX_CHANNEL), instead of dropping its top bits, and with
+/// [`SmcError::InvalidTyp`] if the typ is larger than [`Typ::MAX`](crate::Typ::MAX).
+///
+/// Fails with [`SmcError::MessageTooLong`] if the frame, i.e. the length prefix, the
+/// header and the payload, is longer than [`MAX_MESSAGE_SIZE`]. This is stricter
+/// than [`decode_frame`], which only checks the body after the length prefix against
+/// the limit, so a frame that is decoded is not always encoded.
+pub fn encode_header_into<B: AsRef<[u8]>>(
+    msg: &Message<B>,
+    buf: &mut [u8],
+) -> Result<usize, SmcError> {
+    if msg.is_keepalive() {
+        buf[0] = 0;
+        return Ok(1);
+    }
+    if msg.channel > MAX_CHANNEL {
+        return Err(SmcError::ChannelTooLarge(msg.channel));
+    }
+    if Typ::new(msg.typ).is_none() {
+        return Err(SmcError::InvalidTyp(msg.typ));
+    }
+
+    let header = msg.channel << 4 | msg.typ as u64;
+    let len_header = varint::length(header);
+    let len_body = msg.message.as_ref().len() + len_header;
+    let len_prefix = varint::length(len_body as u64);
+    let len = len_body + len_prefix;
+
+    if len as u64 > MAX_MESSAGE_SIZE {
+        return Err(SmcError::MessageTooLong(len as u64));
+    }
+
+    varint::encode(len_body as u64, &mut buf[..len_prefix]);
+    let end = len_prefix + len_header;
+    varint::encode(header, &mut buf[len_prefix..end]);
+    Ok(end)
+}
+
+/// An incremental decoder for SMC messages, independent of any IO.
+///
+/// Bytes are pushed into the decoder in chunks of any size, as they are received,
+/// and complete messages are taken out of it. This allows to use SMC over any
+/// transport, e.g. with mio, io_uring or QUIC streams.
+///
+/// After an error, the rest of the stream can't be decoded.
+#[derive(Debug, Default)]
+pub struct Decoder {
+    buf: Vec<u8>,
+    pos: usize,
+    keepalives: bool,
+}
+
+impl Decoder {
+    /// Create a new decoder.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set whether keepalive messages are returned.
+    ///
+    /// By default, they are skipped. See [`Message::keepalive`].
+    pub fn with_keepalives(mut self, keepalives: bool) -> Self {
+        self.keepalives = keepalives;
+        self
+    }
+
+    /// The number of bytes that are buffered but not yet decoded.
+    pub fn buffered(&self) -> usize {
+        self.buf.len() - self.pos
+    }
+
+    /// Add bytes to the decoder.
+    pub fn extend(&mut self, data: &[u8]) {
+        // Drop decoded bytes before growing the buffer.
+        if self.pos > 0 && self.pos == self.buf.len() {
+            self.buf.clear();
+            self.pos = 0;
+        } else if self.pos > self.buf.len() / 2 {
+            self.buf.drain(..self.pos);
+            self.pos = 0;
+        }
+        self.buf.extend_from_slice(data);
+    }
+
+    /// Take the next complete message out of the decoder.
+    ///
+    /// Returns `None` if more bytes are needed.
+    pub fn poll_message(&mut self) -> Result<Option<Message>, SmcError> {
+        while let Some((message, len)) = decode_frame(&self.buf[self.pos..])? {
+            self.pos += len;
+            if self.keepalives || !message.is_keepalive() {
+                return Ok(Some(message));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Add bytes to the decoder, and return all messages that are complete.
+    pub fn push(&mut self, data: &[u8]) -> Result<Vec<Message>, SmcError> {
+        self.extend(data);
+        let mut messages = Vec::new();
+        while let Some(message) = self.poll_message()? {
+            messages.push(message);
+        }
+        Ok(messages)
+    }
+}
+
+/// An encoder for SMC messages, independent of any IO.
+///
+/// This is the counterpart of [`Decoder`]. It encodes messages into a buffer that is
+/// provided by the caller, and keeps the state of the [`FrameCipher`] the stream is
+/// encrypted with.
+#[derive(Debug, Default)]
+pub struct Encoder<C = NoCipher> {
+    cipher: C,
+}
+
+impl Encoder {
+    /// Create a new encoder.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<C: FrameCipher> Encoder<C> {
+    /// Encrypt the stream of messages with `cipher`.
+    pub fn with_cipher<D: FrameCipher>(self, cipher: D) -> Encoder<D> {
+        Encoder { cipher }
+    }
+
+    /// Replace the cipher, e.g. with new keys after a handshake.
+    pub fn set_cipher(&mut self, cipher: C) {
+        self.cipher = cipher;
+    }
+
+    /// Get a mutable reference to the cipher.
+    pub fn cipher_mut(&mut self) -> &mut C {
+        &mut self.cipher
+    }
+
+    /// Encode a message and append it to `buf`.
+    ///
+    /// Nothing is appended on error.
+    pub fn encode_into<B: AsRef<[u8]>>(
+        &mut self,
+        msg: &Message<B>,
+        buf: &mut Vec<u8>,
+    ) -> Result<(), SmcError> {
+        let start = buf.len();
+        encode_message_into(msg, buf)?;
+        if let Err(error) = try_apply(&mut self.cipher, &mut buf[start..]) {
+            buf.truncate(start);
+            return Err(error);
+        }
+        Ok(())
+    }
+
+    /// Encode a message and append it to `buf`.
+    ///
+    /// Nothing is appended on error.
+    #[cfg(feature = "bytes")]
+    pub fn encode_into_bytes<B: AsRef<[u8]>>(
+        &mut self,
+        msg: &Message<B>,
+        buf: &mut bytes::BytesMut,
+    ) -> Result<(), SmcError> {
+        let mut header = [0u8; MAX_HEADER_LEN];
+        let len_header = encode_header_into(msg, &mut header)?;
+        let start = buf.len();
+        buf.reserve(len_header + msg.message.as_ref().len());
+        buf.extend_from_slice(&header[..len_header]);
+        buf.extend_from_slice(msg.message.as_ref());
+        if let Err(error) = try_apply(&mut self.cipher, &mut buf[start..]) {
+            buf.truncate(start);
+            return Err(error);
+        }
+        Ok(())
+    }
+}
+
+/// A codec for transports that carry one SMC message per datagram.
+///
+/// Datagram transports like UDP or WebRTC data channels keep the boundaries of
+/// messages, so the length prefix of a frame is redundant. By default, messages are
+/// encoded without it, as the header and the payload. With
+/// [`DatagramCodec::with_length_prefix`], datagrams are complete frames, as sent over
+/// streams.
+///
+/// Keepalives are empty datagrams, or a single zero byte with the length prefix. The
+/// datagrams are not encrypted, as stream ciphers need the bytes in order.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DatagramCodec {
+    length_prefix: bool,
+}
+
+impl DatagramCodec {
+    /// Create a new codec for datagrams without a length prefix.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set whether datagrams start with the length prefix.
+    ///
+    /// When decoding, the length prefix has to match the length of the datagram.
+    pub fn with_length_prefix(mut self, length_prefix: bool) -> Self {
+        self.length_prefix = length_prefix;
+        self
+    }
+
+    /// Encode a message into a datagram.
+    pub fn encode<B: AsRef<[u8]>>(&self, msg: &Message<B>) -> Result<Vec<u8>, SmcError> {
+        let mut buf = Vec::new();
+        self.encode_into(msg, &mut buf)?;
+        Ok(buf)
+    }
+
+    /// Encode a message into a datagram, and append it to `buf`.
+    ///
+    /// Nothing is appended on error.
+    pub fn encode_into<B: AsRef<[u8]>>(
+        &self,
+        msg: &Message<B>,
+        buf: &mut Vec<u8>,
+    ) -> Result<(), SmcError> {
+        let mut header = [0u8; MAX_HEADER_LEN];
+        let len_header = encode_header_into(msg, &mut header)?;
+        let start = if self.length_prefix {
+            0
+        } else {
+            varint::decode(&header[..len_header])?.1
+        };
+        buf.extend_from_slice(&header[start..len_header]);
+        buf.extend_from_slice(msg.message.as_ref());
+        Ok(())
+    }
+
+    /// Decode a message from a datagram.
+    ///
+    /// The payload of the returned message borrows from `datagram`.
+    pub fn decode<'a>(&self, datagram: &'a [u8]) -> Result<Message<&'a [u8]>, SmcError> {
+        if !self.length_prefix {
+            return decode_borrowed(datagram);
+        }
+        let (len, prefixlen) = varint::decode(datagram)?;
+        if len > MAX_FRAME_LEN {
+            return Err(SmcError::MessageTooLong(len));
+        }
+        let got = (datagram.len() - prefixlen) as u64;
+        if got != len {
+            return Err(SmcError::TruncatedFrame { expected: len, got });
+        }
+        decode_borrowed(&datagram[prefixlen..])
+    }
+}
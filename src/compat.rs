@@ -0,0 +1,99 @@
+//! Adapters for [tokio](https://tokio.rs) IO types.
+//!
+//! Enabled with the `tokio` feature.
+
+use futures::io::{AsyncRead, AsyncWrite};
+use futures::task::{Context, Poll};
+use std::io::Error;
+use std::pin::Pin;
+use tokio::io::ReadBuf;
+
+use crate::{Reader, Writer};
+
+/// A [`Reader`] over a [`tokio::io::AsyncRead`].
+pub type TokioReader<R> = Reader<Compat<R>>;
+
+/// A [`Writer`] over a [`tokio::io::AsyncWrite`].
+pub type TokioWriter<W> = Writer<Compat<W>>;
+
+/// Wraps a tokio IO type so that it implements the [`futures::io`] traits.
+#[derive(Debug)]
+pub struct Compat<T> {
+    inner: T,
+}
+
+impl<T> Compat<T> {
+    /// Wrap a tokio IO type.
+    pub fn new(inner: T) -> Self {
+        Self { inner }
+    }
+
+    /// Get a reference to the wrapped IO type.
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    /// Unwrap the wrapped IO type.
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<R> Reader<Compat<R>>
+where
+    R: tokio::io::AsyncRead + Unpin,
+{
+    /// Create a new message reader from any [`tokio::io::AsyncRead`].
+    pub fn from_tokio(reader: R) -> Self {
+        Reader::new(Compat::new(reader))
+    }
+}
+
+impl<W> Writer<Compat<W>>
+where
+    W: tokio::io::AsyncWrite + Unpin,
+{
+    /// Create a new message writer from any [`tokio::io::AsyncWrite`].
+    pub fn from_tokio(writer: W) -> Self {
+        Writer::new(Compat::new(writer))
+    }
+}
+
+impl<T> AsyncRead for Compat<T>
+where
+    T: tokio::io::AsyncRead + Unpin,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, Error>> {
+        let mut buf = ReadBuf::new(buf);
+        match Pin::new(&mut self.inner).poll_read(cx, &mut buf) {
+            Poll::Ready(Ok(())) => Poll::Ready(Ok(buf.filled().len())),
+            Poll::Ready(Err(error)) => Poll::Ready(Err(error)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl<T> AsyncWrite for Compat<T>
+where
+    T: tokio::io::AsyncWrite + Unpin,
+{
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, Error>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
@@ -0,0 +1,142 @@
+//! Payload compression.
+//!
+//! Enabled with the `lz4` and `zstd` features. Once compression is enabled on both
+//! ends of a connection, with [`Writer::enable_compression`](crate::Writer::enable_compression)
+//! and [`Reader::enable_compression`](crate::Reader::enable_compression), the payload of
+//! every message is prefixed with a byte of the [`Codec`] it is compressed with, or `0`
+//! if it is not compressed. Payloads below a threshold are not compressed.
+//!
+//! To agree on a codec, each peer sends its [`offer`] (e.g. in the handshake of the
+//! protocol on top of SMC), and both peers pick the same codec with [`negotiate`].
+
+#[cfg(feature = "zstd")]
+use std::io::Read;
+use std::io::{self, Error, ErrorKind};
+
+use crate::{SmcError, MAX_MESSAGE_SIZE};
+
+/// The default size (in bytes) below which payloads are not compressed.
+pub const DEFAULT_THRESHOLD: usize = 256;
+
+const NONE: u8 = 0;
+#[cfg(feature = "lz4")]
+const LZ4: u8 = 1;
+#[cfg(feature = "zstd")]
+const ZSTD: u8 = 2;
+
+/// The default compression level of zstd.
+#[cfg(feature = "zstd")]
+const ZSTD_LEVEL: i32 = 3;
+
+/// A compression codec.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codec {
+    /// LZ4 block compression, enabled with the `lz4` feature.
+    #[cfg(feature = "lz4")]
+    Lz4,
+    /// Zstandard compression with a compression `level`, enabled with the `zstd` feature.
+    #[cfg(feature = "zstd")]
+    Zstd { level: i32 },
+}
+
+impl Codec {
+    /// The id of the codec, as used in the payload prefix and in offers.
+    pub fn id(&self) -> u8 {
+        match self {
+            #[cfg(feature = "lz4")]
+            Codec::Lz4 => LZ4,
+            #[cfg(feature = "zstd")]
+            Codec::Zstd { .. } => ZSTD,
+        }
+    }
+}
+
+/// The ids of the supported codecs, in the order of preference.
+pub fn offer() -> Vec<u8> {
+    vec![
+        #[cfg(feature = "zstd")]
+        ZSTD,
+        #[cfg(feature = "lz4")]
+        LZ4,
+    ]
+}
+
+/// Pick the codec to use from the [`offer`] of the peer.
+///
+/// Returns the most preferred codec that is supported by both peers, which is the same
+/// on both sides, or `None` if there is none.
+pub fn negotiate(remote: &[u8]) -> Option<Codec> {
+    offer()
+        .into_iter()
+        .find(|id| remote.contains(id))
+        .and_then(|id| match id {
+            #[cfg(feature = "lz4")]
+            LZ4 => Some(Codec::Lz4),
+            #[cfg(feature = "zstd")]
+            ZSTD => Some(Codec::Zstd { level: ZSTD_LEVEL }),
+            _ => None,
+        })
+}
+
+fn payload_error(error: impl std::error::Error + Send + Sync + 'static) -> SmcError {
+    SmcError::Payload(Box::new(error))
+}
+
+// Prefix and compress `payload` with `codec` if it is at least `threshold` bytes long.
+pub(crate) fn compress(codec: Codec, threshold: usize, payload: &[u8]) -> io::Result<Vec<u8>> {
+    if payload.len() < threshold {
+        let mut buf = Vec::with_capacity(payload.len() + 1);
+        buf.push(NONE);
+        buf.extend_from_slice(payload);
+        return Ok(buf);
+    }
+    let mut buf = vec![codec.id()];
+    match codec {
+        #[cfg(feature = "lz4")]
+        Codec::Lz4 => buf.extend_from_slice(&lz4_flex::compress_prepend_size(payload)),
+        #[cfg(feature = "zstd")]
+        Codec::Zstd { level } => zstd::stream::copy_encode(payload, &mut buf, level)?,
+    }
+    Ok(buf)
+}
+
+// Decompress a prefixed `payload`, into `buf` if it is compressed.
+pub(crate) fn decompress<'a>(
+    payload: &'a [u8],
+    buf: &'a mut Vec<u8>,
+) -> Result<&'a [u8], SmcError> {
+    let (codec, data) = match payload.split_first() {
+        Some((codec, data)) => (*codec, data),
+        None => return Ok(payload),
+    };
+    buf.clear();
+    match codec {
+        NONE => return Ok(data),
+        #[cfg(feature = "lz4")]
+        LZ4 => {
+            let (len, data) = lz4_flex::block::uncompressed_size(data).map_err(payload_error)?;
+            if len as u64 > MAX_MESSAGE_SIZE {
+                return Err(SmcError::MessageTooLong(len as u64));
+            }
+            buf.resize(len, 0);
+            let n = lz4_flex::block::decompress_into(data, buf).map_err(payload_error)?;
+            buf.truncate(n);
+        }
+        #[cfg(feature = "zstd")]
+        ZSTD => {
+            let decoder = zstd::stream::read::Decoder::new(data).map_err(payload_error)?;
+            decoder
+                .take(MAX_MESSAGE_SIZE + 1)
+                .read_to_end(buf)
+                .map_err(payload_error)?;
+            if buf.len() as u64 > MAX_MESSAGE_SIZE {
+                return Err(SmcError::MessageTooLong(buf.len() as u64));
+            }
+        }
+        _ => {
+            let error = Error::new(ErrorKind::InvalidData, "Unknown compression codec");
+            return Err(payload_error(error));
+        }
+    }
+    Ok(buf)
+}
@@ -0,0 +1,239 @@
+//! Utilities to inspect messages while debugging a protocol.
+//!
+//! [`Message`] implements [`Display`](core::fmt::Display), which shows the channel, the
+//! typ and the start of the payload, and [`Message::hex_dump`] dumps the whole payload.
+//! With the `hypercore` feature enabled, the typ is shown with its [`MessageType`]
+//! name.
+//!
+//! With the `std` feature enabled, a [`FrameTap`] calls a function with every message
+//! that passes through a stream, e.g. a [`Reader`](crate::Reader), or a sink, e.g. a
+//! [`Writer`](crate::Writer).
+//!
+//! [`MessageType`]: crate::MessageType
+
+use alloc::string::String;
+use core::fmt::{self, Write};
+
+use crate::Message;
+
+/// The number of payload bytes shown by the `Display` impl of [`Message`].
+const DISPLAY_LEN: usize = 16;
+
+/// Dump `bytes` as hex, 16 bytes per line, with offsets and an ASCII column.
+///
+/// This can be used with encoded frames, to see the header as well.
+pub fn hex_dump(bytes: &[u8]) -> String {
+    let mut dump = String::new();
+    for (i, line) in bytes.chunks(16).enumerate() {
+        let _ = write!(dump, "{:08x} ", i * 16);
+        for j in 0..16 {
+            if j == 8 {
+                dump.push(' ');
+            }
+            match line.get(j) {
+                Some(byte) => {
+                    let _ = write!(dump, " {:02x}", byte);
+                }
+                None => dump.push_str("   "),
+            }
+        }
+        dump.push_str("  |");
+        for byte in line {
+            let c = *byte as char;
+            dump.push(if c.is_ascii_graphic() || c == ' ' {
+                c
+            } else {
+                '.'
+            });
+        }
+        dump.push_str("|\n");
+    }
+    dump
+}
+
+impl<B> Message<B>
+where
+    B: AsRef<[u8]>,
+{
+    /// Dump the payload as hex, see [`hex_dump`].
+    pub fn hex_dump(&self) -> String {
+        hex_dump(self.message.as_ref())
+    }
+}
+
+impl<B> fmt::Display for Message<B>
+where
+    B: AsRef<[u8]>,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_keepalive() {
+            return write!(f, "keepalive");
+        }
+        write!(f, "ch {} typ {}", self.channel, self.typ)?;
+        #[cfg(feature = "hypercore")]
+        if let Ok(typ) = self.message_type() {
+            write!(f, " ({:?})", typ)?;
+        }
+        let payload = self.message.as_ref();
+        write!(f, " len {}", payload.len())?;
+        if !payload.is_empty() {
+            f.write_str(": ")?;
+            for byte in payload.iter().take(DISPLAY_LEN) {
+                write!(f, "{:02x}", byte)?;
+            }
+            if payload.len() > DISPLAY_LEN {
+                f.write_str("...")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+pub(crate) use tap::FrameObserver;
+#[cfg(feature = "std")]
+pub use tap::{Direction, FrameTap, Tap};
+
+#[cfg(feature = "std")]
+mod tap {
+    use futures::sink::Sink;
+    use futures::stream::Stream;
+    use futures::task::{Context, Poll};
+    use std::fmt;
+    use std::pin::Pin;
+
+    use crate::{Message, SmcError};
+
+    /// The direction of a message passing through a [`FrameTap`].
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Direction {
+        /// The message was read from the wrapped stream.
+        Read,
+        /// The message is written to the wrapped sink.
+        Write,
+    }
+
+    impl fmt::Display for Direction {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                Direction::Read => f.write_str("<"),
+                Direction::Write => f.write_str(">"),
+            }
+        }
+    }
+
+    /// A function that is called with every message passing through a [`FrameTap`].
+    pub type Tap = fn(Direction, &Message<&[u8]>);
+
+    /// A callback that is called with every message of a reader or a writer, see
+    /// `Reader::set_frame_observer`.
+    pub(crate) type FrameObserver = Box<dyn FnMut(Direction, &Message<&[u8]>) + Send>;
+
+    /// Calls a function with every message that passes through a stream or a sink.
+    ///
+    /// This wraps a stream of messages, e.g. a [`Reader`](crate::Reader), or a sink of
+    /// messages, e.g. a [`Writer`](crate::Writer) or a [`Channel`](crate::Channel), and
+    /// passes each message that is read or written to the tap. Errors are passed
+    /// through untouched.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use async_std::{io, prelude::*};
+    /// use simple_message_channels::debug::FrameTap;
+    /// use simple_message_channels::Reader;
+    /// # async_std::task::block_on(async {
+    /// let mut reader = FrameTap::stderr(Reader::new(io::stdin()));
+    /// while let Some(message) = reader.next().await {
+    ///     message?;
+    /// }
+    /// # io::Result::Ok(())
+    /// # });
+    /// ```
+    pub struct FrameTap<T, F = Tap> {
+        inner: T,
+        tap: F,
+    }
+
+    impl<T> FrameTap<T> {
+        /// Print every message to stderr, prefixed with `<` if it was read and `>` if it
+        /// is written.
+        pub fn stderr(inner: T) -> Self {
+            Self::new(inner, |direction, message| {
+                eprintln!("{} {}", direction, message)
+            })
+        }
+    }
+
+    impl<T, F> FrameTap<T, F>
+    where
+        F: FnMut(Direction, &Message<&[u8]>),
+    {
+        /// Call `tap` with every message that passes through `inner`.
+        pub fn new(inner: T, tap: F) -> Self {
+            Self { inner, tap }
+        }
+
+        /// Get a reference to the wrapped stream or sink.
+        pub fn get_ref(&self) -> &T {
+            &self.inner
+        }
+
+        /// Get a mutable reference to the wrapped stream or sink.
+        pub fn get_mut(&mut self) -> &mut T {
+            &mut self.inner
+        }
+
+        /// Unwrap the wrapped stream or sink.
+        pub fn into_inner(self) -> T {
+            self.inner
+        }
+    }
+
+    impl<T, F, B> Stream for FrameTap<T, F>
+    where
+        T: Stream<Item = Result<Message<B>, SmcError>> + Unpin,
+        F: FnMut(Direction, &Message<&[u8]>) + Unpin,
+        B: AsRef<[u8]>,
+    {
+        type Item = Result<Message<B>, SmcError>;
+        fn poll_next(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+        ) -> Poll<Option<Result<Message<B>, SmcError>>> {
+            let this = self.get_mut();
+            let item = futures::ready!(Pin::new(&mut this.inner).poll_next(cx));
+            if let Some(Ok(message)) = &item {
+                (this.tap)(Direction::Read, &message.as_borrowed());
+            }
+            Poll::Ready(item)
+        }
+    }
+
+    impl<T, F, B> Sink<Message<B>> for FrameTap<T, F>
+    where
+        T: Sink<Message<B>> + Unpin,
+        F: FnMut(Direction, &Message<&[u8]>) + Unpin,
+        B: AsRef<[u8]>,
+    {
+        type Error = T::Error;
+
+        fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), T::Error>> {
+            Pin::new(&mut self.get_mut().inner).poll_ready(cx)
+        }
+
+        fn start_send(self: Pin<&mut Self>, message: Message<B>) -> Result<(), T::Error> {
+            let this = self.get_mut();
+            (this.tap)(Direction::Write, &message.as_borrowed());
+            Pin::new(&mut this.inner).start_send(message)
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), T::Error>> {
+            Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+        }
+
+        fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), T::Error>> {
+            Pin::new(&mut self.get_mut().inner).poll_close(cx)
+        }
+    }
+}
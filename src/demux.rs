@@ -0,0 +1,482 @@
+use futures::future::FutureExt;
+use futures::ready;
+use futures::stream::Stream;
+use futures::task::{Context, Poll, Waker};
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use crate::clock::{system_clock, SharedClock, Sleep};
+use crate::lifecycle::{ConnectionEvent, ConnectionEvents, EventSender};
+use crate::{Message, SmcError};
+
+/// The number of messages a [`ChannelReceiver`] queues, by default.
+const RECEIVER_CAPACITY: usize = 16;
+
+/// What a [`Demux`] does with a message for a channel whose queue is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Overflow {
+    /// Stop reading until the receiver takes a message. This also delays the messages
+    /// of the other channels, and applies backpressure to the peer. This is the default.
+    #[default]
+    Block,
+    /// Drop the oldest queued message to make room.
+    DropOldest,
+    /// Drop the new message.
+    DropNewest,
+    /// Drop the new message, and yield [`SmcError::QueueFull`] from the demux.
+    Error,
+}
+
+/// A demultiplexer that routes the messages of a stream to a receiver per channel.
+///
+/// Receivers are created with a [`DemuxHandle`]. Each receiver queues up to a number
+/// of messages, and the [`Overflow`] policy decides what happens to the messages that
+/// don't fit, so one slow receiver can't use unbounded memory.
+///
+/// The demux is itself a stream, of the messages on channels without a receiver, e.g.
+/// to open a receiver for a new channel. It has to be polled to route the messages.
+/// Receivers end once the demux ends or is dropped.
+///
+/// # Example
+///
+/// ```no_run
+/// # use async_std::{io, task};
+/// use futures::stream::StreamExt;
+/// use simple_message_channels::{Demux, Reader};
+/// # task::block_on(async {
+/// let (mut demux, handle) = Demux::new(Reader::new(io::stdin()));
+/// let mut receiver = handle.receiver(1);
+/// task::spawn(async move {
+///     while let Some(message) = receiver.next().await {
+///         println!("{}", message);
+///     }
+/// });
+/// while let Some(message) = demux.next().await {
+///     eprintln!("no receiver for {}", message?);
+/// }
+/// # io::Result::Ok(())
+/// # });
+/// ```
+pub struct Demux<S> {
+    stream: S,
+    shared: Arc<Mutex<Shared>>,
+    // A message that waits for room in the queue of its channel.
+    blocked: Option<Message>,
+    events: Option<EventSender>,
+}
+
+/// The idle timeout of a [`Demux`].
+struct Idle {
+    timeout: Duration,
+    // The time of the last message of each channel with a receiver, or of the creation
+    // of the receiver, and the channels by that time.
+    last: HashMap<u64, Instant>,
+    order: BTreeSet<(Instant, u64)>,
+    // Fires at the deadline of the channel that was idle the longest.
+    timer: Option<Sleep>,
+}
+
+impl Idle {
+    fn touch(&mut self, channel: u64, now: Instant) {
+        if let Some(last) = self.last.insert(channel, now) {
+            self.order.remove(&(last, channel));
+        }
+        self.order.insert((now, channel));
+    }
+
+    fn remove(&mut self, channel: u64) {
+        if let Some(last) = self.last.remove(&channel) {
+            self.order.remove(&(last, channel));
+        }
+    }
+
+    // Wait for the next channel that is idle.
+    fn poll_idle(&mut self, clock: &SharedClock, cx: &mut Context<'_>) -> Poll<u64> {
+        loop {
+            let (last, channel) = match self.order.first() {
+                Some(first) => *first,
+                None => {
+                    self.timer = None;
+                    return Poll::Pending;
+                }
+            };
+            let now = clock.now();
+            let deadline = match last.checked_add(self.timeout) {
+                Some(deadline) => deadline,
+                None => return Poll::Pending,
+            };
+            if now >= deadline {
+                self.remove(channel);
+                self.timer = None;
+                return Poll::Ready(channel);
+            }
+            let timer = self
+                .timer
+                .get_or_insert_with(|| clock.sleep(deadline - now));
+            ready!(timer.poll_unpin(cx));
+            self.timer = None;
+        }
+    }
+}
+
+struct Shared {
+    queues: HashMap<u64, Queue>,
+    capacity: usize,
+    overflow: Overflow,
+    next_id: u64,
+    finished: bool,
+    clock: SharedClock,
+    idle: Option<Idle>,
+    // The demux, waiting for room in a queue, or for new receivers to track.
+    waker: Option<Waker>,
+}
+
+struct Queue {
+    // Identifies the receiver, which a new receiver on the same channel replaces.
+    id: u64,
+    messages: VecDeque<Message>,
+    capacity: usize,
+    overflow: Overflow,
+    waker: Option<Waker>,
+}
+
+fn wake(waker: &mut Option<Waker>) {
+    if let Some(waker) = waker.take() {
+        waker.wake();
+    }
+}
+
+fn lock(shared: &Mutex<Shared>) -> MutexGuard<'_, Shared> {
+    shared.lock().expect("demux lock poisoned")
+}
+
+// Where a message went.
+enum Route {
+    Queued,
+    Blocked(Message),
+    Unrouted(Message),
+    Full(u64),
+}
+
+impl<S> Demux<S> {
+    /// Create a new demux that routes the messages of `stream`.
+    ///
+    /// Returns the demux and a handle to create receivers with.
+    pub fn new(stream: S) -> (Self, DemuxHandle) {
+        let shared = Arc::new(Mutex::new(Shared {
+            queues: HashMap::new(),
+            capacity: RECEIVER_CAPACITY,
+            overflow: Overflow::Block,
+            next_id: 0,
+            finished: false,
+            clock: system_clock(),
+            idle: None,
+            waker: None,
+        }));
+        let handle = DemuxHandle {
+            shared: shared.clone(),
+        };
+        let demux = Self {
+            stream,
+            shared,
+            blocked: None,
+            events: None,
+        };
+        (demux, handle)
+    }
+
+    /// Set the number of messages a receiver queues, for receivers created from now on.
+    ///
+    /// Defaults to 16.
+    pub fn with_capacity(self, capacity: usize) -> Self {
+        lock(&self.shared).capacity = capacity.max(1);
+        self
+    }
+
+    /// Set what happens to messages for a full queue, for receivers created from now on.
+    ///
+    /// Defaults to [`Overflow::Block`].
+    pub fn with_overflow(self, overflow: Overflow) -> Self {
+        lock(&self.shared).overflow = overflow;
+        self
+    }
+
+    /// End the receivers of channels without messages for `timeout`.
+    ///
+    /// A channel is tracked from the creation of its receiver, and each message to the
+    /// receiver starts the timeout again. Once it is idle, its queued messages are
+    /// dropped, its receiver ends, and the demux emits [`ConnectionEvent::ChannelIdle`]
+    /// on its [events](Demux::events), so the application can free its state of the
+    /// channel. Later messages on the channel are yielded by the demux, like those of
+    /// channels without a receiver.
+    pub fn with_idle_timeout(self, timeout: Duration) -> Self {
+        let mut guard = lock(&self.shared);
+        let shared = &mut *guard;
+        let mut idle = Idle {
+            timeout,
+            last: HashMap::new(),
+            order: BTreeSet::new(),
+            timer: None,
+        };
+        let now = shared.clock.now();
+        shared
+            .queues
+            .keys()
+            .for_each(|&channel| idle.touch(channel, now));
+        shared.idle = Some(idle);
+        drop(guard);
+        self
+    }
+
+    /// Measure the idle timeout with `clock`, see
+    /// [`Reader::with_clock`](crate::Reader::with_clock).
+    #[cfg(feature = "sim")]
+    pub fn with_clock<K: crate::Clock + 'static>(self, clock: K) -> Self {
+        let mut shared = lock(&self.shared);
+        shared.clock = Arc::new(clock);
+        if let Some(idle) = &mut shared.idle {
+            idle.timer = None;
+        }
+        drop(shared);
+        self
+    }
+
+    /// A stream of the [`ConnectionEvent`]s of the demux, i.e. of the channels that were
+    /// idle.
+    ///
+    /// Calling this again replaces the previous stream, which ends.
+    pub fn events(&mut self) -> ConnectionEvents {
+        let (events, sender) = ConnectionEvents::new();
+        self.events = Some(sender);
+        events
+    }
+
+    // Wait for the next channel that is idle, and end its receiver.
+    fn poll_idle(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        let mut guard = lock(&self.shared);
+        let shared = &mut *guard;
+        let channel = match &mut shared.idle {
+            Some(idle) => ready!(idle.poll_idle(&shared.clock, cx)),
+            None => return Poll::Pending,
+        };
+        if let Some(mut queue) = shared.queues.remove(&channel) {
+            wake(&mut queue.waker);
+            if let Some(events) = &self.events {
+                events.emit(ConnectionEvent::ChannelIdle(channel));
+            }
+        }
+        Poll::Ready(())
+    }
+
+    // Queue `message` for its receiver.
+    fn route(&mut self, message: Message, cx: &mut Context<'_>) -> Route {
+        let mut guard = lock(&self.shared);
+        let shared = &mut *guard;
+        let queue = match shared.queues.get_mut(&message.channel) {
+            Some(queue) => queue,
+            None => return Route::Unrouted(message),
+        };
+        if let Some(idle) = &mut shared.idle {
+            idle.touch(message.channel, shared.clock.now());
+        }
+        if queue.messages.len() >= queue.capacity {
+            match queue.overflow {
+                Overflow::Block => {
+                    shared.waker = Some(cx.waker().clone());
+                    return Route::Blocked(message);
+                }
+                Overflow::DropOldest => {
+                    queue.messages.pop_front();
+                }
+                Overflow::DropNewest => return Route::Queued,
+                Overflow::Error => return Route::Full(message.channel),
+            }
+        }
+        queue.messages.push_back(message);
+        wake(&mut queue.waker);
+        Route::Queued
+    }
+}
+
+impl<S> Stream for Demux<S>
+where
+    S: Stream<Item = Result<Message, SmcError>> + Unpin,
+{
+    type Item = Result<Message, SmcError>;
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Message, SmcError>>> {
+        let this = self.get_mut();
+        loop {
+            if this.poll_idle(cx).is_ready() {
+                continue;
+            }
+            if let Some(message) = this.blocked.take() {
+                match this.route(message, cx) {
+                    Route::Queued => {}
+                    Route::Blocked(message) => {
+                        this.blocked = Some(message);
+                        return Poll::Pending;
+                    }
+                    Route::Unrouted(message) => return Poll::Ready(Some(Ok(message))),
+                    Route::Full(channel) => {
+                        return Poll::Ready(Some(Err(SmcError::QueueFull(channel))))
+                    }
+                }
+            }
+            match Pin::new(&mut this.stream).poll_next(cx) {
+                Poll::Ready(Some(Ok(message))) => this.blocked = Some(message),
+                Poll::Ready(Some(Err(error))) => return Poll::Ready(Some(Err(error))),
+                Poll::Ready(None) => {
+                    finish(&mut lock(&this.shared));
+                    return Poll::Ready(None);
+                }
+                Poll::Pending => {
+                    let mut shared = lock(&this.shared);
+                    // The timeout of a new receiver may end before the next message.
+                    if shared.idle.is_some() {
+                        shared.waker = Some(cx.waker().clone());
+                    }
+                    return Poll::Pending;
+                }
+            }
+        }
+    }
+}
+
+// End all receivers, once they took their queued messages.
+fn finish(shared: &mut Shared) {
+    shared.finished = true;
+    shared
+        .queues
+        .values_mut()
+        .for_each(|queue| wake(&mut queue.waker));
+}
+
+impl<S> Drop for Demux<S> {
+    fn drop(&mut self) {
+        if let Ok(mut shared) = self.shared.lock() {
+            finish(&mut shared);
+        }
+    }
+}
+
+/// A handle to create [`ChannelReceiver`]s for a [`Demux`].
+#[derive(Clone)]
+pub struct DemuxHandle {
+    shared: Arc<Mutex<Shared>>,
+}
+
+impl DemuxHandle {
+    /// Create a receiver for the messages on `channel`.
+    ///
+    /// This replaces the previous receiver of the channel, which ends.
+    pub fn receiver(&self, channel: u64) -> ChannelReceiver {
+        let (capacity, overflow) = {
+            let shared = lock(&self.shared);
+            (shared.capacity, shared.overflow)
+        };
+        self.receiver_with_capacity(channel, capacity, overflow)
+    }
+
+    /// Create a receiver for the messages on `channel`, which queues up to `capacity`
+    /// messages and then applies `overflow`.
+    pub fn receiver_with_capacity(
+        &self,
+        channel: u64,
+        capacity: usize,
+        overflow: Overflow,
+    ) -> ChannelReceiver {
+        let mut shared = lock(&self.shared);
+        let id = shared.next_id;
+        shared.next_id += 1;
+        let queue = Queue {
+            id,
+            messages: VecDeque::new(),
+            capacity: capacity.max(1),
+            overflow,
+            waker: None,
+        };
+        if let Some(mut previous) = shared.queues.insert(channel, queue) {
+            wake(&mut previous.waker);
+        }
+        let now = shared.clock.now();
+        if let Some(idle) = &mut shared.idle {
+            idle.touch(channel, now);
+        }
+        // A blocked message for the channel fits into the new queue, and the demux tracks
+        // the new receiver.
+        wake(&mut shared.waker);
+        ChannelReceiver {
+            channel,
+            id,
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+/// Receives the messages of a single channel from a [`Demux`].
+pub struct ChannelReceiver {
+    channel: u64,
+    id: u64,
+    shared: Arc<Mutex<Shared>>,
+}
+
+impl ChannelReceiver {
+    /// The channel this receiver receives messages on.
+    pub fn channel(&self) -> u64 {
+        self.channel
+    }
+
+    /// The number of messages that are queued for this receiver.
+    pub fn queued(&self) -> usize {
+        lock(&self.shared)
+            .queues
+            .get(&self.channel)
+            .filter(|queue| queue.id == self.id)
+            .map_or(0, |queue| queue.messages.len())
+    }
+}
+
+impl Stream for ChannelReceiver {
+    type Item = Message;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Message>> {
+        let mut guard = lock(&self.shared);
+        let shared = &mut *guard;
+        let queue = match shared.queues.get_mut(&self.channel) {
+            Some(queue) if queue.id == self.id => queue,
+            _ => return Poll::Ready(None),
+        };
+        match queue.messages.pop_front() {
+            Some(message) => {
+                wake(&mut shared.waker);
+                Poll::Ready(Some(message))
+            }
+            None if shared.finished => Poll::Ready(None),
+            None => {
+                queue.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl Drop for ChannelReceiver {
+    fn drop(&mut self) {
+        if let Ok(mut shared) = self.shared.lock() {
+            if shared.queues.get(&self.channel).map(|queue| queue.id) == Some(self.id) {
+                shared.queues.remove(&self.channel);
+                if let Some(idle) = &mut shared.idle {
+                    idle.remove(self.channel);
+                }
+                // A blocked message for the channel is not routed to it anymore.
+                wake(&mut shared.waker);
+            }
+        }
+    }
+}
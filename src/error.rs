@@ -0,0 +1,170 @@
+#[cfg(feature = "std")]
+use alloc::boxed::Box;
+use core::fmt;
+#[cfg(feature = "std")]
+use std::io;
+
+use crate::{VarintError, MAX_CHANNEL, MAX_FRAME_LEN};
+
+/// An error when reading or writing SMC messages.
+#[derive(Debug)]
+pub enum SmcError {
+    /// The underlying reader or writer failed.
+    #[cfg(feature = "std")]
+    Io(io::Error),
+    /// A message is longer than [`MAX_MESSAGE_SIZE`](crate::MAX_MESSAGE_SIZE), or a
+    /// reassembled message is longer than the max length of the reader. Contains the
+    /// length of the message.
+    MessageTooLong(u64),
+    /// A message can't be encoded because its channel is larger than [`MAX_CHANNEL`].
+    /// Contains the channel of the message.
+    ChannelTooLarge(u64),
+    /// A varint in the length prefix or header of a message is invalid.
+    Varint(VarintError),
+    /// A message has a different typ than expected. Contains the typ of the message.
+    UnexpectedTyp(u8),
+    /// A typ is larger than 15, and can't be encoded. Contains the typ.
+    InvalidTyp(u8),
+    /// The stream ended in the middle of a frame, or a datagram is shorter or longer than
+    /// its length prefix. Contains the length of the frame and the number of its bytes
+    /// that were received.
+    ///
+    /// If the stream ends in the middle of the length prefix, the length is unknown:
+    /// `expected` is 0, and `got` is the number of bytes of the prefix.
+    TruncatedFrame { expected: u64, got: u64 },
+    /// The checksum of a frame does not match, see `Checksum`.
+    ChecksumMismatch,
+    /// The keystream of the cipher is exhausted, or less than the rekey threshold is
+    /// left, see `Writer::with_rekey_threshold`. Replace the cipher with new keys.
+    RekeyNeeded,
+    /// A key or a nonce has the wrong length, see [`KeyedCipher`](crate::KeyedCipher).
+    InvalidKeyLength,
+    /// A message was not sent before its deadline, see `Writer::send_with_deadline`.
+    SendTimeout,
+    /// The writer was closed, and can't send messages anymore, see `Writer::close`.
+    Closed,
+    /// The queue of a receiver of a `Demux` is full, and its overflow policy is
+    /// `Overflow::Error`. Contains the channel of the receiver.
+    QueueFull(u64),
+    /// A `Remap` has no mapping for the channel of a message, and its policy is
+    /// `Unmapped::Error`. Contains the channel.
+    UnmappedChannel(u64),
+    /// A `SessionState` can't be exported from a channel that failed or was closed, can't
+    /// be imported into a channel that was used, or can't be decoded.
+    InvalidSession,
+    /// The payload of a message could not be encoded or decoded.
+    #[cfg(feature = "std")]
+    Payload(Box<dyn std::error::Error + Send + Sync>),
+}
+
+#[cfg(feature = "std")]
+impl SmcError {
+    /// The [`io::ErrorKind`] that corresponds to this error.
+    pub fn kind(&self) -> io::ErrorKind {
+        match self {
+            SmcError::Io(error) => error.kind(),
+            SmcError::MessageTooLong(_) => io::ErrorKind::InvalidInput,
+            SmcError::ChannelTooLarge(_) => io::ErrorKind::InvalidInput,
+            SmcError::Varint(_) => io::ErrorKind::InvalidData,
+            SmcError::UnexpectedTyp(_) => io::ErrorKind::InvalidData,
+            SmcError::InvalidTyp(_) => io::ErrorKind::InvalidInput,
+            SmcError::TruncatedFrame { .. } => io::ErrorKind::UnexpectedEof,
+            SmcError::ChecksumMismatch => io::ErrorKind::InvalidData,
+            SmcError::RekeyNeeded => io::ErrorKind::Other,
+            SmcError::InvalidKeyLength => io::ErrorKind::InvalidInput,
+            SmcError::SendTimeout => io::ErrorKind::TimedOut,
+            SmcError::Closed => io::ErrorKind::NotConnected,
+            SmcError::QueueFull(_) => io::ErrorKind::Other,
+            SmcError::UnmappedChannel(_) => io::ErrorKind::InvalidInput,
+            SmcError::InvalidSession => io::ErrorKind::InvalidInput,
+            SmcError::Payload(_) => io::ErrorKind::InvalidData,
+        }
+    }
+}
+
+impl fmt::Display for SmcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            #[cfg(feature = "std")]
+            SmcError::Io(error) => write!(f, "IO error: {}", error),
+            SmcError::MessageTooLong(len) => write!(
+                f,
+                "Message too long ({} bytes, max is {} bytes)",
+                len, MAX_FRAME_LEN
+            ),
+            SmcError::ChannelTooLarge(channel) => {
+                write!(f, "Channel too large ({}, max is {})", channel, MAX_CHANNEL)
+            }
+            SmcError::Varint(error) => write!(f, "Invalid varint: {}", error),
+            SmcError::UnexpectedTyp(typ) => write!(f, "Unexpected message typ {}", typ),
+            SmcError::InvalidTyp(typ) => write!(f, "Invalid message typ {}, max is 15", typ),
+            SmcError::TruncatedFrame { expected: 0, got } => write!(
+                f,
+                "Stream ended in the middle of a length prefix ({} bytes received)",
+                got
+            ),
+            SmcError::TruncatedFrame { expected, got } => write!(
+                f,
+                "Stream ended in the middle of a frame ({} of {} bytes received)",
+                got, expected
+            ),
+            SmcError::ChecksumMismatch => write!(f, "Checksum mismatch"),
+            SmcError::RekeyNeeded => write!(f, "Cipher keystream exhausted, rekey needed"),
+            SmcError::InvalidKeyLength => write!(f, "Invalid key or nonce length"),
+            SmcError::SendTimeout => write!(f, "Message not sent before its deadline"),
+            SmcError::Closed => write!(f, "Writer closed"),
+            SmcError::QueueFull(channel) => write!(f, "Queue of channel {} full", channel),
+            SmcError::UnmappedChannel(channel) => write!(f, "No mapping for channel {}", channel),
+            SmcError::InvalidSession => write!(f, "Invalid session state"),
+            #[cfg(feature = "std")]
+            SmcError::Payload(error) => write!(f, "Invalid payload: {}", error),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for SmcError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SmcError::Io(error) => Some(error),
+            SmcError::MessageTooLong(_) => None,
+            SmcError::ChannelTooLarge(_) => None,
+            SmcError::Varint(error) => Some(error),
+            SmcError::UnexpectedTyp(_) => None,
+            SmcError::InvalidTyp(_) => None,
+            SmcError::TruncatedFrame { .. } => None,
+            SmcError::ChecksumMismatch => None,
+            SmcError::RekeyNeeded => None,
+            SmcError::InvalidKeyLength => None,
+            SmcError::SendTimeout => None,
+            SmcError::Closed => None,
+            SmcError::QueueFull(_) => None,
+            SmcError::UnmappedChannel(_) => None,
+            SmcError::InvalidSession => None,
+            SmcError::Payload(error) => Some(error.as_ref()),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<io::Error> for SmcError {
+    fn from(error: io::Error) -> Self {
+        SmcError::Io(error)
+    }
+}
+
+impl From<VarintError> for SmcError {
+    fn from(error: VarintError) -> Self {
+        SmcError::Varint(error)
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<SmcError> for io::Error {
+    fn from(error: SmcError) -> Self {
+        match error {
+            SmcError::Io(error) => error,
+            error => io::Error::new(error.kind(), error),
+        }
+    }
+}
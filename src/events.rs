@@ -0,0 +1,90 @@
+use futures::stream::Stream;
+use futures::task::{Context, Poll};
+use std::collections::HashSet;
+use std::pin::Pin;
+
+use crate::{Message, SmcError, Typ};
+
+/// The typ of messages that close a channel.
+///
+/// This is the typ of the `Close` message of hypercore. A close message has an empty
+/// payload, see [`Writer::close_channel`](crate::Writer::close_channel). This typ is
+/// reserved, see [`Typ::RESERVED`].
+pub const CLOSE_TYP: u8 = Typ::CLOSE.get();
+
+/// An event on a channel, see [`ChannelEvents`].
+#[derive(Debug)]
+pub enum ChannelEvent<B = Vec<u8>> {
+    /// A message was received.
+    Message(Message<B>),
+    /// The channel was closed by the peer.
+    Closed(u64),
+}
+
+/// A stream of [`ChannelEvent`]s.
+///
+/// This wraps a stream of messages, e.g. a [`Reader`](crate::Reader), and turns
+/// close messages into [`ChannelEvent::Closed`] events. Messages that arrive on a
+/// channel after it was closed are dropped.
+pub struct ChannelEvents<S> {
+    stream: S,
+    close_typ: u8,
+    closed: HashSet<u64>,
+}
+
+impl<S> ChannelEvents<S> {
+    /// Create a new stream of events from a stream of messages.
+    pub fn new(stream: S) -> Self {
+        Self {
+            stream,
+            close_typ: CLOSE_TYP,
+            closed: HashSet::new(),
+        }
+    }
+
+    /// Set the typ of messages that close a channel.
+    ///
+    /// Defaults to [`CLOSE_TYP`].
+    pub fn with_close_typ(mut self, typ: u8) -> Self {
+        self.close_typ = typ;
+        self
+    }
+
+    /// Returns `true` if `channel` was closed by the peer.
+    pub fn is_closed(&self, channel: u64) -> bool {
+        self.closed.contains(&channel)
+    }
+
+    /// Unwrap the wrapped stream.
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+}
+
+impl<S, B> Stream for ChannelEvents<S>
+where
+    S: Stream<Item = Result<Message<B>, SmcError>> + Unpin,
+{
+    type Item = Result<ChannelEvent<B>, SmcError>;
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<ChannelEvent<B>, SmcError>>> {
+        let this = self.get_mut();
+        loop {
+            let message = match futures::ready!(Pin::new(&mut this.stream).poll_next(cx)) {
+                Some(Ok(message)) => message,
+                Some(Err(error)) => return Poll::Ready(Some(Err(error))),
+                None => return Poll::Ready(None),
+            };
+            if this.closed.contains(&message.channel) {
+                continue;
+            }
+            if message.typ == this.close_typ {
+                this.closed.insert(message.channel);
+                return Poll::Ready(Some(Ok(ChannelEvent::Closed(message.channel))));
+            }
+            return Poll::Ready(Some(Ok(ChannelEvent::Message(message))));
+        }
+    }
+}
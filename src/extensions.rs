@@ -0,0 +1,136 @@
+use std::collections::HashMap;
+
+use crate::varint;
+use crate::{Message, SmcError};
+
+/// The typ of extension messages, as used by hypercore-protocol.
+pub const EXTENSION_TYP: u8 = 15;
+
+/// A registry of named extensions.
+///
+/// Extensions are carried in messages with typ [`EXTENSION_TYP`]. Each peer sends the
+/// names of the extensions it supports with [`Extensions::handshake`], and then
+/// sends extension messages with the id of the name in its own list. The registry
+/// keeps the lists of the remote peer per channel and maps the ids back to names.
+///
+/// The payload of an extension message is a varint id, followed by the payload of
+/// the extension. The id `0` is the handshake, whose payload is the list of names,
+/// each of them prefixed with its length.
+#[derive(Debug, Clone, Default)]
+pub struct Extensions {
+    names: Vec<String>,
+    remote: HashMap<u64, Vec<String>>,
+}
+
+/// A message of a named extension, see [`Extensions`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtensionMessage {
+    pub channel: u64,
+    pub name: String,
+    pub payload: Vec<u8>,
+}
+
+impl Extensions {
+    /// Create an empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register an extension.
+    pub fn register(&mut self, name: impl Into<String>) {
+        let name = name.into();
+        if let Err(index) = self.names.binary_search(&name) {
+            self.names.insert(index, name);
+        }
+    }
+
+    /// The names of the registered extensions, sorted.
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// The names of the extensions the remote peer supports on `channel`.
+    ///
+    /// Returns `None` if the peer did not send a handshake yet.
+    pub fn remote_names(&self, channel: u64) -> Option<&[String]> {
+        self.remote.get(&channel).map(Vec::as_slice)
+    }
+
+    /// Create the handshake message for `channel`, with the names of all registered
+    /// extensions.
+    pub fn handshake(&self, channel: u64) -> Message {
+        let mut buf = Vec::new();
+        push_varint(0, &mut buf);
+        for name in &self.names {
+            push_varint(name.len() as u64, &mut buf);
+            buf.extend_from_slice(name.as_bytes());
+        }
+        Message::new(channel, EXTENSION_TYP, buf)
+    }
+
+    /// Create a message for the extension `name` on `channel`.
+    ///
+    /// Returns `None` if the extension is not registered.
+    pub fn message(&self, channel: u64, name: &str, payload: &[u8]) -> Option<Message> {
+        let index = self.names.iter().position(|n| n == name)?;
+        let mut buf = Vec::with_capacity(varint::MAX_VARINT_LEN + payload.len());
+        push_varint(index as u64 + 1, &mut buf);
+        buf.extend_from_slice(payload);
+        Some(Message::new(channel, EXTENSION_TYP, buf))
+    }
+
+    /// Handle a received extension message.
+    ///
+    /// A handshake updates the names of the remote peer for the channel and returns
+    /// `None`, as do messages of extensions that are not registered locally.
+    /// Messages that are not extension messages return
+    /// [`SmcError::UnexpectedTyp`].
+    pub fn on_message<B: AsRef<[u8]>>(
+        &mut self,
+        message: &Message<B>,
+    ) -> Result<Option<ExtensionMessage>, SmcError> {
+        if message.typ != EXTENSION_TYP {
+            return Err(SmcError::UnexpectedTyp(message.typ));
+        }
+        let buf = message.message.as_ref();
+        let (id, len) = varint::decode(buf)?;
+        let buf = &buf[len..];
+        if id == 0 {
+            self.remote.insert(message.channel, decode_names(buf)?);
+            return Ok(None);
+        }
+        let name = self
+            .remote
+            .get(&message.channel)
+            .and_then(|names| names.get((id - 1) as usize))
+            .filter(|name| self.names.binary_search(name).is_ok());
+        Ok(name.map(|name| ExtensionMessage {
+            channel: message.channel,
+            name: name.clone(),
+            payload: buf.to_vec(),
+        }))
+    }
+}
+
+fn push_varint(value: u64, buf: &mut Vec<u8>) {
+    let mut varint = [0u8; varint::MAX_VARINT_LEN];
+    let len = varint::encode(value, &mut varint);
+    buf.extend_from_slice(&varint[..len]);
+}
+
+fn decode_names(mut buf: &[u8]) -> Result<Vec<String>, SmcError> {
+    let mut names = Vec::new();
+    while !buf.is_empty() {
+        let (len, varint_len) = varint::decode(buf)?;
+        buf = &buf[varint_len..];
+        if len > buf.len() as u64 {
+            return Err(varint::VarintError::Incomplete.into());
+        }
+        let (name, rest) = buf.split_at(len as usize);
+        let name =
+            String::from_utf8(name.to_vec()).map_err(|error| SmcError::Payload(Box::new(error)))?;
+        names.push(name);
+        buf = rest;
+    }
+    Ok(names)
+}
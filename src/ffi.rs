@@ -0,0 +1,202 @@
+//! C bindings for the codec.
+//!
+//! These functions expose [`encode_header_into`] and the [`Decoder`] with a C ABI, so
+//! that implementations of hypercore-protocol in other languages can reuse the framing
+//! of this crate. The header of the bindings is `include/simple_message_channels.h`,
+//! generated with [cbindgen](https://github.com/mozilla/cbindgen):
+//!
+//! ```sh
+//! cbindgen --config cbindgen.toml --output include/simple_message_channels.h
+//! ```
+//!
+//! Build a static or dynamic library with the bindings with
+//! `cargo rustc --release --features ffi --crate-type staticlib` (or `cdylib`).
+//!
+//! Enabled with the `ffi` feature.
+
+use alloc::boxed::Box;
+use core::ptr;
+use core::slice;
+
+use crate::codec::{encode_header_into, Decoder, MAX_HEADER_LEN};
+use crate::Message;
+
+/// The result of a function of the bindings.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SmcStatus {
+    /// The function succeeded.
+    Ok = 0,
+    /// The decoder needs more bytes to decode the next message.
+    Pending = 1,
+    /// The output buffer is too small. The needed length is written to `out_len`.
+    BufferTooSmall = 2,
+    /// A pointer is null.
+    NullPointer = 3,
+    /// The message can't be encoded, e.g. because its typ is larger than 15.
+    InvalidMessage = 4,
+    /// The stream can't be decoded. The decoder yields no more messages.
+    InvalidData = 5,
+}
+
+/// A decoded message, which borrows its payload from a [`SmcDecoder`].
+#[repr(C)]
+#[derive(Debug)]
+pub struct SmcMessage {
+    /// The channel of the message.
+    pub channel: u64,
+    /// The typ of the message.
+    pub typ: u8,
+    /// The payload of the message, valid until the next call with the decoder.
+    pub data: *const u8,
+    /// The length of the payload.
+    pub len: usize,
+}
+
+/// An incremental decoder, see [`Decoder`].
+///
+/// Create it with [`smc_decoder_new`], and free it with [`smc_decoder_free`].
+pub struct SmcDecoder {
+    decoder: Decoder,
+    // The last message returned by `smc_decoder_next`, which owns its payload.
+    current: Option<Message>,
+    failed: bool,
+}
+
+// The bytes at `data`, or `None` if `data` is null and `len` is not 0.
+unsafe fn bytes<'a>(data: *const u8, len: usize) -> Option<&'a [u8]> {
+    match len {
+        0 => Some(&[]),
+        _ if data.is_null() => None,
+        _ => Some(slice::from_raw_parts(data, len)),
+    }
+}
+
+/// Encode a message into `out`, and write the length of the encoded message to
+/// `out_len`.
+///
+/// If `out_cap` is too small, nothing is written to `out`, the needed length is written
+/// to `out_len`, and this returns [`SmcStatus::BufferTooSmall`].
+///
+/// # Safety
+///
+/// `data` must point to `len` readable bytes, `out` to `out_cap` writable bytes, and
+/// `out_len` to a writable `size_t`. `data` and `out` may be null if their length is 0.
+#[no_mangle]
+pub unsafe extern "C" fn smc_encode(
+    channel: u64,
+    typ: u8,
+    data: *const u8,
+    len: usize,
+    out: *mut u8,
+    out_cap: usize,
+    out_len: *mut usize,
+) -> SmcStatus {
+    let payload = match bytes(data, len) {
+        Some(payload) => payload,
+        None => return SmcStatus::NullPointer,
+    };
+    if out_len.is_null() {
+        return SmcStatus::NullPointer;
+    }
+    let message = Message::borrowed(channel, typ, payload);
+    let mut header = [0u8; MAX_HEADER_LEN];
+    let header_len = match encode_header_into(&message, &mut header) {
+        Ok(header_len) => header_len,
+        Err(_) => return SmcStatus::InvalidMessage,
+    };
+    let encoded_len = header_len + payload.len();
+    *out_len = encoded_len;
+    if out_cap < encoded_len {
+        return SmcStatus::BufferTooSmall;
+    }
+    if out.is_null() {
+        return SmcStatus::NullPointer;
+    }
+    ptr::copy_nonoverlapping(header.as_ptr(), out, header_len);
+    ptr::copy_nonoverlapping(payload.as_ptr(), out.add(header_len), payload.len());
+    SmcStatus::Ok
+}
+
+/// Create a new decoder.
+#[no_mangle]
+pub extern "C" fn smc_decoder_new() -> *mut SmcDecoder {
+    Box::into_raw(Box::new(SmcDecoder {
+        decoder: Decoder::new(),
+        current: None,
+        failed: false,
+    }))
+}
+
+/// Free a decoder, and the payload of the last message it returned.
+///
+/// # Safety
+///
+/// `decoder` must be returned by [`smc_decoder_new`] and not be freed yet, or be null.
+#[no_mangle]
+pub unsafe extern "C" fn smc_decoder_free(decoder: *mut SmcDecoder) {
+    if !decoder.is_null() {
+        drop(Box::from_raw(decoder));
+    }
+}
+
+/// Add `len` bytes at `data` to the decoder.
+///
+/// This does not decode the bytes, take the messages out with [`smc_decoder_next`].
+///
+/// # Safety
+///
+/// `decoder` must be a valid decoder, and `data` must point to `len` readable bytes. `data`
+/// may be null if `len` is 0.
+#[no_mangle]
+pub unsafe extern "C" fn smc_decoder_push(
+    decoder: *mut SmcDecoder,
+    data: *const u8,
+    len: usize,
+) -> SmcStatus {
+    let (decoder, data) = match (decoder.as_mut(), bytes(data, len)) {
+        (Some(decoder), Some(data)) => (decoder, data),
+        _ => return SmcStatus::NullPointer,
+    };
+    decoder.decoder.extend(data);
+    SmcStatus::Ok
+}
+
+/// Take the next complete message out of the decoder, and write it to `out`.
+///
+/// Returns [`SmcStatus::Pending`] if more bytes are needed. The payload of the message
+/// is owned by the decoder, and is valid until the next call with the decoder.
+/// Keepalive messages are skipped.
+///
+/// # Safety
+///
+/// `decoder` must be a valid decoder, and `out` must point to a writable [`SmcMessage`].
+#[no_mangle]
+pub unsafe extern "C" fn smc_decoder_next(
+    decoder: *mut SmcDecoder,
+    out: *mut SmcMessage,
+) -> SmcStatus {
+    let decoder = match decoder.as_mut() {
+        Some(decoder) if !out.is_null() => decoder,
+        _ => return SmcStatus::NullPointer,
+    };
+    decoder.current = None;
+    if decoder.failed {
+        return SmcStatus::InvalidData;
+    }
+    let message = match decoder.decoder.poll_message() {
+        Ok(Some(message)) => decoder.current.insert(message),
+        Ok(None) => return SmcStatus::Pending,
+        Err(_) => {
+            decoder.failed = true;
+            return SmcStatus::InvalidData;
+        }
+    };
+    *out = SmcMessage {
+        channel: message.channel,
+        typ: message.typ,
+        data: message.message.as_ptr(),
+        len: message.message.len(),
+    };
+    SmcStatus::Ok
+}
@@ -0,0 +1,235 @@
+use futures::stream::Stream;
+use futures::task::{Context, Poll, Waker};
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+
+use crate::varint::{self, MAX_VARINT_LEN};
+use crate::{Message, SmcError, Typ};
+
+/// The typ of window update messages, see [`FlowControl`].
+///
+/// This typ is not used by hypercore-protocol, and is reserved, see [`Typ::RESERVED`].
+pub const WINDOW_TYP: u8 = Typ::WINDOW.get();
+
+/// Credit-based flow control.
+///
+/// Each side grants the peer a window of payload bytes per channel, which the peer may
+/// send before it has to wait for more credit. The window of a channel is opened with
+/// [`FlowControl::open`], and refilled once the application [consumed](FlowControl::consume)
+/// half of it. Grants are sent as messages with typ [`WINDOW_TYP`], whose payload is the
+/// number of granted bytes as a varint.
+///
+/// On the receiving side, wrap the stream of messages in a [`FlowStream`], which
+/// counts received bytes and applies the grants of the peer. This is required: a
+/// [`Demux`](crate::Demux) or a [`Reader`](crate::Reader) that is not wrapped does not
+/// count received bytes, and yields the window updates as messages. On the sending side,
+/// [`Mux::with_flow_control`](crate::Mux::with_flow_control) sends the grants and
+/// stops sending on a channel once its credit is used up.
+///
+/// Flow control is opt-in per channel: channels the peer did not grant a window for
+/// are not limited, so peers that ignore flow control keep working.
+///
+/// The handle is cheap to clone, and all clones share the same state.
+#[derive(Clone)]
+pub struct FlowControl {
+    inner: Arc<Mutex<Inner>>,
+}
+
+struct Inner {
+    typ: u8,
+    window: u64,
+    // Bytes received but not consumed, per channel.
+    unconsumed: HashMap<u64, u64>,
+    // Bytes consumed but not granted again, per opened channel.
+    opened: HashMap<u64, u64>,
+    // Bytes the peer granted us, per channel.
+    credit: HashMap<u64, u64>,
+    grants: Vec<Message>,
+    waker: Option<Waker>,
+}
+
+impl FlowControl {
+    /// Create flow control that grants the peer `window` bytes per opened channel.
+    pub fn new(window: u64) -> Self {
+        let inner = Inner {
+            typ: WINDOW_TYP,
+            window,
+            unconsumed: HashMap::new(),
+            opened: HashMap::new(),
+            credit: HashMap::new(),
+            grants: Vec::new(),
+            waker: None,
+        };
+        Self {
+            inner: Arc::new(Mutex::new(inner)),
+        }
+    }
+
+    /// Set the typ of window update messages.
+    ///
+    /// Defaults to [`WINDOW_TYP`].
+    pub fn with_typ(self, typ: u8) -> Self {
+        self.lock().typ = typ;
+        self
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Inner> {
+        self.inner.lock().expect("flow control lock poisoned")
+    }
+
+    /// Grant the peer the window on `channel`.
+    ///
+    /// From now on, the peer is limited to the window on this channel.
+    pub fn open(&self, channel: u64) {
+        let mut inner = self.lock();
+        if inner.opened.contains_key(&channel) {
+            return;
+        }
+        inner.opened.insert(channel, 0);
+        let window = inner.window;
+        inner.grant(channel, window);
+    }
+
+    /// Mark `len` bytes received on `channel` as consumed by the application.
+    ///
+    /// Once half of the window is consumed, or at least one byte of a window of 1 byte,
+    /// the consumed bytes are granted to the peer again.
+    pub fn consume(&self, channel: u64, len: u64) {
+        let mut inner = self.lock();
+        if let Some(unconsumed) = inner.unconsumed.get_mut(&channel) {
+            *unconsumed = unconsumed.saturating_sub(len);
+        }
+        // A threshold of 0 would grant 0 bytes.
+        let threshold = (inner.window / 2).max(1);
+        let consumed = match inner.opened.get_mut(&channel) {
+            Some(consumed) => consumed,
+            None => return,
+        };
+        *consumed += len;
+        if *consumed >= threshold {
+            let len = std::mem::take(consumed);
+            inner.grant(channel, len);
+        }
+    }
+
+    /// The number of bytes received on `channel` that are not consumed yet.
+    ///
+    /// Only the messages that passed a [`FlowStream`] are counted.
+    pub fn unconsumed(&self, channel: u64) -> u64 {
+        self.lock().unconsumed.get(&channel).copied().unwrap_or(0)
+    }
+
+    /// The number of bytes that may be sent on `channel`.
+    ///
+    /// Returns `None` if the peer did not grant a window on the channel, and sending
+    /// is not limited.
+    pub fn credit(&self, channel: u64) -> Option<u64> {
+        self.lock().credit.get(&channel).copied()
+    }
+
+    // Take the grants to send, and wake the task with `cx` once there are more grants
+    // or credit.
+    pub(crate) fn poll_grants(&self, cx: &mut Context<'_>) -> Vec<Message> {
+        let mut inner = self.lock();
+        inner.waker = Some(cx.waker().clone());
+        std::mem::take(&mut inner.grants)
+    }
+
+    // Take credit for `message`. Returns `false` if the channel has no credit left.
+    //
+    // A message is sent while the channel has any credit left, so that messages
+    // larger than the window don't block the channel.
+    pub(crate) fn take_credit<B: AsRef<[u8]>>(&self, message: &Message<B>) -> bool {
+        let mut inner = self.lock();
+        match inner.credit.get_mut(&message.channel) {
+            Some(0) => false,
+            Some(credit) => {
+                *credit = credit.saturating_sub(message.message.as_ref().len() as u64);
+                true
+            }
+            None => true,
+        }
+    }
+
+    // Account a received message. Returns `false` if it is a window update.
+    fn receive<B: AsRef<[u8]>>(&self, message: &Message<B>) -> Result<bool, SmcError> {
+        let mut inner = self.lock();
+        let len = message.message.as_ref().len() as u64;
+        if message.typ != inner.typ {
+            *inner.unconsumed.entry(message.channel).or_insert(0) += len;
+            return Ok(true);
+        }
+        let (granted, _) = varint::decode(message.message.as_ref())?;
+        let credit = inner.credit.entry(message.channel).or_insert(0);
+        *credit = credit.saturating_add(granted);
+        if let Some(waker) = inner.waker.take() {
+            waker.wake();
+        }
+        Ok(false)
+    }
+}
+
+impl Inner {
+    fn grant(&mut self, channel: u64, len: u64) {
+        let mut buf = [0u8; MAX_VARINT_LEN];
+        let n = varint::encode(len, &mut buf);
+        self.grants
+            .push(Message::new(channel, self.typ, buf[..n].to_vec()));
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// A stream of messages with flow control, see [`FlowControl`].
+///
+/// This wraps a stream of messages, e.g. a [`Reader`](crate::Reader). Window updates of
+/// the peer are applied to the [`FlowControl`] and not yielded. The payload of all other
+/// messages counts as unconsumed until it is passed to [`FlowControl::consume`].
+pub struct FlowStream<S> {
+    stream: S,
+    flow: FlowControl,
+}
+
+impl<S> FlowStream<S> {
+    /// Create a new stream with flow control from a stream of messages.
+    pub fn new(stream: S, flow: FlowControl) -> Self {
+        Self { stream, flow }
+    }
+
+    /// Get a reference to the flow control.
+    pub fn flow_control(&self) -> &FlowControl {
+        &self.flow
+    }
+
+    /// Unwrap the wrapped stream.
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+}
+
+impl<S, B> Stream for FlowStream<S>
+where
+    S: Stream<Item = Result<Message<B>, SmcError>> + Unpin,
+    B: AsRef<[u8]>,
+{
+    type Item = Result<Message<B>, SmcError>;
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Message<B>, SmcError>>> {
+        let this = self.get_mut();
+        loop {
+            let message = match futures::ready!(Pin::new(&mut this.stream).poll_next(cx)) {
+                Some(Ok(message)) => message,
+                other => return Poll::Ready(other),
+            };
+            match this.flow.receive(&message) {
+                Ok(true) => return Poll::Ready(Some(Ok(message))),
+                Ok(false) => {}
+                Err(error) => return Poll::Ready(Some(Err(error))),
+            }
+        }
+    }
+}
@@ -0,0 +1,91 @@
+use std::collections::HashMap;
+
+use crate::codec::MAX_HEADER_LEN;
+use crate::{Message, SmcError, Typ, MAX_MESSAGE_SIZE};
+
+/// The typ of the fragments of a message that is split, see
+/// [`Writer::with_fragmentation`](crate::Writer::with_fragmentation).
+///
+/// A message with a payload of more than the fragment length is sent as fragments of
+/// this typ on the channel of the message, followed by a message with the typ of the
+/// message and the rest of the payload. The reader appends the fragments of a channel
+/// until this last message arrives. This typ is not used by hypercore-protocol, and is
+/// reserved, see [`Typ::RESERVED`].
+pub const FRAGMENT_TYP: u8 = Typ::FRAGMENT.get();
+
+/// The largest fragment length, which fits a fragment into a frame of
+/// [`MAX_MESSAGE_SIZE`].
+pub const MAX_FRAGMENT_LEN: usize = MAX_MESSAGE_SIZE as usize - MAX_HEADER_LEN;
+
+// Split the payloads of `messages` into fragments of at most `fragment_len` bytes.
+pub(crate) fn split<B: AsRef<[u8]>>(
+    messages: &[Message<B>],
+    fragment_len: usize,
+) -> Vec<Message<&[u8]>> {
+    let mut fragments = Vec::with_capacity(messages.len());
+    for message in messages {
+        let payload = message.message.as_ref();
+        if payload.len() <= fragment_len {
+            fragments.push(message.as_borrowed());
+            continue;
+        }
+        let mut chunks = payload.chunks(fragment_len).peekable();
+        while let Some(chunk) = chunks.next() {
+            let typ = match chunks.peek() {
+                Some(_) => FRAGMENT_TYP,
+                None => message.typ,
+            };
+            fragments.push(Message::borrowed(message.channel, typ, chunk));
+        }
+    }
+    fragments
+}
+
+/// The result of passing a message to a [`Reassembler`].
+pub(crate) enum Reassembly {
+    /// The message is a fragment, which was buffered.
+    Pending,
+    /// The message was not split.
+    Whole,
+    /// The message is the last part of a split message, with this payload.
+    Complete(Vec<u8>),
+}
+
+/// Reassembles split messages, per channel.
+pub(crate) struct Reassembler {
+    max_len: usize,
+    partial: HashMap<u64, Vec<u8>>,
+}
+
+impl Reassembler {
+    pub(crate) fn new(max_len: usize) -> Self {
+        Self {
+            max_len,
+            partial: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn push(&mut self, message: &Message<&[u8]>) -> Result<Reassembly, SmcError> {
+        let fragment = message.typ == FRAGMENT_TYP;
+        if !fragment && !self.partial.contains_key(&message.channel) {
+            return Ok(Reassembly::Whole);
+        }
+        let partial = self.partial.entry(message.channel).or_default();
+        let len = partial.len() + message.message.len();
+        if len > self.max_len {
+            self.partial.remove(&message.channel);
+            return Err(SmcError::MessageTooLong(len as u64));
+        }
+        partial.extend_from_slice(message.message);
+        if fragment {
+            return Ok(Reassembly::Pending);
+        }
+        let payload = self.partial.remove(&message.channel).unwrap_or_default();
+        Ok(Reassembly::Complete(payload))
+    }
+
+    // Drop the fragments of `channel`, e.g. if its last message is filtered out.
+    pub(crate) fn discard(&mut self, channel: u64) {
+        self.partial.remove(&channel);
+    }
+}
@@ -0,0 +1,132 @@
+//! Message types of hypercore-protocol.
+//!
+//! Enabled with the `hypercore` feature.
+
+use core::convert::TryFrom;
+
+use crate::{Message, SmcError, Typ};
+
+/// The type of a hypercore-protocol message, i.e. the typ of an SMC message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MessageType {
+    Open = 0,
+    Options = 1,
+    Status = 2,
+    Have = 3,
+    Unhave = 4,
+    Want = 5,
+    Unwant = 6,
+    Request = 7,
+    Cancel = 8,
+    Data = 9,
+    Close = 10,
+    Extension = 15,
+}
+
+impl From<MessageType> for u8 {
+    fn from(typ: MessageType) -> u8 {
+        typ as u8
+    }
+}
+
+impl From<MessageType> for Typ {
+    fn from(typ: MessageType) -> Typ {
+        Typ::new(typ as u8).expect("message types are in range")
+    }
+}
+
+impl TryFrom<u8> for MessageType {
+    type Error = SmcError;
+    fn try_from(typ: u8) -> Result<Self, SmcError> {
+        let typ = match typ {
+            0 => MessageType::Open,
+            1 => MessageType::Options,
+            2 => MessageType::Status,
+            3 => MessageType::Have,
+            4 => MessageType::Unhave,
+            5 => MessageType::Want,
+            6 => MessageType::Unwant,
+            7 => MessageType::Request,
+            8 => MessageType::Cancel,
+            9 => MessageType::Data,
+            10 => MessageType::Close,
+            15 => MessageType::Extension,
+            typ => return Err(SmcError::UnexpectedTyp(typ)),
+        };
+        Ok(typ)
+    }
+}
+
+impl<B> Message<B> {
+    /// Create a new message of a hypercore-protocol type.
+    pub fn with_type(channel: u64, typ: MessageType, message: B) -> Message<B> {
+        Message::new(channel, typ.into(), message)
+    }
+
+    /// The hypercore-protocol type of this message.
+    ///
+    /// Returns [`SmcError::UnexpectedTyp`] if the typ is not a known type.
+    pub fn message_type(&self) -> Result<MessageType, SmcError> {
+        MessageType::try_from(self.typ)
+    }
+
+    /// Create a new `Open` message.
+    pub fn open(channel: u64, message: B) -> Message<B> {
+        Message::with_type(channel, MessageType::Open, message)
+    }
+
+    /// Create a new `Options` message.
+    pub fn options(channel: u64, message: B) -> Message<B> {
+        Message::with_type(channel, MessageType::Options, message)
+    }
+
+    /// Create a new `Status` message.
+    pub fn status(channel: u64, message: B) -> Message<B> {
+        Message::with_type(channel, MessageType::Status, message)
+    }
+
+    /// Create a new `Have` message.
+    pub fn have(channel: u64, message: B) -> Message<B> {
+        Message::with_type(channel, MessageType::Have, message)
+    }
+
+    /// Create a new `Unhave` message.
+    pub fn unhave(channel: u64, message: B) -> Message<B> {
+        Message::with_type(channel, MessageType::Unhave, message)
+    }
+
+    /// Create a new `Want` message.
+    pub fn want(channel: u64, message: B) -> Message<B> {
+        Message::with_type(channel, MessageType::Want, message)
+    }
+
+    /// Create a new `Unwant` message.
+    pub fn unwant(channel: u64, message: B) -> Message<B> {
+        Message::with_type(channel, MessageType::Unwant, message)
+    }
+
+    /// Create a new `Request` message.
+    pub fn request(channel: u64, message: B) -> Message<B> {
+        Message::with_type(channel, MessageType::Request, message)
+    }
+
+    /// Create a new `Cancel` message.
+    pub fn cancel(channel: u64, message: B) -> Message<B> {
+        Message::with_type(channel, MessageType::Cancel, message)
+    }
+
+    /// Create a new `Data` message.
+    pub fn data(channel: u64, message: B) -> Message<B> {
+        Message::with_type(channel, MessageType::Data, message)
+    }
+
+    /// Create a new `Close` message.
+    pub fn close(channel: u64, message: B) -> Message<B> {
+        Message::with_type(channel, MessageType::Close, message)
+    }
+
+    /// Create a new `Extension` message.
+    pub fn extension(channel: u64, message: B) -> Message<B> {
+        Message::with_type(channel, MessageType::Extension, message)
+    }
+}
@@ -0,0 +1,220 @@
+use futures::ready;
+use futures::sink::Sink;
+use futures::stream::Stream;
+use futures::task::{Context, Poll};
+use std::collections::VecDeque;
+use std::pin::Pin;
+
+use crate::{Channel, FrameCipher, Message, SmcError};
+
+/// Middleware that transforms, drops or injects messages, see [`Intercepted`].
+///
+/// Both methods pass the message on unchanged by default.
+pub trait Interceptor: Send {
+    /// Intercept a message that was read.
+    ///
+    /// Push the messages that are passed on to `out`: the message, a changed message, or
+    /// others to inject them. Push nothing to drop the message. An error is yielded by
+    /// the stream instead of the messages.
+    fn inbound(&mut self, message: Message, out: &mut Vec<Message>) -> Result<(), SmcError> {
+        out.push(message);
+        Ok(())
+    }
+
+    /// Intercept a message that is sent.
+    ///
+    /// This works like [`Interceptor::inbound`]. An error is returned when the message
+    /// is sent, and none of the messages are sent.
+    fn outbound(&mut self, message: Message, out: &mut Vec<Message>) -> Result<(), SmcError> {
+        out.push(message);
+        Ok(())
+    }
+}
+
+/// A stream and sink of messages with a chain of [`Interceptor`]s.
+///
+/// This wraps a stream and sink of messages, usually a [`Channel`], see
+/// [`Channel::intercept`]. Messages that are read pass the interceptors in the order
+/// they were added, and messages that are sent pass them in reverse order, so the first
+/// interceptor is the closest to the transport.
+///
+/// # Example
+///
+/// ```
+/// use futures::prelude::*;
+/// use simple_message_channels::{pipe, Interceptor, Message, SmcError};
+///
+/// // Drop empty messages.
+/// struct DropEmpty;
+///
+/// impl Interceptor for DropEmpty {
+///     fn inbound(&mut self, message: Message, out: &mut Vec<Message>) -> Result<(), SmcError> {
+///         if !message.message.is_empty() {
+///             out.push(message);
+///         }
+///         Ok(())
+///     }
+/// }
+///
+/// # futures::executor::block_on(async {
+/// let (a, b) = pipe();
+/// let (mut a, mut b) = (a, b.intercept(DropEmpty));
+/// a.send(Message::new(1, 1, vec![])).await?;
+/// a.send(Message::new(1, 1, b"hello".to_vec())).await?;
+/// let message = b.next().await.unwrap()?;
+/// assert_eq!(message.message, b"hello");
+/// # Ok::<(), SmcError>(())
+/// # }).unwrap();
+/// ```
+pub struct Intercepted<S> {
+    inner: S,
+    chain: Vec<Box<dyn Interceptor>>,
+    // Messages that passed the chain, and are not yielded or sent yet.
+    inbound: VecDeque<Message>,
+    outbound: VecDeque<Message>,
+}
+
+impl<S> Intercepted<S> {
+    /// Wrap `inner`, with `interceptor` as the first interceptor of the chain.
+    pub fn new<I: Interceptor + 'static>(inner: S, interceptor: I) -> Self {
+        Self {
+            inner,
+            chain: vec![Box::new(interceptor)],
+            inbound: VecDeque::new(),
+            outbound: VecDeque::new(),
+        }
+    }
+
+    /// Add `interceptor` to the end of the chain.
+    pub fn with<I: Interceptor + 'static>(mut self, interceptor: I) -> Self {
+        self.chain.push(Box::new(interceptor));
+        self
+    }
+
+    /// Get a reference to the wrapped stream and sink.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the wrapped stream and sink.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    /// Unwrap the wrapped stream and sink.
+    ///
+    /// Messages that passed the chain but were not yielded or sent yet are dropped.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+// Pass `message` through `chain`, calling `intercept` with each interceptor.
+fn run<'a, F>(
+    chain: impl Iterator<Item = &'a mut Box<dyn Interceptor>>,
+    message: Message,
+    mut intercept: F,
+) -> Result<Vec<Message>, SmcError>
+where
+    F: FnMut(&mut dyn Interceptor, Message, &mut Vec<Message>) -> Result<(), SmcError>,
+{
+    let mut messages = vec![message];
+    for interceptor in chain {
+        let mut out = Vec::with_capacity(messages.len());
+        for message in messages {
+            intercept(interceptor.as_mut(), message, &mut out)?;
+        }
+        messages = out;
+    }
+    Ok(messages)
+}
+
+impl<S> Intercepted<S>
+where
+    S: Sink<Message, Error = SmcError> + Unpin,
+{
+    // Send the messages that passed the chain to the wrapped sink.
+    fn poll_outbound(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), SmcError>> {
+        while !self.outbound.is_empty() {
+            ready!(Pin::new(&mut self.inner).poll_ready(cx))?;
+            let message = self.outbound.pop_front().expect("not empty");
+            Pin::new(&mut self.inner).start_send(message)?;
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S> Stream for Intercepted<S>
+where
+    S: Stream<Item = Result<Message, SmcError>> + Unpin,
+{
+    type Item = Result<Message, SmcError>;
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Message, SmcError>>> {
+        let this = self.get_mut();
+        loop {
+            if let Some(message) = this.inbound.pop_front() {
+                return Poll::Ready(Some(Ok(message)));
+            }
+            match ready!(Pin::new(&mut this.inner).poll_next(cx)) {
+                Some(Ok(message)) => {
+                    let messages = run(this.chain.iter_mut(), message, |i, message, out| {
+                        i.inbound(message, out)
+                    });
+                    match messages {
+                        Ok(messages) => this.inbound.extend(messages),
+                        Err(error) => return Poll::Ready(Some(Err(error))),
+                    }
+                }
+                item => return Poll::Ready(item),
+            }
+        }
+    }
+}
+
+impl<S> Sink<Message> for Intercepted<S>
+where
+    S: Sink<Message, Error = SmcError> + Unpin,
+{
+    type Error = SmcError;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), SmcError>> {
+        self.get_mut().poll_outbound(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, message: Message) -> Result<(), SmcError> {
+        let this = self.get_mut();
+        let messages = run(this.chain.iter_mut().rev(), message, |i, message, out| {
+            i.outbound(message, out)
+        })?;
+        this.outbound.extend(messages);
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), SmcError>> {
+        let this = self.get_mut();
+        ready!(this.poll_outbound(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), SmcError>> {
+        let this = self.get_mut();
+        ready!(this.poll_outbound(cx))?;
+        Pin::new(&mut this.inner).poll_close(cx)
+    }
+}
+
+impl<T, C> Channel<T, C>
+where
+    C: FrameCipher,
+{
+    /// Pass the messages of the channel through `interceptor`, see [`Intercepted`].
+    ///
+    /// Add more interceptors to the chain with [`Intercepted::with`].
+    pub fn intercept<I: Interceptor + 'static>(self, interceptor: I) -> Intercepted<Self> {
+        Intercepted::new(self, interceptor)
+    }
+}
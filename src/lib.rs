@@ -8,18 +8,285 @@
 //!
 //! This module is a port of the JavaScript module [of the same
 //! name](https://github.com/mafintosh/simple-message-channels/).
+//!
+//! The reader and writer work with the [`futures::io`] traits, and don't depend on an async
+//! runtime. The IO types of async-std and smol can be used directly. With the `tokio`
+//! feature enabled, `TokioReader` and `TokioWriter` accept tokio IO types directly.
+//! Code that can't be async can use the [`sync`] reader and writer, or run a channel
+//! on its own thread with a [`blocking::Bridge`]. Custom transports implement the
+//! [`Transport`] trait, and are used through a [`TransportIo`]. [`pipe`] creates two
+//! connected channels in memory, e.g. for tests.
+//!
+//! With the `bytes` feature enabled, messages can be decoded into `bytes::Bytes` payloads
+//! without copying them out of the read buffer.
+//!
+//! With the `hypercore` feature enabled, `MessageType` names the message types of
+//! hypercore-protocol.
+//!
+//! With the `protocol` feature enabled, the `protocol` module opens and closes
+//! hypercore-protocol channels on top of a reader and a writer.
+//!
+//! With the `net` feature enabled, the `net` module connects and listens over TCP and
+//! Unix sockets and returns [`Channel`]s.
+//!
+//! With the `lz4` or `zstd` feature enabled, payloads can be compressed, see the
+//! `compression` module.
+//!
+//! With the `checksum` feature enabled, frames can carry a [`Checksum`] trailer.
+//!
+//! With the `ws` feature enabled, the `ws` module carries SMC over WebSocket.
+//!
+//! With the `quinn` feature enabled, the `quic` module carries SMC over QUIC, optionally
+//! with a stream per channel.
+//!
+//! With the `testing` feature enabled, the `testing` module records sessions and plays
+//! them back in tests.
+//!
+//! With the `sim` feature enabled, timeouts, deadlines, keepalives and rate limits can
+//! use another [`Clock`], e.g. a `SimClock` with virtual time, for reproducible tests.
+//!
+//! The [`debug`] module helps to inspect messages, e.g. with [`debug::hex_dump`] and
+//! [`debug::FrameTap`]. [`Interceptor`]s transform, drop or inject messages, see
+//! [`Channel::intercept`], e.g. a [`Remap`] that rewrites channel ids. [`relay`]
+//! forwards the messages between two channels, e.g. for a proxy.
+//! [`Channel::events`] is a stream of the [`ConnectionEvent`]s of a channel, e.g. when
+//! its handshake completed or the peer closed the connection.
+//! [`Channel::export_state`] exports the framing and cipher state of a channel as a
+//! [`SessionState`], to resume its session on another transport without a new handshake.
+//!
+//! Named extensions, which hypercore-protocol carries in messages of typ 15, can be
+//! handled with an [`Extensions`] registry.
+//!
+//! A [`Mux`] writes the messages of many channels to one writer, and a [`Demux`] routes
+//! the messages of a reader to a receiver per channel, with bounded queues.
+//!
+//! A [`Pool`] manages the [`Channel`]s of many peers, e.g. in a swarm, and merges their
+//! incoming messages into one stream.
+//!
+//! A [`MessagePool`] recycles the payload buffers of received messages, to avoid an
+//! allocation per message.
+//!
+//! Payloads that are larger than a frame can be split into fragments with
+//! [`Writer::with_fragmentation`], and reassembled with [`Reader::with_fragmentation`].
+//!
+//! [`FlowControl`] limits how many bytes the peer may send on a channel before the
+//! application consumed them.
+//!
+//! Typed messages can be sent and received through the [`TypedMessage`] trait. With the
+//! `serde` feature enabled, it is implemented for types that implement `SerdeMessage`.
+//!
+//! The stream of messages can be encrypted with a [`FrameCipher`], e.g. XSalsa20 with the
+//! `salsa20` feature or ChaCha20 with the `chacha20` feature. With the `rayon` feature
+//! enabled, a writer can encrypt large writes in parallel with a [`SeekableCipher`].
+//! Sending or receiving past the end of the keystream fails with
+//! [`SmcError::RekeyNeeded`], and a [`KeyedCipher`] can be replaced with new keys. The
+//! cipher can start after the first messages, see [`CipherStart`].
+//!
+//! The reader and writer need the `std` feature, which is enabled by default. Without it,
+//! the crate is `no_std` (but needs `alloc`), and provides the [`Message`] type and the
+//! encoding and decoding functions in [`codec`].
+//!
+//! [`RawReader`] and [`RawWriter`] read and write length-prefixed frames without the
+//! channel and typ header.
+//!
+//! To use SMC with other IO, [`Decoder`] decodes messages from chunks of bytes, and
+//! [`Encoder`] encodes messages into buffers.
+//! [`DatagramCodec`] encodes and decodes one message per datagram, e.g. for UDP.
+//! The [`varint`] module reads and writes the varints of the SMC encoding, e.g. for
+//! protocols that embed them in payloads.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
 
+#[cfg(feature = "std")]
+pub mod blocking;
+#[cfg(feature = "std")]
+mod channel;
+#[cfg(feature = "checksum")]
+mod checksum;
+mod cipher;
+#[cfg(feature = "std")]
+mod clock;
+pub mod codec;
+#[cfg(feature = "tokio")]
+mod compat;
+#[cfg(any(feature = "lz4", feature = "zstd"))]
+pub mod compression;
+pub mod debug;
+#[cfg(feature = "std")]
+mod demux;
+mod error;
+#[cfg(feature = "std")]
+mod events;
+#[cfg(feature = "std")]
+mod extensions;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "std")]
+mod flow;
+#[cfg(feature = "std")]
+mod fragment;
+#[cfg(feature = "hypercore")]
+mod hypercore;
+#[cfg(feature = "std")]
+mod intercept;
+#[cfg(feature = "std")]
+mod lifecycle;
 mod message;
+#[cfg(feature = "std")]
+mod mux;
+#[cfg(feature = "net")]
+pub mod net;
+#[cfg(feature = "std")]
+mod pipe;
+#[cfg(feature = "std")]
+mod pool;
+#[cfg(feature = "prost")]
+mod protobuf;
+#[cfg(feature = "protocol")]
+pub mod protocol;
+#[cfg(feature = "quinn")]
+pub mod quic;
+#[cfg(feature = "std")]
+mod rate;
+#[cfg(feature = "std")]
+mod raw;
+#[cfg(feature = "std")]
 mod reader;
+#[cfg(feature = "std")]
+mod recycle;
+#[cfg(feature = "std")]
+mod relay;
+#[cfg(feature = "std")]
+mod remap;
+#[cfg(feature = "std")]
+mod session;
+#[cfg(feature = "std")]
+mod source;
+#[cfg(feature = "std")]
+mod stats;
+#[cfg(feature = "std")]
+mod streaming;
+#[cfg(feature = "std")]
+pub mod sync;
+#[cfg(feature = "testing")]
+pub mod testing;
+#[cfg(feature = "std")]
+mod transport;
+mod typed;
+pub mod varint;
+#[cfg(feature = "std")]
 mod writer;
+#[cfg(feature = "ws")]
+pub mod ws;
 
-pub use message::Message;
-pub use reader::Reader;
-pub use writer::Writer;
+#[cfg(feature = "std")]
+pub use channel::{Channel, ChannelReader, ChannelWriter};
+#[cfg(feature = "checksum")]
+pub use checksum::Checksum;
+pub use cipher::{CipherStart, FrameCipher, KeyedCipher, NoCipher, SeekableCipher};
+#[cfg(feature = "sim")]
+pub use clock::SimClock;
+#[cfg(feature = "std")]
+pub use clock::{Clock, Sleep, SystemClock};
+pub use codec::{DatagramCodec, Decoder, Encoder};
+#[cfg(feature = "tokio")]
+pub use compat::{Compat, TokioReader, TokioWriter};
+#[cfg(feature = "std")]
+pub use demux::{ChannelReceiver, Demux, DemuxHandle, Overflow};
+pub use error::SmcError;
+#[cfg(feature = "std")]
+pub use events::{ChannelEvent, ChannelEvents, CLOSE_TYP};
+#[cfg(feature = "std")]
+pub use extensions::{ExtensionMessage, Extensions, EXTENSION_TYP};
+#[cfg(feature = "std")]
+pub use flow::{FlowControl, FlowStream, WINDOW_TYP};
+#[cfg(feature = "std")]
+pub use fragment::{FRAGMENT_TYP, MAX_FRAGMENT_LEN};
+#[cfg(feature = "hypercore")]
+pub use hypercore::MessageType;
+#[cfg(feature = "std")]
+pub use intercept::{Intercepted, Interceptor};
+#[cfg(feature = "std")]
+pub use lifecycle::{ConnectionEvent, ConnectionEvents};
+pub use message::{Message, MessageBuf, MessageHeader, Payload, Typ};
+#[cfg(feature = "std")]
+pub use mux::{ChannelSender, Mux, MuxHandle};
+#[cfg(feature = "std")]
+pub use pipe::{pipe, pipe_with_capacity, PipeStream};
+#[cfg(feature = "std")]
+pub use pool::{Pool, PoolEvent};
+#[cfg(feature = "std")]
+pub use raw::{RawReader, RawWriter};
+#[cfg(feature = "wasm")]
+pub use reader::LocalReader;
+#[cfg(feature = "std")]
+pub use reader::{Frame, Frames, OnError, Reader};
+#[cfg(feature = "std")]
+pub use recycle::MessagePool;
+#[cfg(feature = "std")]
+pub use relay::relay;
+#[cfg(feature = "std")]
+pub use remap::{Remap, Unmapped};
+#[cfg(feature = "std")]
+pub use session::SessionState;
+#[cfg(feature = "std")]
+pub use stats::{ChannelStats, Stats};
+#[cfg(feature = "std")]
+pub use streaming::{Body, StreamingReader};
+#[cfg(feature = "std")]
+pub use transport::{Transport, TransportIo};
+#[cfg(feature = "serde")]
+pub use typed::SerdeMessage;
+pub use typed::TypedMessage;
+pub use varint::VarintError;
+#[cfg(feature = "sim")]
+pub use writer::keepalives_with_clock;
+#[cfg(feature = "std")]
+pub use writer::{keepalives, CipherHandle, FlushPolicy, Priority, Writer, DEFAULT_MAX_BUFFERED};
 
 /// The max message size (in bytes)
 ///
 /// The limit is arbitrary, and taken from the JavaScript implementation.
 /// (see: https://github.com/mafintosh/simple-message-channels/blob/master/index.js)
 /// TODO: This should be configurable.
+///
+/// Decoders apply the limit to the length prefix of a frame, before they allocate
+/// anything, so a frame of exactly this length is read, and a longer one fails with
+/// [`SmcError::MessageTooLong`], however many bytes its length varint has. On targets
+/// where this does not fit into a `usize`, the limit is `usize::MAX`.
+///
+/// Encoders apply the limit to the whole frame, including its length prefix, see
+/// [`codec::encode_header_into`]. So they refuse the longest frames that decoders
+/// accept, whose body plus length prefix is up to 4 bytes longer than the limit.
+///
+/// ```
+/// use simple_message_channels::codec::decode_frame;
+/// use simple_message_channels::{varint, SmcError, MAX_MESSAGE_SIZE};
+///
+/// let mut frame = [0u8; varint::MAX_VARINT_LEN];
+/// let n = varint::encode(MAX_MESSAGE_SIZE, &mut frame);
+/// assert!(matches!(decode_frame(&frame[..n]), Ok(None)));
+///
+/// for len in [MAX_MESSAGE_SIZE + 1, 1 << 35, u64::MAX] {
+///     let n = varint::encode(len, &mut frame);
+///     assert!(matches!(decode_frame(&frame[..n]), Err(SmcError::MessageTooLong(l)) if l == len));
+/// }
+/// ```
 pub const MAX_MESSAGE_SIZE: u64 = 1024 * 1024 * 8;
+
+// The max length prefix of a frame that is decoded, so that it always fits into a
+// `usize`.
+pub(crate) const MAX_FRAME_LEN: u64 = if (usize::MAX as u64) < MAX_MESSAGE_SIZE {
+    usize::MAX as u64
+} else {
+    MAX_MESSAGE_SIZE
+};
+
+/// The max channel of a message that can be encoded.
+///
+/// The channel is encoded together with the typ in a single varint, so it can't use
+/// the top 4 bits. Encoding a message on a larger channel fails with
+/// [`SmcError::ChannelTooLarge`].
+pub const MAX_CHANNEL: u64 = u64::MAX >> 4;
@@ -0,0 +1,129 @@
+use futures::stream::Stream;
+use futures::task::{Context, Poll, Waker};
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::debug::Direction;
+use crate::SmcError;
+
+/// The number of events a [`ConnectionEvents`] stream buffers before it drops the
+/// oldest.
+const MAX_EVENTS: usize = 1024;
+
+/// A change of the state of a connection, see [`Channel::events`](crate::Channel::events).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnectionEvent {
+    /// The handshake of [`Channel::with_cipher_upgrade`](crate::Channel::with_cipher_upgrade)
+    /// completed.
+    HandshakeComplete,
+    /// The cipher of a direction was replaced, e.g. after the handshake. For the writer,
+    /// this is when the new cipher encrypts its first message.
+    CipherUpgraded(Direction),
+    /// The peer sent a keepalive.
+    KeepaliveReceived,
+    /// The peer closed its side of the connection, at the end of a frame.
+    RemoteClosed,
+    /// A frame could not be read or decoded. Contains the message of the error, which
+    /// the reader also yields.
+    DecodeError(String),
+    /// A channel of a [`Demux`](crate::Demux) had no messages for its idle timeout, and
+    /// its receiver ended, see [`Demux::with_idle_timeout`](crate::Demux::with_idle_timeout).
+    ChannelIdle(u64),
+    /// The writer was dropped with this many bytes that were not written, after the
+    /// transport failed.
+    UnsentDropped(usize),
+}
+
+struct Inner {
+    events: VecDeque<ConnectionEvent>,
+    waker: Option<Waker>,
+    senders: usize,
+}
+
+fn lock(inner: &Mutex<Inner>) -> MutexGuard<'_, Inner> {
+    inner.lock().expect("events lock poisoned")
+}
+
+/// Sends the events of a reader or a writer to a [`ConnectionEvents`] stream.
+pub(crate) struct EventSender {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl EventSender {
+    pub(crate) fn emit(&self, event: ConnectionEvent) {
+        let mut inner = lock(&self.inner);
+        if inner.events.len() == MAX_EVENTS {
+            inner.events.pop_front();
+        }
+        inner.events.push_back(event);
+        if let Some(waker) = inner.waker.take() {
+            waker.wake();
+        }
+    }
+
+    // Emit a `DecodeError` for errors other than IO errors of the transport.
+    pub(crate) fn emit_error(&self, error: &SmcError) {
+        if !matches!(error, SmcError::Io(_)) {
+            self.emit(ConnectionEvent::DecodeError(error.to_string()));
+        }
+    }
+}
+
+impl Clone for EventSender {
+    fn clone(&self) -> Self {
+        lock(&self.inner).senders += 1;
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl Drop for EventSender {
+    fn drop(&mut self) {
+        if let Ok(mut inner) = self.inner.lock() {
+            inner.senders -= 1;
+            if let Some(waker) = inner.waker.take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+/// A stream of [`ConnectionEvent`]s, see [`Channel::events`](crate::Channel::events).
+///
+/// The stream ends once the reader and the writer of the connection are dropped. If
+/// it is not polled, it keeps the last 1024 events.
+pub struct ConnectionEvents {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl ConnectionEvents {
+    pub(crate) fn new() -> (Self, EventSender) {
+        let inner = Arc::new(Mutex::new(Inner {
+            events: VecDeque::new(),
+            waker: None,
+            senders: 1,
+        }));
+        let sender = EventSender {
+            inner: inner.clone(),
+        };
+        (Self { inner }, sender)
+    }
+}
+
+impl Stream for ConnectionEvents {
+    type Item = ConnectionEvent;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<ConnectionEvent>> {
+        let mut inner = lock(&self.inner);
+        match inner.events.pop_front() {
+            Some(event) => Poll::Ready(Some(event)),
+            None if inner.senders == 0 => Poll::Ready(None),
+            None => {
+                inner.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
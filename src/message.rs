@@ -1,17 +1,121 @@
-use crate::MAX_MESSAGE_SIZE;
-use async_std::io::{Error, ErrorKind};
+use crate::codec::{decode_message, encode_header_into, encode_message, encode_message_into};
+use crate::SmcError;
+use alloc::vec::Vec;
+#[cfg(feature = "bytes")]
+use bytes::{Bytes, BytesMut};
+use core::convert::TryFrom;
 
 /// A SMC message.
-#[derive(Debug)]
-pub struct Message {
+///
+/// The payload is a `Vec<u8>` by default. With the `bytes` feature enabled, a message
+/// can also carry a [`bytes::Bytes`] payload, which shares the buffer it was decoded from.
+/// Messages are encoded from any payload that is `AsRef<[u8]>`, e.g. a slice with
+/// [`Message::borrowed`].
+///
+/// With the `serde` feature enabled, messages implement `Serialize` and `Deserialize`, e.g.
+/// to store them in test fixtures.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Message<B = Vec<u8>> {
     pub channel: u64,
     pub typ: u8,
-    pub message: Vec<u8>,
+    pub message: B,
 }
 
-impl Message {
+/// The header of a SMC message.
+///
+/// This is the channel and typ of a message, together with the length of its payload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MessageHeader {
+    pub channel: u64,
+    pub typ: u8,
+    pub len: u64,
+}
+
+/// The typ of a SMC message, which is in the range `0..=15`.
+///
+/// The wire format carries the typ in 4 bits, so a typ that is out of range can't be
+/// encoded.
+///
+/// The typs in [`Typ::RESERVED`] are used by the control messages of this crate, i.e.
+/// close messages, fragments and window updates. [`Typ::user`] creates typs for the messages of an
+/// application, which don't collide with them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Typ(u8);
+
+impl Typ {
+    /// The largest typ.
+    pub const MAX: Typ = Typ(15);
+
+    /// The typ of messages that close a channel, see `CLOSE_TYP`.
+    pub const CLOSE: Typ = Typ(10);
+
+    /// The typ of the fragments of split messages, see `FRAGMENT_TYP`.
+    pub const FRAGMENT: Typ = Typ(13);
+
+    /// The typ of window updates, see `WINDOW_TYP`.
+    pub const WINDOW: Typ = Typ(14);
+
+    /// The typs of the control messages of this crate.
+    pub const RESERVED: [Typ; 3] = [Typ::CLOSE, Typ::FRAGMENT, Typ::WINDOW];
+
+    /// Create a typ for application messages, or `None` if `typ` is reserved or larger
+    /// than [`Typ::MAX`].
+    pub const fn user(typ: u8) -> Option<Typ> {
+        match Typ::new(typ) {
+            Some(typ) if !typ.is_reserved() => Some(typ),
+            _ => None,
+        }
+    }
+
+    /// Returns `true` if this typ is in [`Typ::RESERVED`].
+    pub const fn is_reserved(self) -> bool {
+        let mut i = 0;
+        while i < Typ::RESERVED.len() {
+            if Typ::RESERVED[i].0 == self.0 {
+                return true;
+            }
+            i += 1;
+        }
+        false
+    }
+
+    /// Create a typ, or `None` if `typ` is larger than [`Typ::MAX`].
+    pub const fn new(typ: u8) -> Option<Typ> {
+        if typ <= Typ::MAX.0 {
+            Some(Typ(typ))
+        } else {
+            None
+        }
+    }
+
+    /// The typ as a number.
+    pub const fn get(self) -> u8 {
+        self.0
+    }
+}
+
+impl TryFrom<u8> for Typ {
+    type Error = SmcError;
+    fn try_from(typ: u8) -> Result<Self, SmcError> {
+        Typ::new(typ).ok_or(SmcError::InvalidTyp(typ))
+    }
+}
+
+impl From<Typ> for u8 {
+    fn from(typ: Typ) -> u8 {
+        typ.0
+    }
+}
+
+impl<B> Message<B> {
     /// Create a new message.
-    pub fn new(channel: u64, typ: u8, message: Vec<u8>) -> Message {
+    ///
+    /// The typ is not checked. Encoding a message with a typ larger than [`Typ::MAX`]
+    /// fails with [`SmcError::InvalidTyp`]. See [`Message::try_new`] for a checked
+    /// variant.
+    pub fn new(channel: u64, typ: u8, message: B) -> Message<B> {
         Message {
             channel,
             typ,
@@ -19,58 +123,179 @@ impl Message {
         }
     }
 
+    /// Create a new message, checking the typ.
+    ///
+    /// Fails with [`SmcError::InvalidTyp`] if the typ is larger than [`Typ::MAX`].
+    pub fn try_new(channel: u64, typ: u8, message: B) -> Result<Message<B>, SmcError> {
+        let typ = Typ::try_from(typ)?;
+        Ok(Message::with_typ(channel, typ, message))
+    }
+
+    /// Create a new message with a [`Typ`], which is always in range.
+    pub fn with_typ(channel: u64, typ: Typ, message: B) -> Message<B> {
+        Message::new(channel, typ.get(), message)
+    }
+}
+
+impl<B: Default> Message<B> {
+    /// Create a keepalive message.
+    ///
+    /// Keepalives are encoded as empty frames, without channel and typ. For this
+    /// reason, they use a channel that can't be encoded otherwise.
+    pub fn keepalive() -> Message<B> {
+        Message::new(KEEPALIVE_CHANNEL, 0, B::default())
+    }
+}
+
+impl Message {
     /// Decode a message from `buf` (bytes).
     ///
     /// Note: `buf` has to have a valid length, and the length
     /// prefix has to be removed already.
-    pub fn from_buf(buf: &[u8]) -> Result<Message, Error> {
+    pub fn from_buf(buf: &[u8]) -> Result<Message, SmcError> {
         decode_message(buf)
     }
+}
 
+impl<'a> Message<&'a [u8]> {
+    /// Create a message that borrows its payload.
+    ///
+    /// The writers accept such messages, so a payload can be sent without copying it
+    /// into a `Vec` first, e.g. from a memory-mapped file.
+    pub fn borrowed(channel: u64, typ: u8, message: &'a [u8]) -> Self {
+        Message::new(channel, typ, message)
+    }
+
+    /// Copy the payload into a `Vec`.
+    pub fn into_owned(self) -> Message {
+        Message::new(self.channel, self.typ, self.message.to_vec())
+    }
+}
+
+#[cfg(feature = "bytes")]
+impl Message<Bytes> {
+    /// Decode a message from `buf` without copying the payload.
+    ///
+    /// The payload of the returned message is a slice of `buf`.
+    /// Note: `buf` has to have a valid length, and the length
+    /// prefix has to be removed already.
+    pub fn from_bytes(buf: Bytes) -> Result<Message<Bytes>, SmcError> {
+        crate::codec::decode_bytes(buf)
+    }
+}
+
+impl<B> Message<B>
+where
+    B: AsRef<[u8]>,
+{
     /// Encode a message body into a buffer.
     ///
     /// The result can be sent directly over any medium.
     /// It is length-prefixed, so chunking should not be an issue.
-    pub fn encode(&self) -> Result<Vec<u8>, Error> {
+    pub fn encode(&self) -> Result<Vec<u8>, SmcError> {
         encode_message(self)
     }
+
+    /// Encode a message body and append it to `buf`.
+    ///
+    /// This is useful to encode several messages into a single buffer.
+    pub fn encode_into(&self, buf: &mut Vec<u8>) -> Result<(), SmcError> {
+        encode_message_into(self, buf)
+    }
+
+    /// Encode the header of a message into `buf`, returning the length of the header.
+    ///
+    /// See [`codec::encode_header_into`](crate::codec::encode_header_into).
+    pub fn encode_header_into(&self, buf: &mut [u8]) -> Result<usize, SmcError> {
+        encode_header_into(self, buf)
+    }
+
+    /// Borrow the payload of the message.
+    pub fn as_borrowed(&self) -> Message<&[u8]> {
+        Message::borrowed(self.channel, self.typ, self.message.as_ref())
+    }
+
+    /// Returns `true` if this is a keepalive message.
+    ///
+    /// See [`Message::keepalive`].
+    pub fn is_keepalive(&self) -> bool {
+        self.channel == KEEPALIVE_CHANNEL && self.typ == 0 && self.message.as_ref().is_empty()
+    }
 }
 
-/// Decode a message from `buf` (bytes).
+/// The channel of keepalive messages.
+const KEEPALIVE_CHANNEL: u64 = u64::MAX;
+
+/// A payload type that messages can be decoded into.
 ///
-/// Note: `buf` has to have a valid length, and the length prefixed
-/// has to be removed already.
-pub fn decode_message(buf: &[u8]) -> Result<Message, Error> {
-    let mut header = 0 as u64;
-    let headerlen = varinteger::decode(buf, &mut header);
-    let msg = &buf[headerlen..];
-    let channel = header >> 4;
-    let typ = header & 0b1111;
-    let message = Message {
-        channel: channel,
-        typ: typ as u8,
-        message: msg.to_vec(),
-    };
-    Ok(message)
-}
-
-/// Encode a message body into a buffer.
-pub fn encode_message(msg: &Message) -> Result<Vec<u8>, Error> {
-    let header = msg.channel << 4 | msg.typ as u64;
-    let len_header = varinteger::length(header);
-    let len_body = msg.message.len() + len_header;
-    let len_prefix = varinteger::length(len_body as u64);
-    let len = len_body + len_prefix;
-
-    if len as u64 > MAX_MESSAGE_SIZE {
-        return Err(Error::new(ErrorKind::InvalidInput, "Message too long"));
-    }
-
-    let mut buf = vec![0; len];
-
-    varinteger::encode(len_body as u64, &mut buf[..len_prefix]);
-    let end = len_prefix + len_header;
-    varinteger::encode(header, &mut buf[len_prefix..end]);
-    &mut buf[end..].copy_from_slice(&msg.message);
-    Ok(buf)
+/// This is implemented for `Vec<u8>`, and for [`bytes::Bytes`] if the
+/// `bytes` feature is enabled.
+pub trait Payload: Default {
+    /// Take the payload out of a frame buffer, starting at `offset`.
+    fn from_frame(buf: Vec<u8>, offset: usize) -> Self;
+
+    /// Copy the payload out of `buf`.
+    fn from_slice(buf: &[u8]) -> Self;
+
+    /// Copy the payload out of `buf`, into a buffer taken from `pool` if the payload
+    /// type can use it. Defaults to [`Payload::from_slice`].
+    #[cfg(feature = "std")]
+    fn from_pool(buf: &[u8], pool: &crate::MessagePool) -> Self {
+        let _ = pool;
+        Self::from_slice(buf)
+    }
+}
+
+impl Payload for Vec<u8> {
+    fn from_frame(mut buf: Vec<u8>, offset: usize) -> Self {
+        buf.drain(..offset);
+        buf
+    }
+
+    fn from_slice(buf: &[u8]) -> Self {
+        buf.to_vec()
+    }
+
+    #[cfg(feature = "std")]
+    fn from_pool(buf: &[u8], pool: &crate::MessagePool) -> Self {
+        let mut payload = pool.take(buf.len());
+        payload.extend_from_slice(buf);
+        payload
+    }
+}
+
+#[cfg(feature = "bytes")]
+impl Payload for Bytes {
+    fn from_frame(buf: Vec<u8>, offset: usize) -> Self {
+        Bytes::from(buf).slice(offset..)
+    }
+
+    fn from_slice(buf: &[u8]) -> Self {
+        Bytes::copy_from_slice(buf)
+    }
+}
+
+/// A buffer the caller provides to read a payload into, see
+/// [`Reader::next_into`](crate::Reader::next_into).
+///
+/// This keeps the allocation of payloads with the caller, e.g. buffers from an arena
+/// or a pool that are reused for every message.
+pub trait MessageBuf {
+    /// Replace the contents of the buffer with `payload`.
+    fn fill(&mut self, payload: &[u8]);
+}
+
+impl MessageBuf for Vec<u8> {
+    fn fill(&mut self, payload: &[u8]) {
+        self.clear();
+        self.extend_from_slice(payload);
+    }
+}
+
+#[cfg(feature = "bytes")]
+impl MessageBuf for BytesMut {
+    fn fill(&mut self, payload: &[u8]) {
+        self.clear();
+        self.extend_from_slice(payload);
+    }
 }
@@ -0,0 +1,213 @@
+use futures::channel::mpsc;
+use futures::future::Future;
+use futures::io::AsyncWrite;
+use futures::ready;
+use futures::sink::{Sink, SinkExt};
+use futures::stream::StreamExt;
+use futures::task::{Context, Poll};
+use std::io::{Error, ErrorKind};
+use std::pin::Pin;
+
+use crate::{FlowControl, Message, Priority, SmcError, Writer};
+
+/// The number of messages a [`ChannelSender`] queues before waiting for the [`Mux`].
+const SENDER_CAPACITY: usize = 16;
+
+/// A multiplexer that writes messages of many channels to a single [`Writer`].
+///
+/// Messages are sent through [`ChannelSender`]s, which are created with a [`MuxHandle`].
+/// The mux takes one message from each sender in turn, so that a single busy channel
+/// can't starve the others. Messages of senders with a higher [`Priority`] are written
+/// before queued messages of senders with a lower priority.
+///
+/// The mux is a future that has to be polled (e.g. spawned on a task) to write the
+/// messages. It completes once the [`MuxHandle`] and all senders are dropped and all
+/// messages are written.
+///
+/// # Example
+///
+/// ```no_run
+/// # use async_std::{io, task};
+/// use simple_message_channels::{Mux, Writer};
+/// # task::block_on(async {
+/// let (mux, handle) = Mux::new(Writer::new(io::stdout()));
+/// let mux = task::spawn(mux);
+/// let mut sender = handle.sender(1);
+/// sender.send(0, b"hello".to_vec()).await?;
+/// drop((sender, handle));
+/// mux.await?;
+/// # io::Result::Ok(())
+/// # });
+/// ```
+pub struct Mux<W> {
+    writer: Writer<W>,
+    senders: mpsc::UnboundedReceiver<(mpsc::Receiver<Message>, Priority)>,
+    senders_closed: bool,
+    queues: Vec<Queue>,
+    next: usize,
+    flow: Option<FlowControl>,
+}
+
+struct Queue {
+    receiver: mpsc::Receiver<Message>,
+    priority: Priority,
+    // A message that waits for credit.
+    stalled: Option<Message>,
+}
+
+impl<W> Mux<W>
+where
+    W: AsyncWrite + Unpin,
+{
+    /// Create a new mux that writes to `writer`.
+    ///
+    /// Returns the mux and a handle to create senders with.
+    pub fn new(writer: Writer<W>) -> (Self, MuxHandle) {
+        let (sender, senders) = mpsc::unbounded();
+        let mux = Self {
+            writer,
+            senders,
+            senders_closed: false,
+            queues: Vec::new(),
+            next: 0,
+            flow: None,
+        };
+        (mux, MuxHandle { senders: sender })
+    }
+
+    /// Apply `flow` control to the sent messages.
+    ///
+    /// The mux sends the window updates of `flow`, and stops taking messages from a
+    /// sender once its channel has no credit left. See [`FlowControl`].
+    pub fn with_flow_control(mut self, flow: FlowControl) -> Self {
+        self.flow = Some(flow);
+        self
+    }
+
+    fn poll_senders(&mut self, cx: &mut Context<'_>) {
+        while !self.senders_closed {
+            match self.senders.poll_next_unpin(cx) {
+                Poll::Ready(Some((receiver, priority))) => self.queues.push(Queue {
+                    receiver,
+                    priority,
+                    stalled: None,
+                }),
+                Poll::Ready(None) => self.senders_closed = true,
+                Poll::Pending => break,
+            }
+        }
+    }
+
+    // Take the next message, visiting the queues in turn.
+    fn poll_queues(&mut self, cx: &mut Context<'_>) -> Poll<Option<(Message, Priority)>> {
+        let mut visited = 0;
+        while visited < self.queues.len() {
+            let i = self.next % self.queues.len();
+            let queue = &mut self.queues[i];
+            let next = match queue.stalled.take() {
+                Some(message) => Poll::Ready(Some(message)),
+                None => queue.receiver.poll_next_unpin(cx),
+            };
+            match next {
+                Poll::Ready(Some(message)) => {
+                    self.next = i + 1;
+                    match &self.flow {
+                        Some(flow) if !flow.take_credit(&message) => {
+                            queue.stalled = Some(message);
+                            visited += 1;
+                        }
+                        _ => return Poll::Ready(Some((message, queue.priority))),
+                    }
+                }
+                Poll::Ready(None) => {
+                    self.queues.remove(i);
+                    self.next = i;
+                }
+                Poll::Pending => {
+                    self.next = i + 1;
+                    visited += 1;
+                }
+            }
+        }
+        if self.queues.is_empty() && self.senders_closed {
+            Poll::Ready(None)
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+impl<W> Future for Mux<W>
+where
+    W: AsyncWrite + Unpin,
+{
+    type Output = Result<(), SmcError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        loop {
+            this.poll_senders(cx);
+            ready!(Sink::<Message>::poll_ready(Pin::new(&mut this.writer), cx))?;
+            if let Some(flow) = &this.flow {
+                for grant in flow.poll_grants(cx) {
+                    this.writer.queue(grant, Priority::High)?;
+                }
+            }
+            match this.poll_queues(cx) {
+                Poll::Ready(Some((message, priority))) => this.writer.queue(message, priority)?,
+                Poll::Ready(None) => {
+                    return Sink::<Message>::poll_flush(Pin::new(&mut this.writer), cx)
+                }
+                Poll::Pending => {
+                    ready!(Sink::<Message>::poll_flush(Pin::new(&mut this.writer), cx))?;
+                    return Poll::Pending;
+                }
+            }
+        }
+    }
+}
+
+/// A handle to create [`ChannelSender`]s for a [`Mux`].
+#[derive(Clone)]
+pub struct MuxHandle {
+    senders: mpsc::UnboundedSender<(mpsc::Receiver<Message>, Priority)>,
+}
+
+impl MuxHandle {
+    /// Create a sender for messages on `channel`.
+    pub fn sender(&self, channel: u64) -> ChannelSender {
+        self.sender_with_priority(channel, Priority::Normal)
+    }
+
+    /// Create a sender for messages on `channel` with `priority`.
+    pub fn sender_with_priority(&self, channel: u64, priority: Priority) -> ChannelSender {
+        let (sender, queue) = mpsc::channel(SENDER_CAPACITY);
+        // If the mux is gone, sending on the sender fails.
+        let _ = self.senders.unbounded_send((queue, priority));
+        ChannelSender { channel, sender }
+    }
+}
+
+/// Sends messages on a single channel through a [`Mux`].
+pub struct ChannelSender {
+    channel: u64,
+    sender: mpsc::Sender<Message>,
+}
+
+impl ChannelSender {
+    /// The channel this sender sends messages on.
+    pub fn channel(&self) -> u64 {
+        self.channel
+    }
+
+    /// Send a message with type `typ`.
+    ///
+    /// This waits until the message is queued in the mux, not until it is written.
+    pub async fn send(&mut self, typ: u8, message: Vec<u8>) -> Result<(), SmcError> {
+        let message = Message::new(self.channel, typ, message);
+        self.sender
+            .send(message)
+            .await
+            .map_err(|_| Error::new(ErrorKind::BrokenPipe, "Mux closed").into())
+    }
+}
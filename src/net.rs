@@ -0,0 +1,75 @@
+//! TCP and Unix socket connectors that return [`Channel`]s.
+//!
+//! Enabled with the `net` feature. The sockets are from [`async_net`], which does
+//! not need a specific runtime.
+
+use async_net::AsyncToSocketAddrs;
+use std::io;
+use std::net::SocketAddr;
+
+use crate::Channel;
+
+#[cfg(unix)]
+pub use async_net::unix::UnixStream;
+pub use async_net::TcpStream;
+
+/// Connect to `addr` over TCP.
+pub async fn connect_tcp<A: AsyncToSocketAddrs>(addr: A) -> io::Result<Channel<TcpStream>> {
+    let stream = TcpStream::connect(addr).await?;
+    Ok(Channel::new(stream))
+}
+
+/// Listen for TCP connections on `addr`.
+pub async fn listen_tcp<A: AsyncToSocketAddrs>(addr: A) -> io::Result<TcpListener> {
+    let listener = async_net::TcpListener::bind(addr).await?;
+    Ok(TcpListener { listener })
+}
+
+/// A TCP listener that accepts [`Channel`]s.
+#[derive(Debug)]
+pub struct TcpListener {
+    listener: async_net::TcpListener,
+}
+
+impl TcpListener {
+    /// Accept the next connection.
+    pub async fn accept(&self) -> io::Result<(Channel<TcpStream>, SocketAddr)> {
+        let (stream, addr) = self.listener.accept().await?;
+        Ok((Channel::new(stream), addr))
+    }
+
+    /// The address the listener is bound to.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+}
+
+/// Connect to the Unix socket at `path`.
+#[cfg(unix)]
+pub async fn connect_unix<P: AsRef<std::path::Path>>(path: P) -> io::Result<Channel<UnixStream>> {
+    let stream = UnixStream::connect(path).await?;
+    Ok(Channel::new(stream))
+}
+
+/// Listen for connections on the Unix socket at `path`.
+#[cfg(unix)]
+pub fn listen_unix<P: AsRef<std::path::Path>>(path: P) -> io::Result<UnixListener> {
+    let listener = async_net::unix::UnixListener::bind(path)?;
+    Ok(UnixListener { listener })
+}
+
+/// A Unix socket listener that accepts [`Channel`]s.
+#[cfg(unix)]
+#[derive(Debug)]
+pub struct UnixListener {
+    listener: async_net::unix::UnixListener,
+}
+
+#[cfg(unix)]
+impl UnixListener {
+    /// Accept the next connection.
+    pub async fn accept(&self) -> io::Result<Channel<UnixStream>> {
+        let (stream, _addr) = self.listener.accept().await?;
+        Ok(Channel::new(stream))
+    }
+}
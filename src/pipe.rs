@@ -0,0 +1,149 @@
+use futures::io::{AsyncRead, AsyncWrite};
+use futures::task::{Context, Poll, Waker};
+use std::collections::VecDeque;
+use std::io::{Error, ErrorKind, Result};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+
+use crate::Channel;
+
+/// The number of bytes each direction of a [`pipe`] buffers.
+const DEFAULT_CAPACITY: usize = 64 * 1024;
+
+/// Create two connected [`Channel`]s, backed by in-memory buffers.
+///
+/// Messages sent on one channel are received on the other, without a socket. Use this
+/// in tests, or to talk SMC between components of the same process. Each direction
+/// buffers up to 64 KiB, see [`pipe_with_capacity`].
+pub fn pipe() -> (Channel<PipeStream>, Channel<PipeStream>) {
+    pipe_with_capacity(DEFAULT_CAPACITY)
+}
+
+/// Create two connected [`Channel`]s, which buffer up to `capacity` bytes in each
+/// direction.
+///
+/// A writer waits while the buffer is full, until the other side reads from it.
+pub fn pipe_with_capacity(capacity: usize) -> (Channel<PipeStream>, Channel<PipeStream>) {
+    let (a, b) = PipeStream::pair(capacity);
+    (Channel::new(a), Channel::new(b))
+}
+
+/// One direction of a pipe.
+struct Ring {
+    buf: VecDeque<u8>,
+    capacity: usize,
+    // The writing side closed or was dropped.
+    closed: bool,
+    // The reading side was dropped.
+    reader_gone: bool,
+    read_waker: Option<Waker>,
+    write_waker: Option<Waker>,
+}
+
+impl Ring {
+    fn new(capacity: usize) -> Arc<Mutex<Self>> {
+        Arc::new(Mutex::new(Self {
+            buf: VecDeque::new(),
+            capacity: capacity.max(1),
+            closed: false,
+            reader_gone: false,
+            read_waker: None,
+            write_waker: None,
+        }))
+    }
+}
+
+fn wake(waker: &mut Option<Waker>) {
+    if let Some(waker) = waker.take() {
+        waker.wake();
+    }
+}
+
+/// One end of an in-memory duplex byte stream, see [`pipe`].
+///
+/// Once one end is closed or dropped, the other reads the end of the stream after the
+/// buffered bytes. Writing to an end whose other end is dropped fails with
+/// [`std::io::ErrorKind::BrokenPipe`].
+pub struct PipeStream {
+    read: Arc<Mutex<Ring>>,
+    write: Arc<Mutex<Ring>>,
+}
+
+impl PipeStream {
+    /// Create the two connected ends of a pipe, which buffer up to `capacity` bytes in
+    /// each direction.
+    pub fn pair(capacity: usize) -> (PipeStream, PipeStream) {
+        let (a, b) = (Ring::new(capacity), Ring::new(capacity));
+        let first = PipeStream {
+            read: a.clone(),
+            write: b.clone(),
+        };
+        let second = PipeStream { read: b, write: a };
+        (first, second)
+    }
+}
+
+impl AsyncRead for PipeStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize>> {
+        let mut ring = self.read.lock().expect("pipe lock poisoned");
+        if ring.buf.is_empty() && !buf.is_empty() {
+            if ring.closed {
+                return Poll::Ready(Ok(0));
+            }
+            ring.read_waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+        let n = buf.len().min(ring.buf.len());
+        for (byte, read) in buf.iter_mut().zip(ring.buf.drain(..n)) {
+            *byte = read;
+        }
+        wake(&mut ring.write_waker);
+        Poll::Ready(Ok(n))
+    }
+}
+
+impl AsyncWrite for PipeStream {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+        let mut ring = self.write.lock().expect("pipe lock poisoned");
+        if ring.reader_gone || ring.closed {
+            return Poll::Ready(Err(Error::new(ErrorKind::BrokenPipe, "Pipe closed")));
+        }
+        let free = ring.capacity - ring.buf.len();
+        if free == 0 && !buf.is_empty() {
+            ring.write_waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+        let n = buf.len().min(free);
+        ring.buf.extend(&buf[..n]);
+        wake(&mut ring.read_waker);
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+        let mut ring = self.write.lock().expect("pipe lock poisoned");
+        ring.closed = true;
+        wake(&mut ring.read_waker);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl Drop for PipeStream {
+    fn drop(&mut self) {
+        if let Ok(mut ring) = self.write.lock() {
+            ring.closed = true;
+            wake(&mut ring.read_waker);
+        }
+        if let Ok(mut ring) = self.read.lock() {
+            ring.reader_gone = true;
+            wake(&mut ring.write_waker);
+        }
+    }
+}
@@ -0,0 +1,196 @@
+use futures::io::{AsyncRead, AsyncWrite};
+use futures::stream::{Stream, StreamExt};
+use futures::task::{Context, Poll, Waker};
+use std::io::{Error, ErrorKind};
+use std::pin::Pin;
+
+use crate::{Channel, FrameCipher, Message, NoCipher, SmcError};
+
+/// An event of a [`Pool`].
+#[derive(Debug)]
+pub enum PoolEvent<P> {
+    /// A message was received from a peer.
+    Message(P, Message),
+    /// Reading from the connection of a peer failed.
+    ///
+    /// Whether the connection keeps working depends on the [`OnError`](crate::OnError)
+    /// of its reader. If it does not, it is disconnected next.
+    Error(P, SmcError),
+    /// The connection of a peer ended, and was removed from the pool.
+    Disconnected(P),
+}
+
+/// Many [`Channel`]s, keyed by the id of their peer.
+///
+/// The pool routes outgoing messages to the connection of a peer with [`Pool::send`],
+/// and is a [`futures::stream::Stream`] of the [`PoolEvent`]s of all connections. The
+/// connections are read in turn, so that a single busy peer can't starve the others.
+/// A connection that fails or ends is removed, and the others keep working.
+///
+/// The pool stream never ends. If it has no connections, it waits until one is
+/// inserted.
+///
+/// # Example
+///
+/// ```no_run
+/// # use async_std::{io, net::TcpStream};
+/// use futures::stream::StreamExt;
+/// use simple_message_channels::{Channel, Message, Pool, PoolEvent};
+/// # async_std::task::block_on(async {
+/// let mut pool = Pool::new();
+/// pool.insert("a", Channel::new(TcpStream::connect("127.0.0.1:8000").await?));
+/// pool.insert("b", Channel::new(TcpStream::connect("127.0.0.1:8001").await?));
+/// pool.send(&"a", Message::new(1, 0, b"hello".to_vec())).await?;
+/// while let Some(event) = pool.next().await {
+///     match event {
+///         PoolEvent::Message(peer, message) => println!("{}: {:?}", peer, message),
+///         PoolEvent::Error(peer, error) => eprintln!("{}: {}", peer, error),
+///         PoolEvent::Disconnected(peer) => eprintln!("{} disconnected", peer),
+///     }
+/// }
+/// # io::Result::Ok(())
+/// # });
+/// ```
+pub struct Pool<P, T, C = NoCipher> {
+    connections: Vec<(P, Channel<T, C>)>,
+    next: usize,
+    // The task that waits for events, woken when a connection is inserted.
+    waker: Option<Waker>,
+}
+
+impl<P, T, C> Pool<P, T, C>
+where
+    P: Clone + Eq,
+    T: AsyncRead + AsyncWrite + Unpin,
+    C: FrameCipher + Unpin,
+{
+    /// Create a new, empty pool.
+    pub fn new() -> Self {
+        Self {
+            connections: Vec::new(),
+            next: 0,
+            waker: None,
+        }
+    }
+
+    /// Add the connection of `peer`.
+    ///
+    /// If the pool already has a connection of `peer`, it is replaced and returned.
+    pub fn insert(&mut self, peer: P, channel: Channel<T, C>) -> Option<Channel<T, C>> {
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+        match self.get_mut(&peer) {
+            Some(old) => Some(std::mem::replace(old, channel)),
+            None => {
+                self.connections.push((peer, channel));
+                None
+            }
+        }
+    }
+
+    /// Remove the connection of `peer`, and return it.
+    pub fn remove(&mut self, peer: &P) -> Option<Channel<T, C>> {
+        let i = self.connections.iter().position(|(p, _)| p == peer)?;
+        Some(self.connections.remove(i).1)
+    }
+
+    /// Get a mutable reference to the connection of `peer`.
+    pub fn get_mut(&mut self, peer: &P) -> Option<&mut Channel<T, C>> {
+        self.connections
+            .iter_mut()
+            .find(|(p, _)| p == peer)
+            .map(|(_, channel)| channel)
+    }
+
+    /// Returns `true` if the pool has a connection of `peer`.
+    pub fn contains(&self, peer: &P) -> bool {
+        self.connections.iter().any(|(p, _)| p == peer)
+    }
+
+    /// The ids of the peers in the pool.
+    pub fn peers(&self) -> impl Iterator<Item = &P> {
+        self.connections.iter().map(|(peer, _)| peer)
+    }
+
+    /// The number of connections in the pool.
+    pub fn len(&self) -> usize {
+        self.connections.len()
+    }
+
+    /// Returns `true` if the pool has no connections.
+    pub fn is_empty(&self) -> bool {
+        self.connections.is_empty()
+    }
+
+    /// Send a message to `peer`, on the channel of the message.
+    ///
+    /// Fails with an error of kind [`std::io::ErrorKind::NotConnected`] if the pool has
+    /// no connection of `peer`. If writing to the connection fails, it is removed from
+    /// the pool, and the error is returned.
+    pub async fn send<B: AsRef<[u8]>>(
+        &mut self,
+        peer: &P,
+        message: Message<B>,
+    ) -> Result<(), SmcError> {
+        let channel = match self.get_mut(peer) {
+            Some(channel) => channel,
+            None => return Err(Error::new(ErrorKind::NotConnected, "Unknown peer").into()),
+        };
+        let result = channel.send(message).await;
+        if result.is_err() {
+            self.remove(peer);
+        }
+        result
+    }
+}
+
+impl<P, T, C> Default for Pool<P, T, C>
+where
+    P: Clone + Eq,
+    T: AsyncRead + AsyncWrite + Unpin,
+    C: FrameCipher + Unpin,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<P, T, C> Stream for Pool<P, T, C>
+where
+    P: Clone + Eq + Unpin,
+    T: AsyncRead + AsyncWrite + Unpin,
+    C: FrameCipher + Unpin,
+{
+    type Item = PoolEvent<P>;
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<PoolEvent<P>>> {
+        let this = self.get_mut();
+        // Visit the connections in turn.
+        let mut visited = 0;
+        while visited < this.connections.len() {
+            let i = this.next % this.connections.len();
+            let (peer, channel) = &mut this.connections[i];
+            match channel.poll_next_unpin(cx) {
+                Poll::Ready(Some(Ok(message))) => {
+                    this.next = i + 1;
+                    return Poll::Ready(Some(PoolEvent::Message(peer.clone(), message)));
+                }
+                Poll::Ready(Some(Err(error))) => {
+                    this.next = i + 1;
+                    return Poll::Ready(Some(PoolEvent::Error(peer.clone(), error)));
+                }
+                Poll::Ready(None) => {
+                    let (peer, _) = this.connections.remove(i);
+                    this.next = i;
+                    return Poll::Ready(Some(PoolEvent::Disconnected(peer)));
+                }
+                Poll::Pending => {
+                    this.next = i + 1;
+                    visited += 1;
+                }
+            }
+        }
+        this.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
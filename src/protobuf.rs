@@ -0,0 +1,42 @@
+//! Protobuf payloads.
+//!
+//! Enabled with the `prost` feature.
+
+use futures::io::AsyncWrite;
+
+use crate::{FrameCipher, Message, SmcError, Writer};
+
+impl Message {
+    /// Create a new message with a protobuf encoded payload.
+    pub fn from_protobuf<T: prost::Message>(channel: u64, typ: u8, value: &T) -> Message {
+        Message::new(channel, typ, value.encode_to_vec())
+    }
+}
+
+impl<B> Message<B>
+where
+    B: AsRef<[u8]>,
+{
+    /// Decode the payload of this message as protobuf.
+    pub fn decode_protobuf<T: prost::Message + Default>(&self) -> Result<T, SmcError> {
+        T::decode(self.message.as_ref()).map_err(|error| SmcError::Payload(Box::new(error)))
+    }
+}
+
+impl<W, C> Writer<W, C>
+where
+    W: AsyncWrite + Unpin,
+    C: FrameCipher,
+{
+    /// Send a message with a protobuf encoded payload.
+    ///
+    /// See [`Writer::send`].
+    pub async fn send_protobuf<T: prost::Message>(
+        &mut self,
+        channel: u64,
+        typ: u8,
+        value: &T,
+    ) -> Result<(), SmcError> {
+        self.send(Message::from_protobuf(channel, typ, value)).await
+    }
+}
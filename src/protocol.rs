@@ -0,0 +1,455 @@
+//! A connection-level state machine for hypercore-protocol.
+//!
+//! A [`ChannelMap`] keeps the ids of the channels of a connection by discovery key, for
+//! replication code that doesn't use a [`Protocol`].
+//!
+//! Enabled with the `protocol` feature.
+
+use blake2::digest::consts::U32;
+use blake2::digest::Mac;
+use blake2::Blake2bMac;
+use futures::io::{AsyncRead, AsyncWrite};
+use futures::stream::StreamExt;
+use std::collections::{HashMap, VecDeque};
+use std::convert::TryInto;
+use std::io::{Error, ErrorKind};
+
+use crate::{FrameCipher, Message, MessageType, NoCipher, Reader, SmcError, Writer};
+
+/// The payload of an `Open` message.
+#[derive(Clone, PartialEq, prost::Message)]
+struct Open {
+    #[prost(bytes = "vec", tag = "1")]
+    discovery_key: Vec<u8>,
+    #[prost(bytes = "vec", optional, tag = "2")]
+    capability: Option<Vec<u8>>,
+}
+
+/// The discovery key of a hypercore `key`.
+///
+/// The discovery key is sent instead of the key itself, so that peers only learn the
+/// key of a hypercore if they already know it. Fails if `key` is longer than 64 bytes.
+pub fn discovery_key(key: &[u8]) -> Result<Vec<u8>, SmcError> {
+    let mut mac = Blake2bMac::<U32>::new_from_slice(key)
+        .map_err(|_| Error::new(ErrorKind::InvalidInput, "Key is too long"))?;
+    mac.update(b"hypercore");
+    Ok(mac.finalize().into_bytes().to_vec())
+}
+
+/// The capabilities that prove to a peer that the sender of an `Open` knows the key.
+///
+/// Capabilities are derived from the transport handshake (e.g. the Noise handshake of
+/// hypercore-protocol), which happens before the [`Protocol`] is created.
+pub trait Capability: Send {
+    /// The capability to send when opening a channel for `key`.
+    fn local(&self, key: &[u8]) -> Option<Vec<u8>>;
+
+    /// Verify the `capability` the peer sent when opening a channel for `key`.
+    fn verify(&self, key: &[u8], capability: Option<&[u8]>) -> bool;
+}
+
+/// No capabilities, e.g. for transports that are authenticated otherwise.
+///
+/// Sends no capabilities and accepts all channels.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoCapability;
+
+impl Capability for NoCapability {
+    fn local(&self, _key: &[u8]) -> Option<Vec<u8>> {
+        None
+    }
+
+    fn verify(&self, _key: &[u8], _capability: Option<&[u8]>) -> bool {
+        true
+    }
+}
+
+/// A discovery key, see [`discovery_key`].
+pub type DiscoveryKey = [u8; 32];
+
+/// Maps discovery keys to the channel ids of both peers.
+///
+/// In hypercore-protocol, each peer picks its own id for a channel, and opens it with
+/// an `Open` message that carries the discovery key. The channel is paired once both
+/// peers opened it: messages are sent with the local id, and received with the remote
+/// id. A `ChannelMap` keeps this bookkeeping for replication code that uses a
+/// [`Reader`] and a [`Writer`] directly, and [`Protocol`] uses one internally.
+///
+/// Local ids are the lowest ids that are free, and are only reused after they are
+/// closed. Ids the peer opens twice without closing them are rejected.
+///
+/// # Example
+///
+/// ```
+/// use simple_message_channels::protocol::ChannelMap;
+///
+/// let mut map = ChannelMap::new();
+/// let key = [1; 32];
+/// let local = map.open_local(key);
+/// assert_eq!(map.to_local(7), None);
+/// map.open_remote(key, 7)?;
+/// assert_eq!(map.to_local(7), Some(local));
+/// assert_eq!(map.to_remote(local), Some(7));
+/// assert!(map.open_remote([2; 32], 7).is_err());
+/// # Ok::<(), simple_message_channels::SmcError>(())
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ChannelMap {
+    channels: HashMap<DiscoveryKey, Ids>,
+    // The discovery keys of the channels, by local channel id.
+    local: HashMap<u64, DiscoveryKey>,
+    // The discovery keys of the channels, by remote channel id.
+    remote: HashMap<u64, DiscoveryKey>,
+}
+
+/// The ids of a channel, by discovery key.
+#[derive(Debug, Clone, Copy, Default)]
+struct Ids {
+    local: Option<u64>,
+    remote: Option<u64>,
+}
+
+impl ChannelMap {
+    /// Create an empty map.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Open the channel for `discovery_key` locally.
+    ///
+    /// Returns the local id to send the `Open` with, or the id of the channel if it is
+    /// already open locally.
+    pub fn open_local(&mut self, discovery_key: DiscoveryKey) -> u64 {
+        if let Some(channel) = self.local_channel(&discovery_key) {
+            return channel;
+        }
+        let channel = (0..)
+            .find(|id| !self.local.contains_key(id))
+            .expect("free id");
+        self.channels.entry(discovery_key).or_default().local = Some(channel);
+        self.local.insert(channel, discovery_key);
+        channel
+    }
+
+    /// Record that the peer opened the channel for `discovery_key` with its id `channel`.
+    ///
+    /// Fails if the peer already opened `channel` or `discovery_key`, and did not close
+    /// it.
+    pub fn open_remote(
+        &mut self,
+        discovery_key: DiscoveryKey,
+        channel: u64,
+    ) -> Result<(), SmcError> {
+        if self.remote.contains_key(&channel) {
+            let error = Error::new(ErrorKind::InvalidData, "Remote channel is already open");
+            return Err(error.into());
+        }
+        let ids = self.channels.entry(discovery_key).or_default();
+        if ids.remote.is_some() {
+            let error = Error::new(ErrorKind::InvalidData, "Discovery key is already open");
+            return Err(error.into());
+        }
+        ids.remote = Some(channel);
+        self.remote.insert(channel, discovery_key);
+        Ok(())
+    }
+
+    /// Close the local `channel`, which frees its id.
+    ///
+    /// Returns the discovery key of the channel, or `None` if it was not open.
+    pub fn close_local(&mut self, channel: u64) -> Option<DiscoveryKey> {
+        let discovery_key = self.local.remove(&channel)?;
+        self.release(&discovery_key, |ids| ids.local = None);
+        Some(discovery_key)
+    }
+
+    /// Record that the peer closed its `channel`.
+    ///
+    /// Returns the discovery key of the channel, or `None` if it was not open.
+    pub fn close_remote(&mut self, channel: u64) -> Option<DiscoveryKey> {
+        let discovery_key = self.remote.remove(&channel)?;
+        self.release(&discovery_key, |ids| ids.remote = None);
+        Some(discovery_key)
+    }
+
+    // Clear an id of `discovery_key`, and forget the key once both ids are cleared.
+    fn release(&mut self, discovery_key: &DiscoveryKey, clear: impl FnOnce(&mut Ids)) {
+        if let Some(ids) = self.channels.get_mut(discovery_key) {
+            clear(ids);
+            if ids.local.is_none() && ids.remote.is_none() {
+                self.channels.remove(discovery_key);
+            }
+        }
+    }
+
+    /// The local id of the channel for `discovery_key`, if it is open locally.
+    pub fn local_channel(&self, discovery_key: &DiscoveryKey) -> Option<u64> {
+        self.channels.get(discovery_key)?.local
+    }
+
+    /// The remote id of the channel for `discovery_key`, if the peer opened it.
+    pub fn remote_channel(&self, discovery_key: &DiscoveryKey) -> Option<u64> {
+        self.channels.get(discovery_key)?.remote
+    }
+
+    /// The discovery key of the local `channel`.
+    pub fn local_key(&self, channel: u64) -> Option<&DiscoveryKey> {
+        self.local.get(&channel)
+    }
+
+    /// The discovery key of the remote `channel`.
+    pub fn remote_key(&self, channel: u64) -> Option<&DiscoveryKey> {
+        self.remote.get(&channel)
+    }
+
+    /// Returns `true` if the channel for `discovery_key` is open on both sides.
+    pub fn is_paired(&self, discovery_key: &DiscoveryKey) -> bool {
+        self.channels
+            .get(discovery_key)
+            .is_some_and(|ids| ids.local.is_some() && ids.remote.is_some())
+    }
+
+    /// Map the id of a received message to the local id, if the channel is paired.
+    pub fn to_local(&self, remote: u64) -> Option<u64> {
+        self.local_channel(self.remote.get(&remote)?)
+    }
+
+    /// Map a local id to the id of the peer, if the channel is paired.
+    pub fn to_remote(&self, local: u64) -> Option<u64> {
+        self.remote_channel(self.local.get(&local)?)
+    }
+}
+
+/// An event of a [`Protocol`].
+#[derive(Debug)]
+pub enum Event {
+    /// The peer opened a channel for a discovery key that is not opened locally.
+    ///
+    /// Call [`Protocol::open`] with the key to accept the channel.
+    DiscoveryKey(Vec<u8>),
+    /// A channel is opened on both sides. `channel` is the local channel id.
+    ChannelOpened {
+        discovery_key: Vec<u8>,
+        channel: u64,
+    },
+    /// The peer closed a channel.
+    ChannelClosed {
+        discovery_key: Vec<u8>,
+        channel: u64,
+    },
+    /// A message on an opened channel, with the local channel id.
+    Message(Message),
+}
+
+/// A hypercore-protocol connection.
+///
+/// Handles opening and closing channels on top of a [`Reader`] and a [`Writer`]. Each
+/// peer opens a channel with an `Open` message that carries the discovery key of a
+/// hypercore and a [`Capability`]. Once both peers opened a channel for the same
+/// discovery key and the capability of the peer is verified, the channel is
+/// [opened](Event::ChannelOpened), and its messages are yielded as events.
+///
+/// Channel ids are local: messages are sent with the local channel id, and received
+/// messages are mapped from the id of the peer to the local id, with a [`ChannelMap`].
+/// An `Open` for a remote id or a discovery key that the peer did not close is an
+/// error.
+pub struct Protocol<R, W, C = NoCipher> {
+    reader: Reader<R, Vec<u8>, C>,
+    writer: Writer<W, C>,
+    capability: Box<dyn Capability>,
+    channels: ChannelMap,
+    // The keys of the channels that are opened locally, by discovery key.
+    keys: HashMap<DiscoveryKey, Vec<u8>>,
+    // The capabilities of the channels that the peer opened, by discovery key.
+    capabilities: HashMap<DiscoveryKey, Option<Vec<u8>>>,
+    events: VecDeque<Event>,
+}
+
+impl<R, W, C> Protocol<R, W, C>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+    C: FrameCipher + Unpin,
+{
+    /// Create a new protocol from the reading and writing halves of a connection.
+    pub fn new(reader: Reader<R, Vec<u8>, C>, writer: Writer<W, C>) -> Self {
+        Self {
+            reader,
+            writer,
+            capability: Box::new(NoCapability),
+            channels: ChannelMap::new(),
+            keys: HashMap::new(),
+            capabilities: HashMap::new(),
+            events: VecDeque::new(),
+        }
+    }
+
+    /// Set the capabilities to send and verify.
+    ///
+    /// Defaults to [`NoCapability`].
+    pub fn with_capability(mut self, capability: impl Capability + 'static) -> Self {
+        self.capability = Box::new(capability);
+        self
+    }
+
+    /// Open a channel for the hypercore `key`.
+    ///
+    /// Returns the local channel id. The channel is [opened](Event::ChannelOpened) once
+    /// the peer opened it too.
+    pub async fn open(&mut self, key: &[u8]) -> Result<u64, SmcError> {
+        let discovery_key = to_discovery_key(&discovery_key(key)?)?;
+        if let Some(channel) = self.channels.local_channel(&discovery_key) {
+            return Ok(channel);
+        }
+        let channel = self.channels.open_local(discovery_key);
+        let open = Open {
+            discovery_key: discovery_key.to_vec(),
+            capability: self.capability.local(key),
+        };
+        let sent = self
+            .writer
+            .send(Message::from_protobuf(
+                channel,
+                MessageType::Open.into(),
+                &open,
+            ))
+            .await;
+        if let Err(error) = sent {
+            self.channels.close_local(channel);
+            return Err(error);
+        }
+        self.keys.insert(discovery_key, key.to_vec());
+        if let Some(event) = self.verify(&discovery_key)? {
+            self.events.push_back(event);
+        }
+        Ok(channel)
+    }
+
+    /// Send a message on an opened channel, with the local channel id.
+    pub async fn send(&mut self, message: Message) -> Result<(), SmcError> {
+        if !self.is_opened(message.channel) {
+            return Err(Error::new(ErrorKind::NotConnected, "Channel is not opened").into());
+        }
+        self.writer.send(message).await
+    }
+
+    /// Close the local `channel`.
+    pub async fn close(&mut self, channel: u64) -> Result<(), SmcError> {
+        let discovery_key = match self.channels.close_local(channel) {
+            Some(discovery_key) => discovery_key,
+            None => return Ok(()),
+        };
+        self.keys.remove(&discovery_key);
+        self.writer
+            .send(Message::with_type(channel, MessageType::Close, Vec::new()))
+            .await
+    }
+
+    /// Returns `true` if the local `channel` is opened on both sides.
+    pub fn is_opened(&self, channel: u64) -> bool {
+        self.channels.to_remote(channel).is_some()
+    }
+
+    /// Read the next event.
+    ///
+    /// Returns `None` once the reader ended. This is cancellation safe, like
+    /// [`Reader`].
+    pub async fn next(&mut self) -> Option<Result<Event, SmcError>> {
+        loop {
+            if let Some(event) = self.events.pop_front() {
+                return Some(Ok(event));
+            }
+            let message = match self.reader.next().await? {
+                Ok(message) => message,
+                Err(error) => return Some(Err(error)),
+            };
+            match self.on_message(message) {
+                Ok(Some(event)) => return Some(Ok(event)),
+                Ok(None) => {}
+                Err(error) => return Some(Err(error)),
+            }
+        }
+    }
+
+    /// Get a mutable reference to the reader.
+    pub fn reader(&mut self) -> &mut Reader<R, Vec<u8>, C> {
+        &mut self.reader
+    }
+
+    /// Get a mutable reference to the writer.
+    pub fn writer(&mut self) -> &mut Writer<W, C> {
+        &mut self.writer
+    }
+
+    fn on_message(&mut self, message: Message) -> Result<Option<Event>, SmcError> {
+        match message.message_type() {
+            Ok(MessageType::Open) => {
+                let open: Open = message.decode_protobuf()?;
+                let discovery_key = to_discovery_key(&open.discovery_key)?;
+                self.channels.open_remote(discovery_key, message.channel)?;
+                self.capabilities.insert(discovery_key, open.capability);
+                if self.channels.local_channel(&discovery_key).is_some() {
+                    self.verify(&discovery_key)
+                } else {
+                    Ok(Some(Event::DiscoveryKey(open.discovery_key)))
+                }
+            }
+            Ok(MessageType::Close) => {
+                let discovery_key = match self.channels.close_remote(message.channel) {
+                    Some(discovery_key) => discovery_key,
+                    None => return Ok(None),
+                };
+                self.capabilities.remove(&discovery_key);
+                // The channel is closed locally too.
+                let channel = match self.channels.local_channel(&discovery_key) {
+                    Some(channel) => channel,
+                    None => return Ok(None),
+                };
+                self.channels.close_local(channel);
+                self.keys.remove(&discovery_key);
+                Ok(Some(Event::ChannelClosed {
+                    discovery_key: discovery_key.to_vec(),
+                    channel,
+                }))
+            }
+            _ => {
+                // Messages on channels that are not opened on both sides are dropped.
+                let channel = self.channels.to_local(message.channel);
+                Ok(channel.map(|channel| {
+                    Event::Message(Message::new(channel, message.typ, message.message))
+                }))
+            }
+        }
+    }
+
+    // Verify the capability of a channel that is opened on both sides.
+    fn verify(&mut self, discovery_key: &DiscoveryKey) -> Result<Option<Event>, SmcError> {
+        let (channel, remote) = match (
+            self.channels.local_channel(discovery_key),
+            self.channels.remote_channel(discovery_key),
+        ) {
+            (Some(channel), Some(remote)) => (channel, remote),
+            _ => return Ok(None),
+        };
+        let key = &self.keys[discovery_key];
+        let capability = self.capabilities[discovery_key].as_deref();
+        if !self.capability.verify(key, capability) {
+            self.channels.close_remote(remote);
+            self.capabilities.remove(discovery_key);
+            let error = Error::new(ErrorKind::PermissionDenied, "Invalid capability");
+            return Err(error.into());
+        }
+        Ok(Some(Event::ChannelOpened {
+            discovery_key: discovery_key.to_vec(),
+            channel,
+        }))
+    }
+}
+
+// The discovery key of an `Open` message, which is 32 bytes long.
+fn to_discovery_key(bytes: &[u8]) -> Result<DiscoveryKey, SmcError> {
+    bytes.try_into().map_err(|_| {
+        let error = Error::new(ErrorKind::InvalidData, "Discovery key is not 32 bytes");
+        error.into()
+    })
+}
@@ -0,0 +1,151 @@
+//! QUIC transports.
+//!
+//! Enabled with the `quinn` feature. There are two ways to carry SMC over a
+//! [`quinn::Connection`]:
+//!
+//! * [`open_bi`] and [`accept_bi`] return a [`Channel`] over a single bidirectional
+//!   stream, which carries all SMC channels like a TCP stream would.
+//! * [`QuicChannels`] sends every SMC channel on its own unidirectional stream, so
+//!   that a slow or lossy channel does not block the others.
+
+use futures::io::{AsyncRead, AsyncWrite};
+use futures::stream::{self, BoxStream, Stream, StreamExt};
+use futures::task::{Context, Poll};
+use quinn::{Connection, ConnectionError, RecvStream, SendStream};
+use std::collections::hash_map::{Entry, HashMap};
+use std::io;
+use std::pin::Pin;
+
+use crate::{Channel, Message, Reader, SmcError, Writer};
+
+/// A bidirectional QUIC stream.
+#[derive(Debug)]
+pub struct QuicStream {
+    send: SendStream,
+    recv: RecvStream,
+}
+
+impl QuicStream {
+    /// Create a new stream from the halves of a bidirectional QUIC stream.
+    pub fn new(send: SendStream, recv: RecvStream) -> Self {
+        Self { send, recv }
+    }
+
+    /// Unwrap the halves of the QUIC stream.
+    pub fn into_inner(self) -> (SendStream, RecvStream) {
+        (self.send, self.recv)
+    }
+}
+
+impl AsyncRead for QuicStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        AsyncRead::poll_read(Pin::new(&mut self.recv), cx, buf)
+    }
+}
+
+impl AsyncWrite for QuicStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        AsyncWrite::poll_write(Pin::new(&mut self.send), cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        AsyncWrite::poll_flush(Pin::new(&mut self.send), cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        AsyncWrite::poll_close(Pin::new(&mut self.send), cx)
+    }
+}
+
+/// Open a bidirectional stream on `connection`.
+///
+/// QUIC streams are only announced to the peer once data is sent on them, so the
+/// opening side has to send first.
+pub async fn open_bi(connection: &Connection) -> io::Result<Channel<QuicStream>> {
+    let (send, recv) = connection.open_bi().await?;
+    Ok(Channel::new(QuicStream::new(send, recv)))
+}
+
+/// Accept a bidirectional stream on `connection`.
+pub async fn accept_bi(connection: &Connection) -> io::Result<Channel<QuicStream>> {
+    let (send, recv) = connection.accept_bi().await?;
+    Ok(Channel::new(QuicStream::new(send, recv)))
+}
+
+/// SMC over QUIC with a unidirectional stream per channel.
+///
+/// The stream for a channel is opened with the first message sent on it. Messages
+/// are sent as regular SMC frames, so every stream can be read with a [`Reader`].
+pub struct QuicChannels {
+    connection: Connection,
+    writers: HashMap<u64, Writer<SendStream>>,
+}
+
+impl QuicChannels {
+    /// Create a new multi-stream adapter for `connection`.
+    pub fn new(connection: Connection) -> Self {
+        Self {
+            connection,
+            writers: HashMap::new(),
+        }
+    }
+
+    /// Get a reference to the QUIC connection.
+    pub fn connection(&self) -> &Connection {
+        &self.connection
+    }
+
+    /// Send a message on the stream of its channel.
+    pub async fn send<B: AsRef<[u8]>>(&mut self, message: Message<B>) -> Result<(), SmcError> {
+        let writer = match self.writers.entry(message.channel) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let stream = self.connection.open_uni().await.map_err(io::Error::from)?;
+                entry.insert(Writer::new(stream))
+            }
+        };
+        writer.send(message).await
+    }
+
+    /// Finish the stream of `channel`.
+    ///
+    /// A later message on the channel opens a new stream.
+    pub async fn finish_channel(&mut self, channel: u64) -> Result<(), SmcError> {
+        match self.writers.remove(&channel) {
+            Some(mut writer) => writer.close().await,
+            None => Ok(()),
+        }
+    }
+
+    /// The messages received on all streams the peer opens.
+    ///
+    /// Messages of one channel arrive in order, messages of different channels are
+    /// interleaved in the order they are received. The stream ends when the
+    /// connection is closed.
+    pub fn incoming(&self) -> impl Stream<Item = Result<Message, SmcError>> + Send + 'static {
+        let streams = stream::unfold(Some(self.connection.clone()), |connection| async move {
+            let connection = connection?;
+            match connection.accept_uni().await {
+                Ok(recv) => {
+                    let reader: BoxStream<'static, _> = Reader::new(recv).boxed();
+                    Some((reader, Some(connection)))
+                }
+                Err(ConnectionError::ApplicationClosed(_))
+                | Err(ConnectionError::LocallyClosed) => None,
+                Err(error) => {
+                    let error: SmcError = io::Error::from(error).into();
+                    Some((stream::once(async { Err(error) }).boxed(), None))
+                }
+            }
+        });
+        streams.flatten_unordered(None)
+    }
+}
@@ -0,0 +1,83 @@
+use futures::future::FutureExt;
+use futures::ready;
+use futures::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use crate::clock::{SharedClock, Sleep};
+
+/// The time it takes to fill the bucket of a [`RateLimit`], i.e. the max burst.
+const BURST: Duration = Duration::from_millis(100);
+
+/// A token bucket that limits the number of bytes per second.
+pub(crate) struct RateLimit {
+    bytes_per_sec: u64,
+    capacity: u64,
+    tokens: u64,
+    last: Instant,
+    clock: SharedClock,
+    delay: Option<Sleep>,
+}
+
+impl RateLimit {
+    pub(crate) fn new(bytes_per_sec: u64, clock: SharedClock) -> Self {
+        let bytes_per_sec = bytes_per_sec.max(1);
+        let capacity = (bytes_per_sec as u128 * BURST.as_nanos() / 1_000_000_000).max(1) as u64;
+        Self {
+            bytes_per_sec,
+            capacity,
+            tokens: capacity,
+            last: clock.now(),
+            clock,
+            delay: None,
+        }
+    }
+
+    /// Measure time with `clock` from now on.
+    #[cfg(feature = "sim")]
+    pub(crate) fn set_clock(&mut self, clock: SharedClock) {
+        self.last = clock.now();
+        self.clock = clock;
+        self.delay = None;
+    }
+
+    // Add the tokens for the time since the last refill.
+    fn refill(&mut self) {
+        let now = self.clock.now();
+        let elapsed = now.duration_since(self.last).as_nanos();
+        let new = elapsed * self.bytes_per_sec as u128 / 1_000_000_000;
+        if self.tokens as u128 + new >= self.capacity as u128 {
+            self.tokens = self.capacity;
+            self.last = now;
+        } else if new > 0 {
+            self.tokens += new as u64;
+            // Keep the remainder of the elapsed time for the next refill.
+            let used = new * 1_000_000_000 / self.bytes_per_sec as u128;
+            self.last += Duration::from_nanos(used as u64);
+        }
+    }
+
+    /// Wait until bytes may be transferred, and return how many, at most `max`.
+    pub(crate) fn poll_budget(&mut self, cx: &mut Context<'_>, max: usize) -> Poll<usize> {
+        loop {
+            self.refill();
+            if self.tokens > 0 {
+                self.delay = None;
+                return Poll::Ready((self.tokens as usize).min(max));
+            }
+            // Wait until the bucket has room for `max` bytes, or is full.
+            let needed = (max as u64).clamp(1, self.capacity) as u128;
+            let wait = needed * 1_000_000_000 / self.bytes_per_sec as u128;
+            let clock = &self.clock;
+            let delay = self
+                .delay
+                .get_or_insert_with(|| clock.sleep(Duration::from_nanos(wait as u64)));
+            ready!(delay.poll_unpin(cx));
+            self.delay = None;
+        }
+    }
+
+    /// Take the tokens for `n` transferred bytes.
+    pub(crate) fn consume(&mut self, n: usize) {
+        self.tokens = self.tokens.saturating_sub(n as u64);
+    }
+}
@@ -0,0 +1,119 @@
+use futures::future::poll_fn;
+use futures::io::{AsyncRead, AsyncWrite};
+use futures::ready;
+use futures::stream::Stream;
+use futures::task::{Context, Poll};
+use std::pin::Pin;
+
+use crate::cipher::{FrameCipher, NoCipher};
+use crate::{Reader, SmcError, Writer};
+
+/// A reader for raw, length-prefixed frames.
+///
+/// This reads frames without the channel and typ header of SMC messages, and is a
+/// [`futures::stream::Stream`] of their payloads. It works like [`Reader`], and
+/// can be encrypted in the same way.
+pub struct RawReader<R, C = NoCipher> {
+    reader: Reader<R, Vec<u8>, C>,
+}
+
+impl<R> RawReader<R>
+where
+    R: AsyncRead + Unpin,
+{
+    /// Create a new frame reader from any [`futures::io::AsyncRead`].
+    pub fn new(reader: R) -> Self {
+        // Empty frames are empty payloads, not keepalives.
+        Self {
+            reader: Reader::new(reader).with_keepalives(true),
+        }
+    }
+}
+
+impl<R, C> RawReader<R, C>
+where
+    R: AsyncRead + Unpin,
+    C: FrameCipher,
+{
+    /// Decrypt the stream of frames with `cipher`.
+    ///
+    /// See [`Reader::with_cipher`].
+    pub fn with_cipher<D: FrameCipher>(self, cipher: D) -> RawReader<R, D> {
+        RawReader {
+            reader: self.reader.with_cipher(cipher),
+        }
+    }
+}
+
+impl<R, C> Stream for RawReader<R, C>
+where
+    R: AsyncRead + Unpin,
+    C: FrameCipher + Unpin,
+{
+    type Item = Result<Vec<u8>, SmcError>;
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Vec<u8>, SmcError>>> {
+        let reader = &mut self.get_mut().reader;
+        let result =
+            ready!(reader.poll_frame(cx)).map(|len| len.map(|len| reader.frame(len).to_vec()));
+        Poll::Ready(result)
+    }
+}
+
+/// A writer for raw, length-prefixed frames.
+///
+/// This writes frames without the channel and typ header of SMC messages. It works
+/// like [`Writer`], and can be encrypted in the same way.
+pub struct RawWriter<W, C = NoCipher> {
+    writer: Writer<W, C>,
+}
+
+impl<W> RawWriter<W>
+where
+    W: AsyncWrite + Unpin,
+{
+    /// Create a new frame writer.
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer: Writer::new(writer),
+        }
+    }
+}
+
+impl<W, C> RawWriter<W, C>
+where
+    W: AsyncWrite + Unpin,
+    C: FrameCipher,
+{
+    /// Encrypt the stream of frames with `cipher`.
+    ///
+    /// See [`Writer::with_cipher`].
+    pub fn with_cipher<D: FrameCipher>(self, cipher: D) -> RawWriter<W, D> {
+        RawWriter {
+            writer: self.writer.with_cipher(cipher),
+        }
+    }
+
+    /// Send a frame.
+    ///
+    /// This encodes the frame, writes it and flushes the writer.
+    pub async fn send<B: AsRef<[u8]>>(&mut self, frame: B) -> Result<(), SmcError> {
+        self.send_batch(&[frame]).await
+    }
+
+    /// Send a batch of frames.
+    ///
+    /// See [`Writer::send_batch`].
+    pub async fn send_batch<B: AsRef<[u8]>>(&mut self, frames: &[B]) -> Result<(), SmcError> {
+        poll_fn(|cx| self.writer.poll_ready_buf(cx)).await?;
+        self.writer.encode_frames(frames)?;
+        self.writer.flush().await
+    }
+
+    /// Flush all buffered frames and close the underlying writer.
+    pub async fn close(&mut self) -> Result<(), SmcError> {
+        self.writer.close().await
+    }
+}
@@ -1,105 +1,1437 @@
-use std::io::{Error, ErrorKind};
-use futures::task::{Context, Poll};
-use futures::future::{Future, FutureExt};
-use futures::io::{AsyncRead, AsyncReadExt, BufReader};
+use futures::future::{poll_fn, BoxFuture, FutureExt};
+use futures::io::{AsyncBufRead, AsyncRead, AsyncReadExt, BufReader, Chain, Cursor};
+use futures::ready;
 use futures::stream::Stream;
+use futures::task::{Context, Poll};
+use std::io::{Error, ErrorKind};
+use std::marker::PhantomData;
 use std::pin::Pin;
+use std::time::Duration;
 
-use crate::{Message, MAX_MESSAGE_SIZE};
+#[cfg(feature = "checksum")]
+use crate::checksum::Checksum;
+use crate::cipher::{can_seek, try_apply_from, CipherStart, FrameCipher, NoCipher, SeekableCipher};
+use crate::clock::{system_clock, SharedClock, Sleep};
+use crate::codec::decode_borrowed;
+#[cfg(any(feature = "lz4", feature = "zstd"))]
+use crate::compression;
+use crate::debug::{Direction, FrameObserver};
+use crate::fragment::{Reassembler, Reassembly, FRAGMENT_TYP};
+use crate::lifecycle::{ConnectionEvent, EventSender};
+use crate::rate::RateLimit;
+use crate::session::StreamState;
+use crate::source::Source;
+use crate::stats::{ChannelStats, Stats};
+use crate::varint::{self, VarintDecoder, VarintError, MAX_VARINT_LEN};
+use crate::ChannelEvents;
+use crate::{
+    Message, MessageBuf, MessageHeader, MessagePool, Payload, SmcError, Typ, MAX_FRAME_LEN,
+};
 
 /// A reader for SMC messages.
 ///
 /// Takes any [`futures::io::AsyncRead`] and is a
-/// [`async_std::stream::Stream`] of [`Message`]s.
+/// [`futures::stream::Stream`] of [`Message`]s.
+///
+/// Messages are read into an internal buffer that is reused across messages, and
+/// the payload is copied out of it. [`Reader::next_ref`] reads messages without
+/// copying, so only the messages that are kept need to be copied.
+///
+/// With the `bytes` feature enabled, [`Reader::new_bytes`] creates a reader that
+/// yields messages with [`bytes::Bytes`] payloads.
+///
+/// # Cancellation safety
+///
+/// The decoding state is kept in the reader, not in the futures of its methods. If a
+/// call to `next`, [`Reader::next_ref`] or [`Reader::peek`] is dropped before it
+/// completes, e.g. in a branch of `select!` that lost, the bytes it read are kept and
+/// the next call continues with the same frame. This may be a different method: a
+/// [`Reader::peek`] after a dropped `next` returns the header of the frame that `next`
+/// started to read.
 ///
 /// # Example
 ///
-/// ```rust
+/// ```no_run
+/// # use async_std::{io, prelude::*};
 /// use simple_message_channels::Reader;
-/// let stdin = io::stdin().lock().await;
+/// # async_std::task::block_on(async {
+/// let stdin = io::stdin();
 /// let mut reader = Reader::new(stdin);
 /// while let Some(msg) = reader.next().await {
 ///     let msg = msg?;
-///     println!("Received: ch {} typ {} msg {:?}", msg.channel, msg.typ, text);
+///     println!("Received: ch {} typ {} msg {:?}", msg.channel, msg.typ, msg.message);
 /// }
+/// # io::Result::Ok(())
+/// # });
 /// ```
-pub struct Reader<R> {
-    future: Pin<Box<dyn Future<Output = Result<(Message, BufReader<R>), Error>> + Send>>,
-    finished: bool,
+pub struct Reader<R, B = Vec<u8>, C = NoCipher> {
+    reader: Source<R>,
+    state: State,
+    buf: Vec<u8>,
+    cipher: C,
+    hook: Option<CipherHook<C>>,
+    observer: Option<FrameObserver>,
+    events: Option<EventSender>,
+    rekey_threshold: u64,
+    // The stream offset where the cipher starts, and the number of messages before it
+    // while that offset is not known yet.
+    cipher_offset: u64,
+    plain_messages: u64,
+    filter: Option<ChannelFilter>,
+    stats: Option<Stats>,
+    rate_limit: Option<RateLimit>,
+    message_pool: Option<MessagePool>,
+    fragments: Option<Reassembler>,
+    timeout: Option<Timeout>,
+    clock: SharedClock,
+    on_error: OnError,
+    shrink_threshold: Option<usize>,
+    keepalives: bool,
+    managed: bool,
+    // The number of bytes consumed from the stream, and the offset of the current frame.
+    offset: u64,
+    frame_start: u64,
+    eof_error: bool,
+    // Whether to stop after the header of the next frame, see `Reader::peek`.
+    peek: bool,
+    peeked: Option<MessageHeader>,
+    #[cfg(any(feature = "lz4", feature = "zstd"))]
+    decompressed: Option<Vec<u8>>,
+    #[cfg(feature = "checksum")]
+    checksum: Option<Checksum>,
+    payload: PhantomData<fn() -> B>,
+}
+
+/// A reader for SMC messages that does not require the reader to be [`Send`].
+///
+/// [`Reader`] does not require this anymore, so this is just an alias.
+///
+/// Enabled with the `wasm` feature.
+#[cfg(feature = "wasm")]
+pub type LocalReader<R, B = Vec<u8>> = Reader<R, B>;
+
+/// What a [`Reader`] does after an error.
+///
+/// IO errors always stop the reader.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OnError {
+    /// Stop reading, the reader yields no more messages. This is the default.
+    #[default]
+    Stop,
+    /// Skip frames that are too long or fail to decode, and keep reading.
+    ///
+    /// An invalid length prefix still stops the reader, as the end of the frame is
+    /// unknown.
+    SkipFrame,
+    /// Like [`OnError::SkipFrame`], but also continue after an invalid length
+    /// prefix, by reading the next length prefix from the following byte.
+    ///
+    /// This is a heuristic. It may decode garbage until the reader finds the start of
+    /// a frame again.
+    Resync,
+}
+
+/// A callback that is called with every message read and the cipher of a [`Reader`],
+/// and returns `true` if it upgraded the cipher.
+type CipherHook<C> = Box<dyn FnMut(&Message<&[u8]>, &mut C) -> bool + Send>;
+
+/// A filter for the channel and typ of the frames a [`Reader`] yields.
+type ChannelFilter = Box<dyn Fn(u64, u8) -> bool + Send>;
+
+/// A timeout for the next frame of a [`Reader`].
+struct Timeout {
+    duration: Duration,
+    // Started once the reader waits for the next frame.
+    timer: Option<Sleep>,
+}
+
+/// The decoding state of a [`Reader`].
+enum State {
+    /// Reading the length prefix of the next message.
+    ReadingLength(VarintDecoder),
+    /// Reading the header of a message of `len` bytes, to filter it.
+    ReadingHeader {
+        len: usize,
+        pos: usize,
+        decoder: VarintDecoder,
+    },
+    /// Reading a message of `len` bytes into the buffer.
+    ReadingMessage { len: usize, pos: usize },
+    /// Skipping the rest of a frame of `len` bytes, e.g. one that is too long.
+    Skipping { len: u64, remaining: u64 },
+    /// An error occurred, no more messages are read.
+    Finished,
 }
 
 impl<R> Reader<R>
 where
-    R: AsyncRead + Send + Unpin + 'static,
+    R: AsyncRead + Unpin,
 {
     /// Create a new message reader from any [`futures::io::AsyncRead`].
     pub fn new(reader: R) -> Self {
+        Self::from_reader(BufReader::new(reader).into())
+    }
+
+    /// Create a new message reader that reads from `reader` in chunks of up to `capacity`
+    /// bytes.
+    ///
+    /// [`Reader::new`] uses a capacity of 8 KiB. A larger capacity needs fewer reads
+    /// for bulk transfers, a smaller one less memory.
+    pub fn with_capacity(capacity: usize, reader: R) -> Self {
+        Self::from_reader(BufReader::with_capacity(capacity, reader).into())
+    }
+
+    /// Create a new message reader from a [`BufReader`], e.g. one that was used for a
+    /// handshake.
+    ///
+    /// The bytes that are buffered in `reader` but not consumed yet are read as
+    /// messages.
+    pub fn from_buffered(reader: BufReader<R>) -> Self {
+        Self::from_reader(reader.into())
+    }
+
+    /// Create a new message reader from a reader that is buffered itself, e.g. a
+    /// [`BufReader`] or a [`Cursor`], without buffering its bytes again.
+    ///
+    /// [`Reader::into_inner`] returns the reader with the bytes it buffered, and no
+    /// separate leftover bytes.
+    pub fn from_buf_read(reader: R) -> Self
+    where
+        R: AsyncBufRead,
+    {
+        Self::from_reader(Source::passthrough(reader))
+    }
+
+    /// Create a new message reader that reads `leftover` before it reads from `reader`.
+    ///
+    /// Use this with bytes that a handshake or another protocol read from `reader` but
+    /// did not consume, e.g. the bytes returned by [`Reader::into_inner`].
+    pub fn with_leftover(reader: R, leftover: Vec<u8>) -> Reader<Chain<Cursor<Vec<u8>>, R>> {
+        Reader::new(Cursor::new(leftover).chain(reader))
+    }
+
+    /// Run `preamble` on the transport before reading messages, and decrypt the messages
+    /// with the cipher it returns.
+    ///
+    /// The preamble reads from the same buffered reader as the message reader, e.g. to
+    /// run a handshake in a different format, so bytes that it buffered but did not
+    /// consume are read as messages.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use async_std::io;
+    /// use futures::io::AsyncReadExt;
+    /// use simple_message_channels::{NoCipher, Reader};
+    /// # async_std::task::block_on(async {
+    /// let reader = Reader::with_preamble(io::stdin(), |reader| {
+    ///     Box::pin(async move {
+    ///         let mut magic = [0u8; 4];
+    ///         reader.read_exact(&mut magic).await?;
+    ///         Ok(NoCipher)
+    ///     })
+    /// })
+    /// .await?;
+    /// # Ok::<(), simple_message_channels::SmcError>(())
+    /// # });
+    /// ```
+    pub async fn with_preamble<F, D>(
+        reader: R,
+        preamble: F,
+    ) -> Result<Reader<R, Vec<u8>, D>, SmcError>
+    where
+        F: for<'a> FnOnce(&'a mut BufReader<R>) -> BoxFuture<'a, Result<D, SmcError>>,
+        D: FrameCipher,
+    {
+        let mut reader = BufReader::new(reader);
+        let cipher = preamble(&mut reader).await?;
+        Ok(Self::from_reader(reader.into()).with_cipher(cipher))
+    }
+}
+
+#[cfg(feature = "bytes")]
+impl<R> Reader<R, bytes::Bytes>
+where
+    R: AsyncRead + Unpin,
+{
+    /// Create a new message reader that yields [`bytes::Bytes`] payloads.
+    pub fn new_bytes(reader: R) -> Self {
+        Self::from_reader(BufReader::new(reader).into())
+    }
+}
+
+impl<R, B> Reader<R, B>
+where
+    R: AsyncRead + Unpin,
+{
+    fn from_reader(reader: Source<R>) -> Self {
         Self {
-            future: decoder(BufReader::new(reader)).boxed(),
-            finished: false,
+            reader,
+            state: State::ReadingLength(VarintDecoder::new()),
+            buf: Vec::new(),
+            cipher: NoCipher,
+            hook: None,
+            observer: None,
+            events: None,
+            rekey_threshold: 0,
+            cipher_offset: 0,
+            plain_messages: 0,
+            filter: None,
+            stats: None,
+            rate_limit: None,
+            message_pool: None,
+            fragments: None,
+            timeout: None,
+            clock: system_clock(),
+            on_error: OnError::Stop,
+            shrink_threshold: None,
+            keepalives: false,
+            managed: false,
+            offset: 0,
+            frame_start: 0,
+            eof_error: false,
+            peek: false,
+            peeked: None,
+            #[cfg(any(feature = "lz4", feature = "zstd"))]
+            decompressed: None,
+            #[cfg(feature = "checksum")]
+            checksum: None,
+            payload: PhantomData,
         }
     }
 }
 
-// Proxy to the internal BufReader and decode messages.
-impl<R> Stream for Reader<R>
+impl<R, B, C> Reader<R, B, C>
 where
-    R: AsyncRead + Send + Unpin + 'static,
+    R: AsyncRead + Unpin,
+    C: FrameCipher,
 {
-    type Item = Result<Message, Error>;
-    fn poll_next(
-        mut self: Pin<&mut Self>,
+    /// Decrypt the stream of messages with `cipher`.
+    ///
+    /// The cipher is applied to all bytes that are read from now on. This removes
+    /// the hook set with [`Reader::with_cipher_hook`].
+    pub fn with_cipher<D: FrameCipher>(self, cipher: D) -> Reader<R, B, D> {
+        Reader {
+            reader: self.reader,
+            state: self.state,
+            buf: self.buf,
+            cipher,
+            hook: None,
+            observer: self.observer,
+            events: self.events,
+            rekey_threshold: self.rekey_threshold,
+            cipher_offset: self.cipher_offset,
+            plain_messages: self.plain_messages,
+            filter: self.filter,
+            stats: self.stats,
+            rate_limit: self.rate_limit,
+            message_pool: self.message_pool,
+            fragments: self.fragments,
+            timeout: self.timeout,
+            clock: self.clock,
+            on_error: self.on_error,
+            shrink_threshold: self.shrink_threshold,
+            keepalives: self.keepalives,
+            managed: self.managed,
+            offset: self.offset,
+            frame_start: self.frame_start,
+            eof_error: self.eof_error,
+            peek: self.peek,
+            peeked: self.peeked,
+            #[cfg(any(feature = "lz4", feature = "zstd"))]
+            decompressed: self.decompressed,
+            #[cfg(feature = "checksum")]
+            checksum: self.checksum,
+            payload: PhantomData,
+        }
+    }
+
+    /// Replace the cipher, e.g. with new keys after a handshake.
+    ///
+    /// The new cipher is applied to all bytes that are read from now on.
+    pub fn set_cipher(&mut self, cipher: C) {
+        self.cipher = cipher;
+        self.emit(ConnectionEvent::CipherUpgraded(Direction::Read));
+    }
+
+    /// Get a mutable reference to the cipher.
+    pub fn cipher_mut(&mut self) -> &mut C {
+        &mut self.cipher
+    }
+
+    /// Start decrypting at `start`, and read the bytes before it in plain text.
+    ///
+    /// This is for protocols that send their first messages unencrypted, like
+    /// hypercore-protocol v7, whose first message carries the nonce of the cipher. The
+    /// writer of the peer needs the same start, see
+    /// [`Writer::cipher_start_after`](crate::Writer::cipher_start_after).
+    pub fn cipher_start_after(mut self, start: CipherStart) -> Self {
+        (self.cipher_offset, self.plain_messages) = match start {
+            CipherStart::Messages(0) => (self.offset, 0),
+            CipherStart::Messages(n) => (u64::MAX, n),
+            CipherStart::Bytes(offset) => (offset, 0),
+        };
+        self
+    }
+
+    /// Set the number of bytes of keystream that should be left before the keys are
+    /// rotated, see [`Reader::needs_rekey`].
+    ///
+    /// Defaults to 0.
+    pub fn with_rekey_threshold(mut self, threshold: u64) -> Self {
+        self.rekey_threshold = threshold;
+        self
+    }
+
+    /// Returns `true` if the cipher has at most the rekey threshold of keystream left,
+    /// see [`FrameCipher::remaining`].
+    ///
+    /// The peer has to rekey before it sends more bytes than are left, or reading fails
+    /// with [`SmcError::RekeyNeeded`]. See
+    /// [`Writer::with_rekey_threshold`](crate::Writer::with_rekey_threshold).
+    pub fn needs_rekey(&self) -> bool {
+        self.cipher
+            .remaining()
+            .is_some_and(|remaining| remaining <= self.rekey_threshold)
+    }
+
+    /// Set a hook that is called with every message and the cipher.
+    ///
+    /// The hook is called after a message is decoded, before any more bytes are
+    /// decrypted. This allows to upgrade the cipher after a handshake, e.g. with
+    /// an `Option` of a cipher that is `None` until the keys are known. The hook
+    /// can capture state, like the handshake or a key store.
+    pub fn with_cipher_hook<F>(mut self, hook: F) -> Self
+    where
+        F: FnMut(&Message<&[u8]>, &mut C) + Send + 'static,
+    {
+        let mut hook = hook;
+        self.hook = Some(Box::new(move |message, cipher| {
+            hook(message, cipher);
+            false
+        }));
+        self
+    }
+
+    // Set a hook that returns `true` once it upgraded the cipher after a handshake.
+    pub(crate) fn with_upgrade_hook<F>(mut self, hook: F) -> Self
+    where
+        F: FnMut(&Message<&[u8]>, &mut C) -> bool + Send + 'static,
+    {
+        self.hook = Some(Box::new(hook));
+        self
+    }
+
+    // Send the lifecycle events of the reader to `events`.
+    pub(crate) fn set_events(&mut self, events: EventSender) {
+        self.events = Some(events);
+    }
+
+    fn emit(&self, event: ConnectionEvent) {
+        if let Some(events) = &self.events {
+            events.emit(event);
+        }
+    }
+
+    /// Call `observer` with every message that is read, e.g. for metrics, audit logs or
+    /// to capture a session.
+    ///
+    /// Messages are observed after they are decrypted and decompressed. Fragments are
+    /// observed before they are reassembled, and messages skipped by the channel filter
+    /// are not observed. See also
+    /// [`FrameTap`](crate::debug::FrameTap), which wraps a stream of messages instead.
+    pub fn set_frame_observer<F>(&mut self, observer: F)
+    where
+        F: FnMut(Direction, &Message<&[u8]>) + Send + 'static,
+    {
+        self.observer = Some(Box::new(observer));
+    }
+
+    /// Remove the observer set with [`Reader::set_frame_observer`].
+    pub fn clear_frame_observer(&mut self) {
+        self.observer = None;
+    }
+
+    /// Set whether [`Stats`] are collected.
+    ///
+    /// By default, they are not.
+    pub fn with_stats(mut self, stats: bool) -> Self {
+        self.stats = if stats { Some(Stats::default()) } else { None };
+        self
+    }
+
+    /// The [`Stats`] of the messages read so far, if enabled.
+    pub fn stats(&self) -> Option<&Stats> {
+        self.stats.as_ref()
+    }
+
+    /// The [`ChannelStats`] of the messages read on `channel`, if stats are enabled.
+    pub fn channel_stats(&self, channel: u64) -> Option<&ChannelStats> {
+        self.stats.as_ref()?.channel_stats(channel)
+    }
+
+    /// Consume the reader, returning the underlying reader and the bytes that were
+    /// read from it but not parsed yet.
+    ///
+    /// The reader reads ahead of the frames it parses. The returned bytes follow the last
+    /// message that was read, and come before the bytes that are read from the
+    /// underlying reader next, e.g. to switch a transport to another protocol after an
+    /// SMC handshake. They are not decrypted with the cipher, except after
+    /// [`Channel::import_state`](crate::Channel::import_state): the bytes of the frame
+    /// that the exported channel was reading were decrypted already, and come first
+    /// while they are not read. The bytes of a frame that was only partially read are
+    /// discarded.
+    pub fn into_inner(self) -> (R, Vec<u8>) {
+        let buffered = [self.reader.prefix(), self.reader.buffer()].concat();
+        (self.reader.into_inner(), buffered)
+    }
+
+    // The state of the reader for a `SessionState`, which starts with the partial frame.
+    pub(crate) fn export_state(&self) -> Result<StreamState, SmcError>
+    where
+        C: SeekableCipher,
+    {
+        let (offset, mut plain) = match &self.state {
+            State::ReadingLength(decoder) if decoder.is_empty() => (self.offset, Vec::new()),
+            State::ReadingLength(decoder) => (self.frame_start, decoder.pushed()),
+            State::ReadingHeader { len, pos, .. } | State::ReadingMessage { len, pos } => {
+                let mut plain = vec![0u8; MAX_VARINT_LEN];
+                let prefix = varint::encode(*len as u64, &mut plain);
+                plain.truncate(prefix);
+                plain.extend_from_slice(&self.buf[..*pos]);
+                (self.frame_start, plain)
+            }
+            State::Skipping { .. } | State::Finished => return Err(SmcError::InvalidSession),
+        };
+        // The rest of an imported prefix, which is not read yet.
+        let (prefix_plain, prefix_raw) = self.reader.prefix_parts();
+        plain.extend_from_slice(prefix_plain);
+        // The length prefix of the partial frame is read again, and counted again.
+        let decoded = matches!(
+            self.state,
+            State::ReadingHeader { .. } | State::ReadingMessage { .. }
+        );
+        let plain_messages = match self.plain_messages {
+            0 => 0,
+            n => n + decoded as u64,
+        };
+        Ok(StreamState {
+            offset,
+            cipher_offset: self.cipher_offset,
+            plain_messages,
+            position: self.cipher.position(),
+            plain,
+            raw: [prefix_raw, self.reader.buffer()].concat(),
+        })
+    }
+
+    // Stop reading after the state was exported, since the bytes that were read ahead
+    // are part of the state.
+    pub(crate) fn finish_export(&mut self) {
+        self.state = State::Finished;
+        self.reader.clear();
+    }
+
+    // Check that the session of `state` can continue on this reader, which is unused.
+    pub(crate) fn check_import(&self, state: &StreamState) -> Result<(), SmcError>
+    where
+        C: SeekableCipher,
+    {
+        let fresh = matches!(&self.state, State::ReadingLength(decoder) if decoder.is_empty());
+        let buffered = !self.reader.prefix().is_empty() || !self.reader.buffer().is_empty();
+        if !fresh || self.offset > 0 || buffered || !can_seek(&self.cipher, state.position) {
+            return Err(SmcError::InvalidSession);
+        }
+        Ok(())
+    }
+
+    // Continue the session of `state`, once it passed `check_import`.
+    pub(crate) fn import_state(&mut self, state: &StreamState) -> Result<(), SmcError>
+    where
+        C: SeekableCipher,
+    {
+        self.cipher
+            .seek(state.position)
+            .map_err(|_| SmcError::InvalidSession)?;
+        self.offset = state.offset;
+        self.frame_start = state.offset;
+        self.plain_messages = state.plain_messages;
+        // The partial frame is already decrypted.
+        let plain_end = state.offset + state.plain.len() as u64;
+        self.cipher_offset = match state.plain_messages {
+            0 => state.cipher_offset.max(plain_end),
+            _ => state.cipher_offset,
+        };
+        self.reader.set_prefix(&state.plain, &state.raw);
+        Ok(())
+    }
+
+    /// Copy the payloads of the messages that are yielded into buffers taken from `pool`.
+    ///
+    /// Return the buffers with [`Message::recycle`] once the messages are handled. This
+    /// only applies to `Vec<u8>` payloads. See [`MessagePool`].
+    pub fn with_message_pool(mut self, pool: MessagePool) -> Self {
+        self.message_pool = Some(pool);
+        self
+    }
+
+    /// Set a timeout for the next frame.
+    ///
+    /// If no complete frame arrives within `timeout`, the reader yields an error of
+    /// kind [`std::io::ErrorKind::TimedOut`]. Keepalives count as frames, even if they
+    /// are not yielded. The reader can still be used after a timeout, and waits for
+    /// the next frame with a new timeout.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(Timeout {
+            duration: timeout,
+            timer: None,
+        });
+        self
+    }
+
+    /// Measure the timeout and the rate limit with `clock`, e.g. with a [`SimClock`] in
+    /// tests.
+    ///
+    /// Defaults to the [`SystemClock`](crate::SystemClock).
+    ///
+    /// [`SimClock`]: crate::SimClock
+    #[cfg(feature = "sim")]
+    pub fn with_clock<K: crate::Clock + 'static>(mut self, clock: K) -> Self {
+        self.clock = std::sync::Arc::new(clock);
+        if let Some(timeout) = &mut self.timeout {
+            timeout.timer = None;
+        }
+        if let Some(rate_limit) = &mut self.rate_limit {
+            rate_limit.set_clock(self.clock.clone());
+        }
+        self
+    }
+
+    /// Set what the reader does after an error.
+    ///
+    /// Defaults to [`OnError::Stop`]. The error is yielded in any case.
+    pub fn with_on_error(mut self, on_error: OnError) -> Self {
+        self.on_error = on_error;
+        self
+    }
+
+    /// Limit the bytes read from the underlying reader to `bytes_per_sec`.
+    ///
+    /// Reads are delayed once the budget is used up, so the transport fills up, which
+    /// applies backpressure to the peer, e.g. through the TCP window. Bursts of up to a
+    /// tenth of the rate are read at once. The delays count towards the timeout set
+    /// with [`Reader::with_timeout`].
+    pub fn set_rate_limit(&mut self, bytes_per_sec: u64) {
+        self.rate_limit = Some(RateLimit::new(bytes_per_sec, self.clock.clone()));
+    }
+
+    /// Remove the rate limit set with [`Reader::set_rate_limit`].
+    pub fn clear_rate_limit(&mut self) {
+        self.rate_limit = None;
+    }
+
+    /// Set a filter for the channel and typ of the messages that are yielded.
+    ///
+    /// Frames for which the filter returns `false` are skipped after their header
+    /// is read, without reading them into the buffer. Use this to ignore channels
+    /// that are closed or not subscribed to.
+    pub fn set_channel_filter<F>(&mut self, filter: F)
+    where
+        F: Fn(u64, u8) -> bool + Send + 'static,
+    {
+        self.filter = Some(Box::new(filter));
+    }
+
+    /// Remove the filter set with [`Reader::set_channel_filter`].
+    pub fn clear_channel_filter(&mut self) {
+        self.filter = None;
+    }
+
+    /// Set whether keepalive frames are yielded.
+    ///
+    /// Keepalives are empty frames, which peers send to keep a connection open.
+    /// If `true`, they are yielded as [`Message::keepalive`] messages. By default,
+    /// they are skipped.
+    pub fn with_keepalives(mut self, keepalives: bool) -> Self {
+        self.keepalives = keepalives;
+        self
+    }
+
+    /// Set whether the reader is managed, i.e. skips the control messages of this crate.
+    ///
+    /// A managed reader skips keepalives and the messages with a typ in
+    /// [`Typ::RESERVED`], so it only yields the messages of the application. Don't
+    /// enable this if the control messages are handled on top of the reader, e.g. by
+    /// [`ChannelEvents`] or a [`FlowStream`](crate::FlowStream). By default, the reader
+    /// is not managed.
+    pub fn with_managed(mut self, managed: bool) -> Self {
+        self.managed = managed;
+        self
+    }
+
+    /// Reassemble messages that were split into fragments of [`FRAGMENT_TYP`], see
+    /// [`Writer::with_fragmentation`](crate::Writer::with_fragmentation).
+    ///
+    /// Fragments are not yielded, and are not passed to the filter set with
+    /// [`Reader::set_channel_filter`]. If the last part of a message is filtered out,
+    /// its fragments are dropped. A reassembled message of more than `max_len` bytes
+    /// fails with [`SmcError::MessageTooLong`]. By default, fragments are yielded like
+    /// other messages.
+    pub fn with_fragmentation(mut self, max_len: usize) -> Self {
+        self.fragments = Some(Reassembler::new(max_len));
+        self
+    }
+
+    /// Set whether the end of the stream is an error.
+    ///
+    /// By default, the reader ends (yields `None`) if the stream ends between two
+    /// frames. If `true`, it yields an [`std::io::ErrorKind::UnexpectedEof`] error
+    /// first, as older versions did. A stream that ends in the middle of a frame is
+    /// always an error, see [`SmcError::TruncatedFrame`].
+    pub fn with_eof_error(mut self, eof_error: bool) -> Self {
+        self.eof_error = eof_error;
+        self
+    }
+
+    /// Set the size above which the internal buffer is shrunk again.
+    ///
+    /// Messages are read into a buffer that is reused, and grows to the largest
+    /// message seen. If a threshold is set, the buffer is shrunk back to it once a
+    /// message that fits into it is read. By default, the buffer is never shrunk.
+    pub fn with_shrink_threshold(mut self, threshold: usize) -> Self {
+        self.shrink_threshold = Some(threshold);
+        self
+    }
+
+    /// Decompress the payloads of all messages that are read from now on.
+    ///
+    /// The payloads have to be prefixed with their codec, as sent by a writer with
+    /// [`Writer::enable_compression`](crate::Writer::enable_compression). See the
+    /// [`compression`](crate::compression) module.
+    ///
+    /// Enabled with the `lz4` or `zstd` feature.
+    #[cfg(any(feature = "lz4", feature = "zstd"))]
+    pub fn enable_compression(&mut self) {
+        self.decompressed.get_or_insert_with(Vec::new);
+    }
+
+    /// Check the `checksum` of every frame, see [`Checksum`].
+    ///
+    /// Frames with a wrong checksum fail with [`SmcError::ChecksumMismatch`].
+    ///
+    /// Enabled with the `checksum` feature.
+    #[cfg(feature = "checksum")]
+    pub fn with_checksum(mut self, checksum: Checksum) -> Self {
+        self.checksum = Some(checksum);
+        self
+    }
+
+    /// The number of bytes read from the stream so far.
+    ///
+    /// This counts the bytes of all frames that were read, including skipped frames and
+    /// keepalives, but not the bytes that are buffered and not read yet.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Turn the reader into a stream of [`Frame`]s, which carry the position of each
+    /// message on the wire.
+    pub fn frames(self) -> Frames<Self> {
+        Frames { reader: self }
+    }
+
+    /// Turn the reader into a stream of [`ChannelEvent`](crate::ChannelEvent)s.
+    ///
+    /// See [`ChannelEvents`].
+    pub fn events(self) -> ChannelEvents<Self> {
+        ChannelEvents::new(self)
+    }
+
+    /// Read the next message without copying its payload.
+    ///
+    /// The payload borrows from the internal buffer of the reader, so it is only valid
+    /// until the next message is read. Use this to inspect messages and copy out only
+    /// the ones that are kept. Returns `None` at the end of the stream or after an error.
+    ///
+    /// This is cancellation safe, see [`Reader`].
+    pub async fn next_ref(&mut self) -> Option<Result<Message<&[u8]>, SmcError>> {
+        match poll_fn(|cx| self.poll_frame(cx)).await? {
+            Ok(len) => Some(self.decode(len)),
+            Err(error) => Some(Err(error)),
+        }
+    }
+
+    /// Read the header of the next message, without reading its payload.
+    ///
+    /// The message is then read as usual, e.g. with [`Reader::next_ref`], or skipped
+    /// with [`Reader::skip_peeked`]. Peeking again returns the same header. The length in the
+    /// header is the length of the payload as it is sent, i.e. before it is
+    /// decompressed. Returns `None` at the end of the stream or after an error.
+    ///
+    /// This is cancellation safe, see [`Reader`].
+    pub async fn peek(&mut self) -> Option<Result<MessageHeader, SmcError>> {
+        poll_fn(|cx| self.poll_peek(cx)).await
+    }
+
+    /// Skip the message whose header was read with [`Reader::peek`].
+    ///
+    /// Does nothing if no header was peeked.
+    pub fn skip_peeked(&mut self) {
+        if self.peeked.take().is_none() {
+            return;
+        }
+        if let State::ReadingMessage { len, pos } = self.state {
+            self.state = if pos < len {
+                State::Skipping {
+                    len: len as u64,
+                    remaining: (len - pos) as u64,
+                }
+            } else {
+                State::ReadingLength(VarintDecoder::new())
+            };
+        }
+    }
+
+    fn poll_peek(&mut self, cx: &mut Context<'_>) -> Poll<Option<Result<MessageHeader, SmcError>>> {
+        if let Some(header) = self.peeked {
+            return Poll::Ready(Some(Ok(header)));
+        }
+        self.peek = true;
+        let result = ready!(self.poll_next_frame(cx));
+        self.peek = false;
+        let len = match result {
+            Some(Ok(None)) => return Poll::Ready(self.peeked.map(Ok)),
+            Some(Ok(Some(len))) => len,
+            Some(Err(error)) => return Poll::Ready(Some(Err(error))),
+            None => return Poll::Ready(None),
+        };
+        // The frame was read without peeking its header, e.g. a keepalive, or a frame
+        // whose read was cancelled after its header. The frame is kept in the buffer,
+        // to be read or skipped like a peeked one.
+        let header = if len == 0 {
+            let keepalive = Message::<Vec<u8>>::keepalive();
+            MessageHeader {
+                channel: keepalive.channel,
+                typ: keepalive.typ,
+                len: 0,
+            }
+        } else {
+            match varint::decode(&self.buf[..len]) {
+                Ok((header, header_len)) => {
+                    #[cfg(feature = "checksum")]
+                    let len = match self.checksum {
+                        Some(_) => len.saturating_sub(crate::checksum::CHECKSUM_LEN),
+                        None => len,
+                    };
+                    MessageHeader {
+                        channel: header >> 4,
+                        typ: (header & 0b1111) as u8,
+                        len: len.saturating_sub(header_len) as u64,
+                    }
+                }
+                // An invalid header fails to decode.
+                Err(_) => match self.decode(len) {
+                    Err(error) => return Poll::Ready(Some(Err(error))),
+                    Ok(_) => unreachable!("frame with an invalid header decoded"),
+                },
+            }
+        };
+        self.peeked = Some(header);
+        self.state = State::ReadingMessage { len, pos: len };
+        Poll::Ready(Some(Ok(header)))
+    }
+
+    // Decode the frame of `len` bytes in the buffer, and pass it to the hook.
+    fn decode(&mut self, len: usize) -> Result<Message<&[u8]>, SmcError> {
+        let frame = &self.buf[..len];
+        #[cfg(feature = "checksum")]
+        let result = match &self.checksum {
+            Some(checksum) => checksum.verify(frame).and_then(decode_borrowed),
+            None => decode_borrowed(frame),
+        };
+        #[cfg(not(feature = "checksum"))]
+        let result = decode_borrowed(frame);
+        #[cfg(any(feature = "lz4", feature = "zstd"))]
+        let result = match &mut self.decompressed {
+            Some(buf) => result.and_then(move |message| {
+                let payload = compression::decompress(message.message, buf)?;
+                Ok(Message::new(message.channel, message.typ, payload))
+            }),
+            None => result,
+        };
+        match result {
+            Ok(message) => {
+                if let Some(stats) = &mut self.stats {
+                    if len > 0 {
+                        stats.record(&message, self.clock.now());
+                    }
+                }
+                if let Some(observer) = &mut self.observer {
+                    observer(Direction::Read, &message);
+                }
+                if let Some(hook) = &mut self.hook {
+                    if hook(&message, &mut self.cipher) {
+                        if let Some(events) = &self.events {
+                            events.emit(ConnectionEvent::HandshakeComplete);
+                            events.emit(ConnectionEvent::CipherUpgraded(Direction::Read));
+                        }
+                    }
+                }
+                Ok(message)
+            }
+            Err(error) => {
+                if let Some(stats) = &mut self.stats {
+                    stats.errors += 1;
+                }
+                // The frame was read completely, so it can be skipped.
+                if self.on_error == OnError::Stop {
+                    self.state = State::Finished;
+                }
+                Err(error)
+            }
+        }
+    }
+
+    // The frame of `len` bytes in the buffer.
+    pub(crate) fn frame(&self, len: usize) -> &[u8] {
+        &self.buf[..len]
+    }
+
+    // Read the next frame into the buffer, returning its length.
+    pub(crate) fn poll_frame(
+        &mut self,
         cx: &mut Context<'_>,
-    ) -> Poll<Option<Result<Message, Error>>> {
-        if self.finished {
+    ) -> Poll<Option<Result<usize, SmcError>>> {
+        self.peek = false;
+        self.peeked = None;
+        self.poll_next_frame(cx)
+            .map(|result| result.map(|result| result.map(|len| len.expect("header is not peeked"))))
+    }
+
+    // Read the next frame, or only its header if peeking. Returns `None` as the length
+    // if the header was peeked.
+    fn poll_next_frame(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Option<usize>, SmcError>>> {
+        if let State::Finished = self.state {
             return Poll::Ready(None);
         }
-        match self.future.poll_unpin(cx) {
-            Poll::Pending => Poll::Pending,
-            Poll::Ready(result) => {
-                match result {
-                    Ok((message, reader)) => {
-                        // Re-init the future.
-                        self.future = decoder(reader).boxed();
-                        Poll::Ready(Some(Ok(message)))
+        let result = match self.poll_read_frame(cx) {
+            Poll::Ready(result) => result,
+            Poll::Pending => return self.poll_timeout(cx),
+        };
+        if let Some(timeout) = &mut self.timeout {
+            timeout.timer = None;
+        }
+        // The stream ended between two frames.
+        if let State::Finished = self.state {
+            if let Ok(None) = result {
+                return Poll::Ready(None);
+            }
+        }
+        if let Err(error) = &result {
+            if let Some(stats) = &mut self.stats {
+                stats.errors += 1;
+            }
+            let recover = match error {
+                SmcError::MessageTooLong(_) => self.on_error != OnError::Stop,
+                SmcError::Varint(VarintError::Incomplete) => false,
+                SmcError::Varint(_) => self.on_error == OnError::Resync,
+                _ => false,
+            };
+            if !recover {
+                self.state = State::Finished;
+            }
+        }
+        Poll::Ready(Some(result))
+    }
+
+    // Poll the timeout while waiting for a frame.
+    fn poll_timeout<T>(&mut self, cx: &mut Context<'_>) -> Poll<Option<Result<T, SmcError>>> {
+        let timeout = match &mut self.timeout {
+            Some(timeout) => timeout,
+            None => return Poll::Pending,
+        };
+        let duration = timeout.duration;
+        let clock = &self.clock;
+        let timer = timeout.timer.get_or_insert_with(|| clock.sleep(duration));
+        ready!(timer.poll_unpin(cx));
+        timeout.timer = None;
+        let error = Error::new(ErrorKind::TimedOut, "No frame received within timeout");
+        Poll::Ready(Some(Err(error.into())))
+    }
+
+    fn poll_read_frame(&mut self, cx: &mut Context<'_>) -> Poll<Result<Option<usize>, SmcError>> {
+        loop {
+            match &mut self.state {
+                State::Finished => unreachable!("poll_read_frame called after an error"),
+                State::ReadingLength(decoder) => {
+                    let budget = ready!(poll_budget(&mut self.rate_limit, cx, MAX_VARINT_LEN));
+                    let buf = ready!(Pin::new(&mut self.reader).poll_fill_buf(cx))?;
+                    if buf.is_empty() && !decoder.is_empty() {
+                        return Poll::Ready(Err(varint::truncated_prefix(decoder)));
+                    }
+                    if buf.is_empty() && self.eof_error {
+                        return Poll::Ready(Err(Error::from(ErrorKind::UnexpectedEof).into()));
+                    }
+                    if buf.is_empty() {
+                        self.state = State::Finished;
+                        self.emit(ConnectionEvent::RemoteClosed);
+                        return Poll::Ready(Ok(None));
+                    }
+                    if decoder.is_empty() {
+                        self.frame_start = self.offset;
+                    }
+                    let mut len = Ok(None);
+                    let mut applied = Ok(());
+                    let mut consumed = 0;
+                    for byte in buf.iter().take(budget) {
+                        let mut byte = [*byte];
+                        let offset = self.offset + consumed as u64;
+                        applied =
+                            try_apply_from(&mut self.cipher, self.cipher_offset, offset, &mut byte);
+                        if applied.is_err() {
+                            break;
+                        }
+                        consumed += 1;
+                        len = decoder.push(byte[0]);
+                        if !matches!(len, Ok(None)) {
+                            break;
+                        }
+                    }
+                    // Bytes of an invalid length prefix are consumed and counted, to resync
+                    // after them with the stream offset and the cipher in sync.
+                    Pin::new(&mut self.reader).consume(consumed);
+                    self.offset += consumed as u64;
+                    if let Some(rate_limit) = &mut self.rate_limit {
+                        rate_limit.consume(consumed);
+                    }
+                    if let Some(stats) = &mut self.stats {
+                        stats.bytes += consumed as u64;
+                    }
+                    applied?;
+                    let len = len?;
+                    if len == Some(0) {
+                        if let Some(stats) = &mut self.stats {
+                            stats.keepalives += 1;
+                        }
+                        self.emit(ConnectionEvent::KeepaliveReceived);
+                    }
+                    if let Some(len) = len.filter(|&len| len > 0 && self.plain_messages > 0) {
+                        self.plain_messages -= 1;
+                        if self.plain_messages == 0 {
+                            self.cipher_offset = self.offset + len;
+                        }
+                    }
+                    match len {
+                        None => {}
+                        Some(len) if len > MAX_FRAME_LEN => {
+                            self.state = State::Skipping {
+                                len,
+                                remaining: len,
+                            };
+                            return Poll::Ready(Err(SmcError::MessageTooLong(len)));
+                        }
+                        // Empty frames are keepalives.
+                        Some(0) if self.keepalives && !self.managed => {
+                            return Poll::Ready(Ok(Some(0)));
+                        }
+                        Some(0) => {
+                            if let Some(timeout) = &mut self.timeout {
+                                timeout.timer = None;
+                            }
+                        }
+                        Some(len) => {
+                            let len = len as usize;
+                            if let Some(threshold) = self.shrink_threshold {
+                                if len <= threshold && self.buf.len() > threshold {
+                                    self.buf.truncate(threshold);
+                                    self.buf.shrink_to(threshold);
+                                }
+                            }
+                            if self.buf.len() < len {
+                                self.buf.resize(len, 0);
+                            }
+                            self.state = if self.filter.is_some() || self.peek || self.managed {
+                                State::ReadingHeader {
+                                    len,
+                                    pos: 0,
+                                    decoder: VarintDecoder::new(),
+                                }
+                            } else {
+                                State::ReadingMessage { len, pos: 0 }
+                            };
+                        }
+                    }
+                }
+                State::ReadingHeader { len, pos, decoder } => {
+                    let budget = ready!(poll_budget(&mut self.rate_limit, cx, *len - *pos));
+                    let buf = ready!(Pin::new(&mut self.reader).poll_fill_buf(cx))?;
+                    if buf.is_empty() {
+                        return Poll::Ready(Err(SmcError::TruncatedFrame {
+                            expected: *len as u64,
+                            got: *pos as u64,
+                        }));
+                    }
+                    let mut header = Ok(None);
+                    let mut applied = Ok(());
+                    let mut consumed = 0;
+                    for byte in &buf[..buf.len().min(budget)] {
+                        let target = &mut self.buf[*pos..*pos + 1];
+                        target[0] = *byte;
+                        let offset = self.offset + consumed as u64;
+                        applied =
+                            try_apply_from(&mut self.cipher, self.cipher_offset, offset, target);
+                        if applied.is_err() {
+                            break;
+                        }
+                        consumed += 1;
+                        *pos += 1;
+                        header = decoder.push(target[0]);
+                        if !matches!(header, Ok(None)) {
+                            break;
+                        }
+                    }
+                    Pin::new(&mut self.reader).consume(consumed);
+                    self.offset += consumed as u64;
+                    if let Some(rate_limit) = &mut self.rate_limit {
+                        rate_limit.consume(consumed);
+                    }
+                    if let Some(stats) = &mut self.stats {
+                        stats.bytes += consumed as u64;
+                    }
+                    applied?;
+                    let (len, pos) = (*len, *pos);
+                    match header {
+                        Ok(Some(header)) => {
+                            let (channel, typ) = (header >> 4, (header & 0b1111) as u8);
+                            let reserved = Typ::new(typ).is_some_and(Typ::is_reserved);
+                            let fragment = self.fragments.is_some() && typ == FRAGMENT_TYP;
+                            let accept = fragment
+                                || !(self.managed && reserved)
+                                    && self
+                                        .filter
+                                        .as_ref()
+                                        .is_none_or(|filter| filter(channel, typ));
+                            if !accept {
+                                if let Some(fragments) = &mut self.fragments {
+                                    fragments.discard(channel);
+                                }
+                            }
+                            if accept && self.peek {
+                                self.state = State::ReadingMessage { len, pos };
+                                #[cfg(feature = "checksum")]
+                                let len = match self.checksum {
+                                    Some(_) => len.saturating_sub(crate::checksum::CHECKSUM_LEN),
+                                    None => len,
+                                };
+                                self.peeked = Some(MessageHeader {
+                                    channel,
+                                    typ,
+                                    len: len.saturating_sub(pos) as u64,
+                                });
+                                return Poll::Ready(Ok(None));
+                            }
+                            self.state = if accept {
+                                State::ReadingMessage { len, pos }
+                            } else if pos < len {
+                                State::Skipping {
+                                    len: len as u64,
+                                    remaining: (len - pos) as u64,
+                                }
+                            } else {
+                                State::ReadingLength(VarintDecoder::new())
+                            };
+                        }
+                        // Read the rest of the frame, to fail decoding it.
+                        Err(_) => self.state = State::ReadingMessage { len, pos },
+                        Ok(None) if pos == len => self.state = State::ReadingMessage { len, pos },
+                        Ok(None) => {}
+                    }
+                }
+                State::Skipping { len, remaining } => {
+                    // Skipped bytes are decrypted too, to keep the cipher in sync.
+                    let chunk = (*remaining).min(8 * 1024) as usize;
+                    let chunk = ready!(poll_budget(&mut self.rate_limit, cx, chunk));
+                    if self.buf.len() < chunk {
+                        self.buf.resize(chunk, 0);
+                    }
+                    let buf = &mut self.buf[..chunk];
+                    let n = ready!(Pin::new(&mut self.reader).poll_read(cx, buf))?;
+                    if n == 0 {
+                        return Poll::Ready(Err(SmcError::TruncatedFrame {
+                            expected: *len,
+                            got: *len - *remaining,
+                        }));
+                    }
+                    try_apply_from(
+                        &mut self.cipher,
+                        self.cipher_offset,
+                        self.offset,
+                        &mut buf[..n],
+                    )?;
+                    self.offset += n as u64;
+                    if let Some(rate_limit) = &mut self.rate_limit {
+                        rate_limit.consume(n);
+                    }
+                    if let Some(stats) = &mut self.stats {
+                        stats.bytes += n as u64;
+                    }
+                    *remaining -= n as u64;
+                    if *remaining == 0 {
+                        self.state = State::ReadingLength(VarintDecoder::new());
+                    }
+                }
+                State::ReadingMessage { len, pos } => {
+                    while *pos < *len {
+                        let budget = ready!(poll_budget(&mut self.rate_limit, cx, *len - *pos));
+                        let buf = &mut self.buf[*pos..*pos + budget];
+                        let n = ready!(Pin::new(&mut self.reader).poll_read(cx, buf))?;
+                        if n == 0 {
+                            return Poll::Ready(Err(SmcError::TruncatedFrame {
+                                expected: *len as u64,
+                                got: *pos as u64,
+                            }));
+                        }
+                        try_apply_from(
+                            &mut self.cipher,
+                            self.cipher_offset,
+                            self.offset,
+                            &mut buf[..n],
+                        )?;
+                        *pos += n;
+                        self.offset += n as u64;
+                        if let Some(rate_limit) = &mut self.rate_limit {
+                            rate_limit.consume(n);
+                        }
+                        if let Some(stats) = &mut self.stats {
+                            stats.bytes += n as u64;
+                        }
+                    }
+                    let len = *len;
+                    self.state = State::ReadingLength(VarintDecoder::new());
+                    return Poll::Ready(Ok(Some(len)));
+                }
+            }
+        }
+    }
+}
+
+// Wait for the budget to read up to `max` bytes, if the reader is rate limited.
+fn poll_budget(
+    rate_limit: &mut Option<RateLimit>,
+    cx: &mut Context<'_>,
+    max: usize,
+) -> Poll<usize> {
+    match rate_limit {
+        Some(rate_limit) => rate_limit.poll_budget(cx, max),
+        None => Poll::Ready(max),
+    }
+}
+
+impl<R, B, C> Reader<R, B, C>
+where
+    R: AsyncRead + Unpin,
+    B: Payload,
+    C: FrameCipher,
+{
+    /// Poll for the next message.
+    ///
+    /// This is what [`futures::stream::Stream::poll_next`] does, without pinning the
+    /// reader, for futures and actors that drive the reader by hand. Returns `None` at
+    /// the end of the stream or after an error.
+    pub fn poll_next_message(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Message<B>, SmcError>>> {
+        let pool = self.message_pool.take();
+        let poll = self.poll_decoded(cx, |channel, typ, payload| {
+            let payload = match payload {
+                Decoded::Borrowed(payload) => match &pool {
+                    Some(pool) => B::from_pool(payload, pool),
+                    None => B::from_slice(payload),
+                },
+                Decoded::Reassembled(payload) => B::from_frame(payload, 0),
+            };
+            Message::new(channel, typ, payload)
+        });
+        self.message_pool = pool;
+        poll
+    }
+
+    /// Read the next message into `buf`, which the caller provides.
+    ///
+    /// The payload replaces the contents of `buf`, and the header of the message is
+    /// returned. Use this to allocate payloads from an arena or a pool, e.g. with a
+    /// `bytes::BytesMut` that is reused. Returns `None` at the end of the stream or after
+    /// an error.
+    ///
+    /// This is cancellation safe, see [`Reader`].
+    pub async fn next_into<M: MessageBuf>(
+        &mut self,
+        buf: &mut M,
+    ) -> Option<Result<MessageHeader, SmcError>> {
+        poll_fn(|cx| self.poll_next_into(cx, buf)).await
+    }
+
+    /// Poll to read the next message into `buf`, see [`Reader::next_into`].
+    pub fn poll_next_into<M: MessageBuf>(
+        &mut self,
+        cx: &mut Context<'_>,
+        buf: &mut M,
+    ) -> Poll<Option<Result<MessageHeader, SmcError>>> {
+        self.poll_decoded(cx, |channel, typ, payload| {
+            let payload = match &payload {
+                Decoded::Borrowed(payload) => payload,
+                Decoded::Reassembled(payload) => payload.as_slice(),
+            };
+            buf.fill(payload);
+            MessageHeader {
+                channel,
+                typ,
+                len: payload.len() as u64,
+            }
+        })
+    }
+
+    // Poll for the next message, and pass its channel, typ and payload to `take`.
+    fn poll_decoded<T, F>(
+        &mut self,
+        cx: &mut Context<'_>,
+        mut take: F,
+    ) -> Poll<Option<Result<T, SmcError>>>
+    where
+        F: FnMut(u64, u8, Decoded<'_>) -> T,
+    {
+        loop {
+            let len = match ready!(self.poll_frame(cx)) {
+                Some(Ok(len)) => len,
+                Some(Err(error)) => {
+                    if let Some(events) = &self.events {
+                        events.emit_error(&error);
+                    }
+                    return Poll::Ready(Some(Err(error)));
+                }
+                None => return Poll::Ready(None),
+            };
+            let mut fragments = self.fragments.take();
+            let result = self.decode(len).map(|message| {
+                let reassembly = match &mut fragments {
+                    Some(fragments) => fragments.push(&message)?,
+                    None => Reassembly::Whole,
+                };
+                let payload = match reassembly {
+                    Reassembly::Pending => return Ok(None),
+                    Reassembly::Complete(payload) => Decoded::Reassembled(payload),
+                    Reassembly::Whole => Decoded::Borrowed(message.message),
+                };
+                Ok(Some(take(message.channel, message.typ, payload)))
+            });
+            self.fragments = fragments;
+            match result {
+                Ok(Ok(Some(item))) => return Poll::Ready(Some(Ok(item))),
+                Ok(Ok(None)) => {}
+                Ok(Err(error)) => {
+                    if let Some(stats) = &mut self.stats {
+                        stats.errors += 1;
                     }
-                    Err(error) => {
-                        self.finished = true;
-                        Poll::Ready(Some(Err(error)))
+                    if self.on_error == OnError::Stop {
+                        self.state = State::Finished;
                     }
+                    if let Some(events) = &self.events {
+                        events.emit_error(&error);
+                    }
+                    return Poll::Ready(Some(Err(error)));
+                }
+                Err(error) => {
+                    if let Some(events) = &self.events {
+                        events.emit_error(&error);
+                    }
+                    return Poll::Ready(Some(Err(error)));
                 }
             }
         }
     }
 }
 
-/// Decode a single message from a BufReader.
-///
-/// Returns either an error or both the message and the BufReader.
-pub async fn decoder<'a, R>(mut reader: BufReader<R>) -> Result<(Message, BufReader<R>), Error>
+// The payload of a decoded message.
+enum Decoded<'a> {
+    // The payload in the buffer of the reader.
+    Borrowed(&'a [u8]),
+    // A payload reassembled from fragments.
+    Reassembled(Vec<u8>),
+}
+
+// Proxy to the internal BufReader and decode messages.
+impl<R, B, C> Stream for Reader<R, B, C>
+where
+    R: AsyncRead + Unpin,
+    B: Payload,
+    C: FrameCipher + Unpin,
+{
+    type Item = Result<Message<B>, SmcError>;
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Message<B>, SmcError>>> {
+        self.get_mut().poll_next_message(cx)
+    }
+}
+
+/// A message together with its position on the wire, see [`Reader::frames`].
+#[derive(Debug)]
+pub struct Frame<B = Vec<u8>> {
+    /// The message.
+    pub message: Message<B>,
+    /// The number of bytes of the frame on the wire, including the length prefix.
+    ///
+    /// For a reassembled message, this is the last part of the message, see
+    /// [`Reader::with_fragmentation`].
+    pub wire_len: u64,
+    /// The offset of the frame from the start of the stream.
+    ///
+    /// The offset of the next frame is at least `offset + wire_len`. It is more if
+    /// frames were skipped in between, e.g. keepalives.
+    pub offset: u64,
+}
+
+/// A stream of [`Frame`]s, see [`Reader::frames`].
+pub struct Frames<R> {
+    reader: R,
+}
+
+impl<R> Frames<R> {
+    /// Get a mutable reference to the reader.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    /// Unwrap the reader.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl<R, B, C> Stream for Frames<Reader<R, B, C>>
 where
-    R: AsyncRead + Send + Unpin + 'static,
+    R: AsyncRead + Unpin,
+    B: Payload,
+    C: FrameCipher + Unpin,
 {
-    let mut varint: u64 = 0;
-    let mut factor = 1;
-    let mut headerbuf = vec![0u8; 1];
-    // Read initial varint (message length).
-    loop {
-        reader.read_exact(&mut headerbuf).await?;
-        let byte = headerbuf[0];
-        varint = varint + (byte as u64 & 127) * factor;
-        if byte < 128 {
-            break;
-        }
-        if varint > MAX_MESSAGE_SIZE {
-            return Err(Error::new(ErrorKind::InvalidInput, "Message too long"));
-        }
-        factor = factor * 128;
-    }
-
-    // Read main message.
-    let mut messagebuf = vec![0u8; varint as usize];
-    reader.read_exact(&mut messagebuf).await?;
-    let message = Message::from_buf(&messagebuf)?;
-    Ok((message, reader))
+    type Item = Result<Frame<B>, SmcError>;
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<B>, SmcError>>> {
+        let reader = &mut self.get_mut().reader;
+        let result = ready!(Pin::new(&mut *reader).poll_next(cx));
+        Poll::Ready(result.map(|result| {
+            result.map(|message| Frame {
+                message,
+                wire_len: reader.offset - reader.frame_start,
+                offset: reader.frame_start,
+            })
+        }))
+    }
 }
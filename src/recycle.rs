@@ -0,0 +1,90 @@
+use std::sync::{Arc, Mutex};
+
+use crate::Message;
+
+/// The default max number of idle buffers a [`MessagePool`] keeps.
+const DEFAULT_MAX_BUFFERS: usize = 64;
+
+/// A pool of payload buffers, to reuse them instead of allocating one per message.
+///
+/// A reader with [`Reader::with_message_pool`](crate::Reader::with_message_pool) copies
+/// the payloads of the messages it yields into buffers taken from the pool.
+/// Once a message is handled, [`Message::recycle`] returns its buffer to the pool. In
+/// a steady state, e.g. replication, buffers are then allocated only until the pool
+/// holds enough of them.
+///
+/// The pool is a cheap handle, and clones share the same buffers. Buffers keep their
+/// capacity, so the pool holds at most [`MessagePool::with_max_buffers`] buffers of
+/// up to [`MAX_MESSAGE_SIZE`](crate::MAX_MESSAGE_SIZE) bytes.
+#[derive(Clone)]
+pub struct MessagePool {
+    buffers: Arc<Mutex<Vec<Vec<u8>>>>,
+    max_buffers: usize,
+}
+
+impl MessagePool {
+    /// Create a new, empty pool.
+    pub fn new() -> Self {
+        Self {
+            buffers: Default::default(),
+            max_buffers: DEFAULT_MAX_BUFFERS,
+        }
+    }
+
+    /// Set the max number of idle buffers the pool keeps.
+    ///
+    /// Buffers that are returned while the pool is full are dropped. Defaults to 64.
+    pub fn with_max_buffers(mut self, max_buffers: usize) -> Self {
+        self.max_buffers = max_buffers;
+        self
+    }
+
+    /// Take an empty buffer with room for at least `len` bytes.
+    ///
+    /// This allocates a new buffer if the pool is empty.
+    pub fn take(&self, len: usize) -> Vec<u8> {
+        let buf = self.buffers.lock().expect("pool lock poisoned").pop();
+        match buf {
+            Some(mut buf) => {
+                buf.clear();
+                buf.reserve(len);
+                buf
+            }
+            None => Vec::with_capacity(len),
+        }
+    }
+
+    /// Return a buffer to the pool.
+    pub fn put(&self, buf: Vec<u8>) {
+        if buf.capacity() == 0 {
+            return;
+        }
+        let mut buffers = self.buffers.lock().expect("pool lock poisoned");
+        if buffers.len() < self.max_buffers {
+            buffers.push(buf);
+        }
+    }
+
+    /// The number of idle buffers in the pool.
+    pub fn len(&self) -> usize {
+        self.buffers.lock().expect("pool lock poisoned").len()
+    }
+
+    /// Returns `true` if the pool has no idle buffers.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for MessagePool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Message {
+    /// Return the payload buffer of the message to `pool`, see [`MessagePool`].
+    pub fn recycle(self, pool: &MessagePool) {
+        pool.put(self.message);
+    }
+}
@@ -0,0 +1,111 @@
+use futures::future::poll_fn;
+use futures::ready;
+use futures::sink::Sink;
+use futures::stream::Stream;
+use futures::task::{Context, Poll};
+use std::pin::Pin;
+
+use crate::{Message, SmcError};
+
+/// Forward the messages of `a` to `b`, and the messages of `b` to `a`, e.g. for a proxy
+/// or a relay between two peers.
+///
+/// `a` and `b` are streams and sinks of messages, usually [`Channel`](crate::Channel)s.
+/// Wrap them with [`Channel::intercept`](crate::Channel::intercept) to remap channels,
+/// e.g. with a [`Remap`](crate::Remap), to filter messages, or to inspect them.
+///
+/// Once a side ends, the sink of the other side is closed, and the other direction is
+/// still forwarded. This returns once both sides ended, or fails with the first error
+/// of either side. Keepalives are only relayed if the readers yield them.
+///
+/// # Example
+///
+/// ```
+/// use futures::prelude::*;
+/// use simple_message_channels::{pipe, relay, Message, Remap, SmcError};
+///
+/// # futures::executor::block_on(async {
+/// let (mut client, a) = pipe();
+/// let (b, mut server) = pipe();
+/// let relayed = relay(a, b.intercept(Remap::new().with_offset(10)));
+/// let exchange = async {
+///     // The channels of the server appear at an offset of 10 to the client.
+///     client.send(Message::new(11, 1, b"ping".to_vec())).await?;
+///     assert_eq!(server.next().await.unwrap()?.channel, 1);
+///     server.send(Message::new(2, 1, b"pong".to_vec())).await?;
+///     assert_eq!(client.next().await.unwrap()?.channel, 12);
+///     server.close().await?;
+///     client.close().await?;
+///     Ok::<(), SmcError>(())
+/// };
+/// futures::try_join!(relayed, exchange)?;
+/// # Ok::<(), SmcError>(())
+/// # }).unwrap();
+/// ```
+pub async fn relay<A, B>(mut a: A, mut b: B) -> Result<(), SmcError>
+where
+    A: Stream<Item = Result<Message, SmcError>> + Sink<Message, Error = SmcError> + Unpin,
+    B: Stream<Item = Result<Message, SmcError>> + Sink<Message, Error = SmcError> + Unpin,
+{
+    let mut a_to_b = Forward::default();
+    let mut b_to_a = Forward::default();
+    poll_fn(|cx| {
+        let a_done = a_to_b.poll(cx, &mut a, &mut b)?;
+        let b_done = b_to_a.poll(cx, &mut b, &mut a)?;
+        if a_done.is_ready() && b_done.is_ready() {
+            Poll::Ready(Ok(()))
+        } else {
+            Poll::Pending
+        }
+    })
+    .await
+}
+
+// One direction of a relay.
+#[derive(Default)]
+struct Forward {
+    // A message that waits for the sink to be ready.
+    pending: Option<Message>,
+    done: bool,
+}
+
+impl Forward {
+    // Forward messages from `source` to `sink`, until `source` ends and `sink` is closed.
+    fn poll<S, T>(
+        &mut self,
+        cx: &mut Context<'_>,
+        source: &mut S,
+        sink: &mut T,
+    ) -> Poll<Result<(), SmcError>>
+    where
+        S: Stream<Item = Result<Message, SmcError>> + Unpin,
+        T: Sink<Message, Error = SmcError> + Unpin,
+    {
+        if self.done {
+            return Poll::Ready(Ok(()));
+        }
+        loop {
+            if let Some(message) = self.pending.take() {
+                match Pin::new(&mut *sink).poll_ready(cx)? {
+                    Poll::Ready(()) => Pin::new(&mut *sink).start_send(message)?,
+                    Poll::Pending => {
+                        self.pending = Some(message);
+                        return Poll::Pending;
+                    }
+                }
+            }
+            match Pin::new(&mut *source).poll_next(cx)? {
+                Poll::Ready(Some(message)) => self.pending = Some(message),
+                Poll::Ready(None) => {
+                    ready!(Pin::new(&mut *sink).poll_close(cx))?;
+                    self.done = true;
+                    return Poll::Ready(Ok(()));
+                }
+                Poll::Pending => {
+                    ready!(Pin::new(&mut *sink).poll_flush(cx))?;
+                    return Poll::Pending;
+                }
+            }
+        }
+    }
+}
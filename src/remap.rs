@@ -0,0 +1,143 @@
+use std::collections::HashMap;
+
+use crate::{Interceptor, Message, SmcError};
+
+/// What a [`Remap`] does with a message on a channel it has no mapping for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Unmapped {
+    /// Pass the message on with its channel unchanged. This is the default.
+    #[default]
+    Pass,
+    /// Drop the message.
+    Drop,
+    /// Fail with [`SmcError::UnmappedChannel`].
+    Error,
+}
+
+/// An [`Interceptor`] that rewrites the channels of messages between the wire and the
+/// application.
+///
+/// Channels are mapped by explicit pairs, and otherwise by an offset: with an offset of
+/// `n`, the wire channel `c` is the local channel `c + n`. This moves the channels of a
+/// connection into their own namespace, e.g. to bridge several connections into one
+/// session. Received messages are mapped from the wire to the local channel, and sent
+/// messages back. Keepalives are passed on unchanged.
+///
+/// # Example
+///
+/// ```
+/// use futures::prelude::*;
+/// use simple_message_channels::{pipe, Message, Remap, SmcError};
+///
+/// # futures::executor::block_on(async {
+/// let (a, b) = pipe();
+/// let (mut a, mut b) = (a, b.intercept(Remap::new().with_offset(100)));
+/// a.send(Message::new(1, 1, b"hello".to_vec())).await?;
+/// assert_eq!(b.next().await.unwrap()?.channel, 101);
+/// b.send(Message::new(102, 1, b"world".to_vec())).await?;
+/// assert_eq!(a.next().await.unwrap()?.channel, 2);
+/// # Ok::<(), SmcError>(())
+/// # }).unwrap();
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Remap {
+    // Local channels by wire channel, and wire channels by local channel.
+    to_local: HashMap<u64, u64>,
+    to_wire: HashMap<u64, u64>,
+    offset: Option<u64>,
+    unmapped: Unmapped,
+}
+
+impl Remap {
+    /// Create a remap without mappings, which passes all channels on unchanged.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Map the wire channel `wire` to the local channel `local`.
+    ///
+    /// This replaces previous mappings of both channels, and takes precedence over the
+    /// offset.
+    pub fn with_channel(mut self, wire: u64, local: u64) -> Self {
+        if let Some(previous) = self.to_local.insert(wire, local) {
+            self.to_wire.remove(&previous);
+        }
+        if let Some(previous) = self.to_wire.insert(local, wire) {
+            if previous != wire {
+                self.to_local.remove(&previous);
+            }
+        }
+        self
+    }
+
+    /// Map the wire channel `c` to the local channel `c + offset`, for channels without
+    /// an explicit mapping.
+    ///
+    /// Local channels below `offset`, wire channels whose local channel would overflow,
+    /// and channels that map to an explicitly mapped channel are unmapped.
+    pub fn with_offset(mut self, offset: u64) -> Self {
+        self.offset = Some(offset);
+        self
+    }
+
+    /// Set what happens to messages on unmapped channels.
+    ///
+    /// Defaults to [`Unmapped::Pass`]. Without an offset, all channels without an
+    /// explicit mapping are unmapped.
+    pub fn with_unmapped(mut self, unmapped: Unmapped) -> Self {
+        self.unmapped = unmapped;
+        self
+    }
+
+    /// The local channel of the wire channel `wire`.
+    pub fn to_local(&self, wire: u64) -> Option<u64> {
+        if let Some(local) = self.to_local.get(&wire) {
+            return Some(*local);
+        }
+        wire.checked_add(self.offset?)
+            .filter(|local| !self.to_wire.contains_key(local))
+    }
+
+    /// The wire channel of the local channel `local`.
+    pub fn to_wire(&self, local: u64) -> Option<u64> {
+        if let Some(wire) = self.to_wire.get(&local) {
+            return Some(*wire);
+        }
+        local
+            .checked_sub(self.offset?)
+            .filter(|wire| !self.to_local.contains_key(wire))
+    }
+
+    // Pass `message` on with `channel`, or apply the unmapped policy.
+    fn remap(
+        &self,
+        mut message: Message,
+        channel: Option<u64>,
+        out: &mut Vec<Message>,
+    ) -> Result<(), SmcError> {
+        if message.is_keepalive() {
+            out.push(message);
+            return Ok(());
+        }
+        match (channel, self.unmapped) {
+            (Some(channel), _) => message.channel = channel,
+            (None, Unmapped::Pass) => {}
+            (None, Unmapped::Drop) => return Ok(()),
+            (None, Unmapped::Error) => return Err(SmcError::UnmappedChannel(message.channel)),
+        }
+        out.push(message);
+        Ok(())
+    }
+}
+
+impl Interceptor for Remap {
+    fn inbound(&mut self, message: Message, out: &mut Vec<Message>) -> Result<(), SmcError> {
+        let channel = self.to_local(message.channel);
+        self.remap(message, channel, out)
+    }
+
+    fn outbound(&mut self, message: Message, out: &mut Vec<Message>) -> Result<(), SmcError> {
+        let channel = self.to_wire(message.channel);
+        self.remap(message, channel, out)
+    }
+}
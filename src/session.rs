@@ -0,0 +1,143 @@
+use crate::varint::{self, MAX_VARINT_LEN};
+use crate::SmcError;
+
+/// The version of the encoding of [`SessionState::to_bytes`].
+const VERSION: u8 = 1;
+
+/// The state of the framing and the ciphers of a [`Channel`](crate::Channel), to resume
+/// its session on another transport without a new handshake.
+///
+/// Export the state with [`Channel::export_state`](crate::Channel::export_state), and
+/// import it into a new channel on the new transport with
+/// [`Channel::import_state`](crate::Channel::import_state). The state contains the
+/// positions of both ciphers, the part of a frame that was read, the bytes that were
+/// read ahead, and the messages that were queued but not written yet. It does not
+/// contain the keys of the ciphers, the configuration of the channel, or the fragments
+/// of a message that is reassembled: set up the new channel as the old one, before the
+/// state is imported.
+///
+/// Bytes that were written to the old transport, but not read by the peer, are lost.
+/// Migrate once the peer received everything, e.g. after an acknowledgement.
+///
+/// # Example
+///
+/// ```
+/// use futures::prelude::*;
+/// use simple_message_channels::{Channel, Message, PipeStream, SessionState, SmcError};
+///
+/// # futures::executor::block_on(async {
+/// let (x, y) = PipeStream::pair(1024);
+/// let (mut a, mut b) = (Channel::new(x), Channel::new(y));
+/// a.send(Message::new(1, 1, b"hello".to_vec())).await?;
+/// assert_eq!(b.next().await.unwrap()?.message, b"hello");
+///
+/// // Move both ends to a new transport, e.g. after the network changed.
+/// let a_state = a.export_state()?;
+/// let b_state = b.export_state()?;
+/// let b_state = SessionState::from_bytes(&b_state.to_bytes())?;
+/// let (x, y) = PipeStream::pair(1024);
+/// let mut a = Channel::new(x).import_state(&a_state)?;
+/// let mut b = Channel::new(y).import_state(&b_state)?;
+/// a.send(Message::new(1, 1, b"world".to_vec())).await?;
+/// assert_eq!(b.next().await.unwrap()?.message, b"world");
+/// # Ok::<(), SmcError>(())
+/// # }).unwrap();
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SessionState {
+    pub(crate) read: StreamState,
+    pub(crate) write: StreamState,
+}
+
+/// The state of one direction of a channel.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(crate) struct StreamState {
+    // The stream offset where the bytes of the state start.
+    pub(crate) offset: u64,
+    // The stream offset where the cipher starts, and the number of messages before it
+    // while that offset is not known yet.
+    pub(crate) cipher_offset: u64,
+    pub(crate) plain_messages: u64,
+    // The position of the cipher, see `SeekableCipher::position`.
+    pub(crate) position: u64,
+    // Decrypted bytes of a partial frame, and the bytes after them as they are on the
+    // wire.
+    pub(crate) plain: Vec<u8>,
+    pub(crate) raw: Vec<u8>,
+}
+
+impl SessionState {
+    /// Encode the state, e.g. to send it to the process that takes over the session.
+    ///
+    /// The encoding may change between versions of this crate.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = vec![VERSION];
+        self.read.encode(&mut buf);
+        self.write.encode(&mut buf);
+        buf
+    }
+
+    /// Decode a state that was encoded with [`SessionState::to_bytes`].
+    ///
+    /// Fails with [`SmcError::InvalidSession`] if `buf` is not a valid state.
+    pub fn from_bytes(buf: &[u8]) -> Result<Self, SmcError> {
+        let mut buf = match buf.split_first() {
+            Some((&VERSION, rest)) => rest,
+            _ => return Err(SmcError::InvalidSession),
+        };
+        let read = StreamState::decode(&mut buf)?;
+        let write = StreamState::decode(&mut buf)?;
+        if !buf.is_empty() {
+            return Err(SmcError::InvalidSession);
+        }
+        Ok(Self { read, write })
+    }
+}
+
+impl StreamState {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        put(buf, self.offset);
+        put(buf, self.cipher_offset);
+        put(buf, self.plain_messages);
+        put(buf, self.position);
+        for bytes in [&self.plain, &self.raw] {
+            put(buf, bytes.len() as u64);
+            buf.extend_from_slice(bytes);
+        }
+    }
+
+    fn decode(buf: &mut &[u8]) -> Result<Self, SmcError> {
+        Ok(Self {
+            offset: take(buf)?,
+            cipher_offset: take(buf)?,
+            plain_messages: take(buf)?,
+            position: take(buf)?,
+            plain: take_bytes(buf)?,
+            raw: take_bytes(buf)?,
+        })
+    }
+}
+
+fn put(buf: &mut Vec<u8>, value: u64) {
+    let mut varint = [0u8; MAX_VARINT_LEN];
+    let len = varint::encode(value, &mut varint);
+    buf.extend_from_slice(&varint[..len]);
+}
+
+fn take(buf: &mut &[u8]) -> Result<u64, SmcError> {
+    let (value, len) = varint::decode(buf).map_err(|_| SmcError::InvalidSession)?;
+    *buf = &buf[len..];
+    Ok(value)
+}
+
+fn take_bytes(buf: &mut &[u8]) -> Result<Vec<u8>, SmcError> {
+    let len = take(buf)?;
+    if len > buf.len() as u64 {
+        return Err(SmcError::InvalidSession);
+    }
+    let (bytes, rest) = buf.split_at(len as usize);
+    *buf = rest;
+    Ok(bytes.to_vec())
+}
@@ -0,0 +1,163 @@
+use futures::io::{AsyncBufRead, AsyncRead, BufReader};
+use futures::task::{Context, Poll};
+use std::io::Result;
+use std::pin::Pin;
+
+type FillBuf<R> = for<'a> fn(Pin<&'a mut R>, &mut Context<'_>) -> Poll<Result<&'a [u8]>>;
+
+/// The buffered input of a [`Reader`](crate::Reader).
+///
+/// This is either a [`BufReader`] the reader owns, or a reader that is buffered itself
+/// and is used directly, see [`Reader::from_buf_read`](crate::Reader::from_buf_read).
+/// Bytes of an imported session are read before it, see
+/// [`Channel::import_state`](crate::Channel::import_state).
+pub(crate) struct Source<R> {
+    input: Input<R>,
+    // Decrypted bytes, followed by bytes as they are on the wire.
+    prefix: Vec<u8>,
+    prefix_plain: usize,
+    prefix_pos: usize,
+}
+
+enum Input<R> {
+    Buffered(BufReader<R>),
+    // The `AsyncBufRead` methods of `reader`, which the other methods don't require.
+    Passthrough {
+        reader: R,
+        fill_buf: FillBuf<R>,
+        consume: fn(Pin<&mut R>, usize),
+    },
+}
+
+impl<R> Source<R> {
+    fn new(input: Input<R>) -> Self {
+        Self {
+            input,
+            prefix: Vec::new(),
+            prefix_plain: 0,
+            prefix_pos: 0,
+        }
+    }
+
+    pub(crate) fn passthrough(reader: R) -> Self
+    where
+        R: AsyncBufRead,
+    {
+        Self::new(Input::Passthrough {
+            reader,
+            fill_buf: R::poll_fill_buf,
+            consume: R::consume,
+        })
+    }
+
+    // Read the decrypted bytes `plain` and then the bytes `raw` before the input.
+    pub(crate) fn set_prefix(&mut self, plain: &[u8], raw: &[u8]) {
+        self.prefix = [plain, raw].concat();
+        self.prefix_plain = plain.len();
+        self.prefix_pos = 0;
+    }
+
+    // The bytes of the prefix that are not consumed yet.
+    pub(crate) fn prefix(&self) -> &[u8] {
+        &self.prefix[self.prefix_pos..]
+    }
+
+    // The decrypted and the raw bytes of the prefix that are not consumed yet.
+    pub(crate) fn prefix_parts(&self) -> (&[u8], &[u8]) {
+        let split = self.prefix_pos.max(self.prefix_plain);
+        let plain = &self.prefix[self.prefix_pos.min(split)..split];
+        (plain, &self.prefix[split..])
+    }
+
+    // Drop the prefix and the bytes that are buffered by the input.
+    pub(crate) fn clear(&mut self)
+    where
+        R: AsyncRead + Unpin,
+    {
+        self.prefix.clear();
+        self.prefix_plain = 0;
+        self.prefix_pos = 0;
+        if let Input::Buffered(reader) = &mut self.input {
+            let len = reader.buffer().len();
+            Pin::new(reader).consume(len);
+        }
+    }
+
+    // The bytes that are buffered by the input and not consumed yet. A passthrough
+    // reader keeps them itself.
+    pub(crate) fn buffer(&self) -> &[u8] {
+        match &self.input {
+            Input::Buffered(reader) => reader.buffer(),
+            Input::Passthrough { .. } => &[],
+        }
+    }
+
+    pub(crate) fn into_inner(self) -> R {
+        match self.input {
+            Input::Buffered(reader) => reader.into_inner(),
+            Input::Passthrough { reader, .. } => reader,
+        }
+    }
+}
+
+impl<R> From<BufReader<R>> for Source<R> {
+    fn from(reader: BufReader<R>) -> Self {
+        Self::new(Input::Buffered(reader))
+    }
+}
+
+impl<R> AsyncRead for Source<R>
+where
+    R: AsyncRead + Unpin,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize>> {
+        let this = self.get_mut();
+        let prefix = this.prefix();
+        if !prefix.is_empty() {
+            let n = prefix.len().min(buf.len());
+            buf[..n].copy_from_slice(&prefix[..n]);
+            this.prefix_pos += n;
+            return Poll::Ready(Ok(n));
+        }
+        match &mut this.input {
+            Input::Buffered(reader) => Pin::new(reader).poll_read(cx, buf),
+            Input::Passthrough { reader, .. } => Pin::new(reader).poll_read(cx, buf),
+        }
+    }
+}
+
+impl<R> AsyncBufRead for Source<R>
+where
+    R: AsyncRead + Unpin,
+{
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<&[u8]>> {
+        let this = self.get_mut();
+        if this.prefix_pos < this.prefix.len() {
+            return Poll::Ready(Ok(&this.prefix[this.prefix_pos..]));
+        }
+        match &mut this.input {
+            Input::Buffered(reader) => Pin::new(reader).poll_fill_buf(cx),
+            Input::Passthrough {
+                reader, fill_buf, ..
+            } => fill_buf(Pin::new(reader), cx),
+        }
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        let this = self.get_mut();
+        if this.prefix_pos < this.prefix.len() {
+            this.prefix_pos += amt;
+            return;
+        }
+        match &mut this.input {
+            Input::Buffered(reader) => Pin::new(reader).consume(amt),
+            Input::Passthrough {
+                reader, consume, ..
+            } => consume(Pin::new(reader), amt),
+        }
+    }
+}
@@ -0,0 +1,79 @@
+use std::collections::HashMap;
+use std::time::Instant;
+
+use crate::Message;
+
+/// Counters of the messages that a [`Reader`](crate::Reader) read or a
+/// [`Writer`](crate::Writer) wrote.
+///
+/// Stats are opt-in, see [`Reader::with_stats`](crate::Reader::with_stats) and
+/// [`Writer::with_stats`](crate::Writer::with_stats).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// The number of bytes read or written.
+    pub bytes: u64,
+    /// The number of messages read or written, without keepalives.
+    pub messages: u64,
+    /// The number of keepalives read or written.
+    pub keepalives: u64,
+    /// The number of errors while reading or writing.
+    pub errors: u64,
+    /// The number of messages per channel and typ.
+    pub frames: HashMap<(u64, u8), u64>,
+    /// The counters of each channel, see [`Stats::channel_stats`].
+    pub channels: HashMap<u64, ChannelStats>,
+}
+
+/// Counters of the messages on one channel, see [`Stats::channel_stats`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChannelStats {
+    /// The number of messages read or written on the channel.
+    pub messages: u64,
+    /// The number of payload bytes read or written on the channel.
+    pub bytes: u64,
+    /// When the last message on the channel was read or written.
+    pub last_activity: Instant,
+}
+
+impl Stats {
+    pub(crate) fn record<B: AsRef<[u8]>>(&mut self, message: &Message<B>, now: Instant) {
+        if message.is_keepalive() {
+            self.keepalives += 1;
+        } else {
+            self.messages += 1;
+            *self
+                .frames
+                .entry((message.channel, message.typ))
+                .or_insert(0) += 1;
+            let channel = self
+                .channels
+                .entry(message.channel)
+                .or_insert(ChannelStats {
+                    messages: 0,
+                    bytes: 0,
+                    last_activity: now,
+                });
+            channel.messages += 1;
+            channel.bytes += message.message.as_ref().len() as u64;
+            channel.last_activity = now;
+        }
+    }
+
+    /// The counters of `channel`, or `None` if no message was read or written on it.
+    pub fn channel_stats(&self, channel: u64) -> Option<&ChannelStats> {
+        self.channels.get(&channel)
+    }
+
+    /// The channels without messages since `since`, e.g. to close idle channels.
+    pub fn idle_channels(&self, since: Instant) -> impl Iterator<Item = u64> + '_ {
+        self.channels
+            .iter()
+            .filter(move |(_, stats)| stats.last_activity < since)
+            .map(|(channel, _)| *channel)
+    }
+
+    /// Forget the counters of `channel`, e.g. once it is closed.
+    pub fn remove_channel(&mut self, channel: u64) -> Option<ChannelStats> {
+        self.channels.remove(&channel)
+    }
+}
@@ -0,0 +1,193 @@
+use futures::future::poll_fn;
+use futures::io::{AsyncBufRead, AsyncRead, BufReader};
+use futures::ready;
+use futures::task::{Context, Poll};
+use std::io::{Error, ErrorKind};
+use std::pin::Pin;
+
+use crate::codec::decode_header;
+use crate::varint::{self, VarintDecoder, MAX_VARINT_LEN};
+use crate::{MessageHeader, SmcError, MAX_FRAME_LEN};
+
+/// A reader for SMC messages that streams message payloads.
+///
+/// Instead of reading whole messages into memory, this yields the [`MessageHeader`]
+/// of each message together with a [`Body`], which reads the payload from the
+/// underlying reader. This allows to e.g. write large payloads to disk without
+/// keeping a full copy in memory.
+///
+/// # Example
+///
+/// ```no_run
+/// # use async_std::io;
+/// use simple_message_channels::StreamingReader;
+/// # async_std::task::block_on(async {
+/// let mut reader = StreamingReader::new(io::stdin());
+/// loop {
+///     let (header, mut body) = reader.next().await?;
+///     eprintln!("Receiving {} bytes on channel {}", header.len, header.channel);
+///     io::copy(&mut body, &mut io::sink()).await?;
+/// }
+/// # io::Result::Ok(())
+/// # });
+/// ```
+pub struct StreamingReader<R> {
+    reader: BufReader<R>,
+    // The length of the current body, and how much of it is left.
+    len: u64,
+    remaining: u64,
+    state: State,
+}
+
+/// The decoding state of a [`StreamingReader`].
+enum State {
+    /// Reading the length prefix of the next message.
+    ReadingLength(VarintDecoder),
+    /// Reading the header of a message of `len` bytes.
+    ReadingHeader {
+        len: u64,
+        header: Vec<u8>,
+        decoder: VarintDecoder,
+    },
+}
+
+impl<R> StreamingReader<R>
+where
+    R: AsyncRead + Unpin,
+{
+    /// Create a new streaming message reader from any [`futures::io::AsyncRead`].
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader: BufReader::new(reader),
+            len: 0,
+            remaining: 0,
+            state: State::ReadingLength(VarintDecoder::new()),
+        }
+    }
+
+    /// Read the header of the next message.
+    ///
+    /// Returns the header and a [`Body`] to read the payload of the message. If the body of
+    /// the previous message was not read to the end, the rest of it is skipped.
+    ///
+    /// This is cancellation safe: if the future is dropped before it completes, the bytes
+    /// it read are kept, and the next call continues with the same message.
+    pub async fn next(&mut self) -> Result<(MessageHeader, Body<'_, R>), SmcError> {
+        let header = poll_fn(|cx| self.poll_header(cx)).await?;
+        Ok((header, Body { reader: self }))
+    }
+
+    /// Consume the streaming reader, returning the underlying reader.
+    pub fn into_inner(self) -> BufReader<R> {
+        self.reader
+    }
+
+    fn poll_header(&mut self, cx: &mut Context<'_>) -> Poll<Result<MessageHeader, SmcError>> {
+        loop {
+            let buf = ready!(Pin::new(&mut self.reader).poll_fill_buf(cx))?;
+            if buf.is_empty() {
+                let error = match &self.state {
+                    _ if self.remaining > 0 => SmcError::TruncatedFrame {
+                        expected: self.len,
+                        got: self.len - self.remaining,
+                    },
+                    State::ReadingLength(decoder) if decoder.is_empty() => {
+                        Error::from(ErrorKind::UnexpectedEof).into()
+                    }
+                    State::ReadingLength(decoder) => varint::truncated_prefix(decoder),
+                    State::ReadingHeader { len, header, .. } => SmcError::TruncatedFrame {
+                        expected: *len,
+                        got: header.len() as u64,
+                    },
+                };
+                return Poll::Ready(Err(error));
+            }
+            // Skip the rest of the previous body.
+            if self.remaining > 0 {
+                let n = buf.len().min(self.remaining as usize);
+                Pin::new(&mut self.reader).consume(n);
+                self.remaining -= n as u64;
+                continue;
+            }
+            let byte = buf[0];
+            Pin::new(&mut self.reader).consume(1);
+            match &mut self.state {
+                State::ReadingLength(decoder) => match decoder.push(byte)? {
+                    None => {}
+                    Some(len) if len > MAX_FRAME_LEN => {
+                        return Poll::Ready(Err(SmcError::MessageTooLong(len)))
+                    }
+                    // Skip empty frames (keepalives).
+                    Some(0) => decoder.reset(),
+                    Some(len) => {
+                        self.state = State::ReadingHeader {
+                            len,
+                            header: Vec::with_capacity(MAX_VARINT_LEN),
+                            decoder: VarintDecoder::new(),
+                        }
+                    }
+                },
+                State::ReadingHeader {
+                    len,
+                    header,
+                    decoder,
+                } => {
+                    header.push(byte);
+                    let done = decoder.push(byte)?.is_some();
+                    if !done && *len > header.len() as u64 {
+                        continue;
+                    }
+                    let len = *len;
+                    let result = decode_header(header);
+                    self.state = State::ReadingLength(VarintDecoder::new());
+                    let (channel, typ, headerlen) = result?;
+                    self.len = len;
+                    self.remaining = len - headerlen as u64;
+                    return Poll::Ready(Ok(MessageHeader {
+                        channel,
+                        typ,
+                        len: self.remaining,
+                    }));
+                }
+            }
+        }
+    }
+}
+
+/// The payload of a message read by a [`StreamingReader`].
+///
+/// Reads at most the length of the payload from the underlying reader.
+pub struct Body<'a, R> {
+    reader: &'a mut StreamingReader<R>,
+}
+
+impl<R> Body<'_, R> {
+    /// The number of payload bytes that have not been read yet.
+    pub fn remaining(&self) -> u64 {
+        self.reader.remaining
+    }
+}
+
+impl<R> AsyncRead for Body<'_, R>
+where
+    R: AsyncRead + Unpin,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, Error>> {
+        let remaining = self.reader.remaining;
+        if remaining == 0 {
+            return Poll::Ready(Ok(0));
+        }
+        let max = buf.len().min(remaining as usize);
+        match Pin::new(&mut self.reader.reader).poll_read(cx, &mut buf[..max]) {
+            Poll::Ready(Ok(n)) => {
+                self.reader.remaining -= n as u64;
+                Poll::Ready(Ok(n))
+            }
+            poll => poll,
+        }
+    }
+}
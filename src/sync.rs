@@ -0,0 +1,240 @@
+//! Blocking reader and writer for [`std::io`] streams.
+//!
+//! These work like [`crate::Reader`] and [`crate::Writer`], but block on the
+//! underlying stream instead of being async.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use simple_message_channels::sync::{Reader, Writer};
+//! use simple_message_channels::Message;
+//! # fn main() -> Result<(), simple_message_channels::SmcError> {
+//! let mut writer = Writer::new(std::io::stdout());
+//! for msg in Reader::new(std::io::stdin()) {
+//!     let msg = msg?;
+//!     writer.send(Message::new(msg.channel, msg.typ + 1, msg.message))?;
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::io::{BufReader, ErrorKind, Read, Write};
+
+use crate::codec::decode_frame_buf;
+use crate::varint::{self, VarintDecoder, VarintError, MAX_VARINT_LEN};
+use crate::{Message, Payload, SmcError, MAX_FRAME_LEN};
+
+/// A blocking reader for SMC messages.
+///
+/// Takes any [`std::io::Read`] and is an [`Iterator`] of [`Message`]s. The iterator ends
+/// at the end of the stream, and after an error.
+pub struct Reader<R> {
+    reader: BufReader<R>,
+    keepalives: bool,
+    eof_error: bool,
+    // Whether the stream ended between two frames.
+    eof: bool,
+    finished: bool,
+}
+
+impl<R> Reader<R>
+where
+    R: Read,
+{
+    /// Create a new blocking message reader.
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader: BufReader::new(reader),
+            keepalives: false,
+            eof_error: false,
+            eof: false,
+            finished: false,
+        }
+    }
+
+    /// Set whether keepalive frames are yielded.
+    ///
+    /// See [`crate::Reader::with_keepalives`].
+    pub fn with_keepalives(mut self, keepalives: bool) -> Self {
+        self.keepalives = keepalives;
+        self
+    }
+
+    /// Set whether the end of the stream is an error for the iterator.
+    ///
+    /// See [`crate::Reader::with_eof_error`].
+    pub fn with_eof_error(mut self, eof_error: bool) -> Self {
+        self.eof_error = eof_error;
+        self
+    }
+
+    /// Consume the reader, returning the underlying reader and the bytes that were
+    /// read from it but not parsed yet.
+    ///
+    /// See [`crate::Reader::into_inner`].
+    pub fn into_inner(self) -> (R, Vec<u8>) {
+        let buffered = self.reader.buffer().to_vec();
+        (self.reader.into_inner(), buffered)
+    }
+
+    /// Read the next message.
+    ///
+    /// At the end of the stream, this fails with an
+    /// [`std::io::ErrorKind::UnexpectedEof`] error.
+    pub fn read<B: Payload>(&mut self) -> Result<Message<B>, SmcError> {
+        loop {
+            let len = self.read_length()?;
+            if len == 0 {
+                if self.keepalives {
+                    return Ok(Message::keepalive());
+                }
+                continue;
+            }
+            let mut buf = vec![0u8; len as usize];
+            self.read_frame(&mut buf)?;
+            return decode_frame_buf(buf);
+        }
+    }
+
+    fn read_length(&mut self) -> Result<u64, SmcError> {
+        let mut decoder = VarintDecoder::new();
+        let len = match read_varint_with(&mut self.reader, &mut decoder) {
+            Err(SmcError::Io(error)) if error.kind() == ErrorKind::UnexpectedEof => {
+                self.eof = true;
+                return Err(error.into());
+            }
+            Err(SmcError::Varint(VarintError::Incomplete)) => {
+                return Err(varint::truncated_prefix(&decoder))
+            }
+            result => result?,
+        };
+        if len > MAX_FRAME_LEN {
+            return Err(SmcError::MessageTooLong(len));
+        }
+        Ok(len)
+    }
+
+    // Like `read_exact`, but fails with `SmcError::TruncatedFrame` at EOF.
+    fn read_frame(&mut self, buf: &mut [u8]) -> Result<(), SmcError> {
+        let mut got = 0;
+        while got < buf.len() {
+            match self.reader.read(&mut buf[got..]) {
+                Ok(0) => {
+                    return Err(SmcError::TruncatedFrame {
+                        expected: buf.len() as u64,
+                        got: got as u64,
+                    })
+                }
+                Ok(n) => got += n,
+                Err(error) if error.kind() == ErrorKind::Interrupted => {}
+                Err(error) => return Err(error.into()),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<R> Iterator for Reader<R>
+where
+    R: Read,
+{
+    type Item = Result<Message, SmcError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+        let result = self.read();
+        if result.is_err() {
+            self.finished = true;
+            if self.eof && !self.eof_error {
+                return None;
+            }
+        }
+        Some(result)
+    }
+}
+
+/// A blocking writer for SMC messages.
+///
+/// Takes any [`std::io::Write`] to which messages will be written.
+pub struct Writer<W> {
+    writer: W,
+    buf: Vec<u8>,
+}
+
+impl<W> Writer<W>
+where
+    W: Write,
+{
+    /// Create a new blocking message writer.
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            buf: Vec::new(),
+        }
+    }
+
+    /// Send a message.
+    ///
+    /// This encodes the message, writes it and flushes the writer.
+    pub fn send<B: AsRef<[u8]>>(&mut self, message: Message<B>) -> Result<(), SmcError> {
+        self.send_batch(&[message])
+    }
+
+    /// Send a batch of messages.
+    ///
+    /// All messages are encoded into a single buffer, which is written at once.
+    pub fn send_batch<B: AsRef<[u8]>>(&mut self, messages: &[Message<B>]) -> Result<(), SmcError> {
+        self.buf.clear();
+        for message in messages {
+            message.encode_into(&mut self.buf)?;
+        }
+        self.writer.write_all(&self.buf)?;
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    /// Send a keepalive.
+    pub fn send_keepalive(&mut self) -> Result<(), SmcError> {
+        self.send(Message::<Vec<u8>>::keepalive())
+    }
+
+    /// Consume the writer, returning the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// Read a varint from `reader`.
+///
+/// See [`varint::read_varint`].
+pub fn read_varint<R: Read>(reader: &mut R) -> Result<u64, SmcError> {
+    read_varint_with(reader, &mut VarintDecoder::new())
+}
+
+// Read a varint with `decoder`, which keeps the bytes that were read at EOF.
+fn read_varint_with<R: Read>(reader: &mut R, decoder: &mut VarintDecoder) -> Result<u64, SmcError> {
+    let mut byte = [0u8; 1];
+    loop {
+        match reader.read(&mut byte) {
+            Ok(0) => return Err(varint::eof(decoder)),
+            Ok(_) => {}
+            Err(error) if error.kind() == ErrorKind::Interrupted => continue,
+            Err(error) => return Err(error.into()),
+        }
+        if let Some(value) = decoder.push(byte[0])? {
+            return Ok(value);
+        }
+    }
+}
+
+/// Write `value` as a varint to `writer`, returning the number of bytes written.
+///
+/// See [`varint::write_varint`].
+pub fn write_varint<W: Write>(writer: &mut W, value: u64) -> Result<usize, SmcError> {
+    let mut buf = [0u8; MAX_VARINT_LEN];
+    let len = varint::encode(value, &mut buf);
+    writer.write_all(&buf[..len])?;
+    Ok(len)
+}
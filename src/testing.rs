@@ -0,0 +1,481 @@
+//! Helpers to test protocols on top of SMC.
+//!
+//! Enabled with the `testing` feature. [`RecordingTransport`] records the messages of a
+//! session to a file, and [`ReplayReader`] plays them back, to build deterministic
+//! tests from real sessions. [`MockStream`] injects partial reads, delays and errors, to
+//! test how a protocol handles them.
+//!
+//! The `arbitrary` feature enables this module too, and also implements
+//! [`arbitrary::Arbitrary`] for [`Message`], to property-test protocols.
+
+use futures::io::{AsyncRead, AsyncWrite};
+use futures::sink::Sink;
+use futures::stream::Stream;
+use futures::task::{Context, Poll};
+use futures::FutureExt;
+use futures_timer::Delay;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::path::Path;
+use std::pin::Pin;
+use std::time::{Duration, Instant};
+
+use crate::codec::{decode_frame, decode_frame_buf};
+use crate::debug::Direction;
+use crate::varint;
+use crate::{Message, SmcError, MAX_FRAME_LEN};
+
+#[cfg(feature = "arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for Message {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        let channel = u.int_in_range(0..=crate::MAX_CHANNEL)?;
+        let typ = u.int_in_range(0..=15)?;
+        let message = u.arbitrary()?;
+        Ok(Message::new(channel, typ, message))
+    }
+}
+
+/// Assert that `message` encodes and decodes back to the same message.
+///
+/// # Panics
+///
+/// If `message` fails to encode, its encoding fails to decode, or the decoded
+/// message is different.
+pub fn assert_roundtrip<B: AsRef<[u8]>>(message: &Message<B>) {
+    let buf = message.encode().expect("message failed to encode");
+    let (decoded, len) = decode_frame(&buf)
+        .expect("encoded message failed to decode")
+        .expect("encoded message is incomplete");
+    assert_eq!(len, buf.len(), "decoded length differs from encoded length");
+    assert_eq!(decoded.channel, message.channel, "channel differs");
+    assert_eq!(decoded.typ, message.typ, "typ differs");
+    assert_eq!(decoded.message, message.message.as_ref(), "payload differs");
+}
+
+/// A message recorded by a [`RecordingTransport`].
+#[derive(Debug)]
+pub struct Record {
+    /// Whether the message was read or written.
+    pub direction: Direction,
+    /// The time since the recording started.
+    pub elapsed: Duration,
+    /// The message.
+    pub message: Message,
+}
+
+/// Records every message that passes through a stream or a sink of messages.
+///
+/// This wraps e.g. a [`Reader`](crate::Reader), a [`Writer`](crate::Writer) or a
+/// [`Channel`](crate::Channel), and writes each message that is read or written to
+/// an [`io::Write`], together with its direction and the time since the recording
+/// started. Messages are recorded after decryption and decompression. The recording
+/// can be played back with a [`ReplayReader`].
+///
+/// Records are written with blocking IO, which is fine for tests.
+pub struct RecordingTransport<T, W = BufWriter<File>> {
+    inner: T,
+    out: W,
+    start: Instant,
+    buf: Vec<u8>,
+}
+
+impl<T> RecordingTransport<T> {
+    /// Record the messages of `inner` to a new file at `path`.
+    pub fn create<P: AsRef<Path>>(inner: T, path: P) -> io::Result<Self> {
+        let file = File::create(path)?;
+        Ok(Self::new(inner, BufWriter::new(file)))
+    }
+}
+
+impl<T, W> RecordingTransport<T, W>
+where
+    W: Write,
+{
+    /// Record the messages of `inner` to `out`.
+    pub fn new(inner: T, out: W) -> Self {
+        Self {
+            inner,
+            out,
+            start: Instant::now(),
+            buf: Vec::new(),
+        }
+    }
+
+    /// Get a mutable reference to the wrapped stream or sink.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    /// Unwrap the wrapped stream or sink and the output of the recording.
+    ///
+    /// The output is not flushed.
+    pub fn into_inner(self) -> (T, W) {
+        (self.inner, self.out)
+    }
+
+    // A record is the direction, the elapsed microseconds as a varint and the message
+    // as a frame.
+    fn record<B: AsRef<[u8]>>(
+        &mut self,
+        direction: Direction,
+        message: &Message<B>,
+    ) -> Result<(), SmcError> {
+        let elapsed = self.start.elapsed().as_micros() as u64;
+        let mut header = [0u8; 1 + varint::MAX_VARINT_LEN];
+        header[0] = match direction {
+            Direction::Read => 0,
+            Direction::Write => 1,
+        };
+        let len = 1 + varint::encode(elapsed, &mut header[1..]);
+        self.buf.clear();
+        self.buf.extend_from_slice(&header[..len]);
+        message.encode_into(&mut self.buf)?;
+        self.out.write_all(&self.buf)?;
+        Ok(())
+    }
+}
+
+impl<T, W, B> Stream for RecordingTransport<T, W>
+where
+    T: Stream<Item = Result<Message<B>, SmcError>> + Unpin,
+    W: Write + Unpin,
+    B: AsRef<[u8]>,
+{
+    type Item = Result<Message<B>, SmcError>;
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Message<B>, SmcError>>> {
+        let this = self.get_mut();
+        let item = futures::ready!(Pin::new(&mut this.inner).poll_next(cx));
+        if let Some(Ok(message)) = &item {
+            this.record(Direction::Read, message)?;
+        }
+        Poll::Ready(item)
+    }
+}
+
+impl<T, W, B> Sink<Message<B>> for RecordingTransport<T, W>
+where
+    T: Sink<Message<B>, Error = SmcError> + Unpin,
+    W: Write + Unpin,
+    B: AsRef<[u8]>,
+{
+    type Error = SmcError;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), SmcError>> {
+        Pin::new(&mut self.get_mut().inner).poll_ready(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, message: Message<B>) -> Result<(), SmcError> {
+        let this = self.get_mut();
+        this.record(Direction::Write, &message)?;
+        Pin::new(&mut this.inner).start_send(message)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), SmcError>> {
+        let this = self.get_mut();
+        this.out.flush()?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), SmcError>> {
+        let this = self.get_mut();
+        this.out.flush()?;
+        Pin::new(&mut this.inner).poll_close(cx)
+    }
+}
+
+/// Read the records of a recording made with a [`RecordingTransport`].
+pub fn read_records<R: Read>(mut reader: R) -> Result<Vec<Record>, SmcError> {
+    let mut buf = Vec::new();
+    reader.read_to_end(&mut buf)?;
+    let mut records = Vec::new();
+    let mut pos = 0;
+    while pos < buf.len() {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Invalid record");
+        let direction = match buf[pos] {
+            0 => Direction::Read,
+            1 => Direction::Write,
+            _ => return Err(invalid().into()),
+        };
+        pos += 1;
+        let (elapsed, n) = varint::decode(&buf[pos..])?;
+        pos += n;
+        let (len, n) = varint::decode(&buf[pos..])?;
+        if len > MAX_FRAME_LEN {
+            return Err(SmcError::MessageTooLong(len));
+        }
+        pos += n;
+        let end = pos + len as usize;
+        let frame = buf.get(pos..end).ok_or_else(invalid)?;
+        let message = decode_frame_buf(frame.to_vec())?;
+        pos = end;
+        records.push(Record {
+            direction,
+            elapsed: Duration::from_micros(elapsed),
+            message,
+        });
+    }
+    Ok(records)
+}
+
+/// Plays back the messages of a recording made with a [`RecordingTransport`].
+///
+/// This is a stream of the messages that were read during the recording, in their
+/// order, so it can stand in for the [`Reader`](crate::Reader) of a test. The messages
+/// that were written are available with [`ReplayReader::written`], to compare them
+/// with the messages written in the test.
+///
+/// # Example
+///
+/// ```no_run
+/// # use futures::stream::StreamExt;
+/// use simple_message_channels::testing::ReplayReader;
+/// # async_std::task::block_on(async {
+/// let mut replay = ReplayReader::open("session.smc")?;
+/// while let Some(message) = replay.next().await {
+///     let message = message?;
+///     println!("Replaying: ch {} typ {}", message.channel, message.typ);
+/// }
+/// # Ok::<(), simple_message_channels::SmcError>(())
+/// # });
+/// ```
+pub struct ReplayReader {
+    read: VecDeque<Record>,
+    written: Vec<Message>,
+    timing: bool,
+    start: Option<Instant>,
+    delay: Option<Delay>,
+}
+
+impl ReplayReader {
+    /// Open the recording at `path`.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, SmcError> {
+        Ok(Self::new(read_records(File::open(path)?)?))
+    }
+
+    /// Play back `records`.
+    pub fn new(records: Vec<Record>) -> Self {
+        let mut read = VecDeque::new();
+        let mut written = Vec::new();
+        for record in records {
+            match record.direction {
+                Direction::Read => read.push_back(record),
+                Direction::Write => written.push(record.message),
+            }
+        }
+        Self {
+            read,
+            written,
+            timing: false,
+            start: None,
+            delay: None,
+        }
+    }
+
+    /// Yield messages with the same timing as during the recording.
+    ///
+    /// By default, messages are yielded as fast as they are polled.
+    pub fn with_timing(mut self, timing: bool) -> Self {
+        self.timing = timing;
+        self
+    }
+
+    /// The messages that were written during the recording.
+    pub fn written(&self) -> &[Message] {
+        &self.written
+    }
+}
+
+impl Stream for ReplayReader {
+    type Item = Result<Message, SmcError>;
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        let record = match this.read.front() {
+            Some(record) => record,
+            None => return Poll::Ready(None),
+        };
+        if this.timing {
+            let start = *this.start.get_or_insert_with(Instant::now);
+            if let Some(wait) = record.elapsed.checked_sub(start.elapsed()) {
+                let delay = this.delay.get_or_insert_with(|| Delay::new(wait));
+                futures::ready!(delay.poll_unpin(cx));
+            }
+            this.delay = None;
+        }
+        let record = this.read.pop_front().expect("record exists");
+        Poll::Ready(Some(Ok(record.message)))
+    }
+}
+
+/// A scripted step of a [`MockStream`].
+enum Step {
+    Data(Vec<u8>),
+    Delay(Duration),
+    Eof,
+    Error(io::ErrorKind),
+}
+
+/// An IO stream for tests that reads a script of bytes and faults.
+///
+/// Reads follow the steps that were added with the `with_` methods, in order: bytes are
+/// read in chunks of at most [`MockStream::with_max_read`] bytes and never across two
+/// steps, a delay makes reads wait, and an EOF or an error is returned once. After the
+/// last step, reads return EOF. Written bytes are collected, see
+/// [`MockStream::written`].
+///
+/// # Example
+///
+/// ```
+/// # use futures::stream::StreamExt;
+/// use simple_message_channels::testing::MockStream;
+/// use simple_message_channels::{Message, Reader};
+/// # futures::executor::block_on(async {
+/// let frame = Message::new(1, 2, b"hello".to_vec()).encode()?;
+/// // Deliver the frame one byte at a time, then disconnect in the middle of a frame.
+/// let stream = MockStream::new()
+///     .with_max_read(1)
+///     .with_read(&frame)
+///     .with_read(&frame[..3])
+///     .with_eof();
+/// let mut reader = Reader::new(stream);
+/// assert_eq!(reader.next().await.unwrap()?.message, b"hello");
+/// assert!(reader.next().await.unwrap().is_err());
+/// # Ok::<(), simple_message_channels::SmcError>(())
+/// # });
+/// ```
+#[derive(Default)]
+pub struct MockStream {
+    steps: VecDeque<Step>,
+    max_read: Option<usize>,
+    max_write: Option<usize>,
+    delay: Option<Delay>,
+    written: Vec<u8>,
+}
+
+impl MockStream {
+    /// Create a stream with an empty script, which reads EOF.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read `bytes`.
+    pub fn with_read(mut self, bytes: &[u8]) -> Self {
+        if !bytes.is_empty() {
+            self.steps.push_back(Step::Data(bytes.to_vec()));
+        }
+        self
+    }
+
+    /// Read `bytes`, split into separate reads at the offsets in `at`.
+    ///
+    /// Use this to split frames across reads at arbitrary byte boundaries.
+    pub fn with_read_split(mut self, bytes: &[u8], at: &[usize]) -> Self {
+        let mut start = 0;
+        for &end in at.iter().chain(Some(&bytes.len())) {
+            let end = end.clamp(start, bytes.len());
+            self = self.with_read(&bytes[start..end]);
+            start = end;
+        }
+        self
+    }
+
+    /// Wait for `delay` before the next read.
+    pub fn with_delay(mut self, delay: Duration) -> Self {
+        self.steps.push_back(Step::Delay(delay));
+        self
+    }
+
+    /// Read EOF, e.g. in the middle of a frame.
+    pub fn with_eof(mut self) -> Self {
+        self.steps.push_back(Step::Eof);
+        self
+    }
+
+    /// Fail the next read with an error of `kind`.
+    pub fn with_error(mut self, kind: io::ErrorKind) -> Self {
+        self.steps.push_back(Step::Error(kind));
+        self
+    }
+
+    /// Read at most `max` bytes at a time, to simulate partial reads.
+    pub fn with_max_read(mut self, max: usize) -> Self {
+        self.max_read = Some(max.max(1));
+        self
+    }
+
+    /// Write at most `max` bytes at a time, to simulate partial writes.
+    pub fn with_max_write(mut self, max: usize) -> Self {
+        self.max_write = Some(max.max(1));
+        self
+    }
+
+    /// The bytes that were written to the stream.
+    pub fn written(&self) -> &[u8] {
+        &self.written
+    }
+}
+
+impl AsyncRead for MockStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        loop {
+            match this.steps.front_mut() {
+                None => return Poll::Ready(Ok(0)),
+                Some(Step::Data(data)) => {
+                    let max = this.max_read.unwrap_or(usize::MAX);
+                    let n = data.len().min(buf.len()).min(max);
+                    buf[..n].copy_from_slice(&data[..n]);
+                    data.drain(..n);
+                    if data.is_empty() {
+                        this.steps.pop_front();
+                    }
+                    return Poll::Ready(Ok(n));
+                }
+                Some(Step::Delay(delay)) => {
+                    let delay = *delay;
+                    let timer = this.delay.get_or_insert_with(|| Delay::new(delay));
+                    futures::ready!(timer.poll_unpin(cx));
+                    this.delay = None;
+                    this.steps.pop_front();
+                }
+                Some(Step::Eof) => {
+                    this.steps.pop_front();
+                    return Poll::Ready(Ok(0));
+                }
+                Some(Step::Error(kind)) => {
+                    let kind = *kind;
+                    this.steps.pop_front();
+                    return Poll::Ready(Err(io::Error::new(kind, "Injected error")));
+                }
+            }
+        }
+    }
+}
+
+impl AsyncWrite for MockStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let n = buf.len().min(this.max_write.unwrap_or(usize::MAX));
+        this.written.extend_from_slice(&buf[..n]);
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
@@ -0,0 +1,122 @@
+use futures::io::{AsyncRead, AsyncWrite};
+use futures::task::{Context, Poll};
+use std::io::Result;
+use std::pin::Pin;
+
+use crate::Channel;
+
+/// A duplex byte stream to send SMC messages over.
+///
+/// This is the smallest interface a transport needs, e.g. for a custom transport over
+/// an FFI boundary or a message queue. It is implemented for all
+/// [`futures::io::AsyncRead`] + [`futures::io::AsyncWrite`] types, which includes the
+/// IO types of async-std and smol, tokio IO types wrapped in a `Compat`, and the
+/// in-memory [`PipeStream`](crate::PipeStream). Wrap other transports in a
+/// [`TransportIo`] to use them with a [`Reader`](crate::Reader), a
+/// [`Writer`](crate::Writer) or a [`Channel`], e.g. with [`Channel::from_transport`].
+///
+/// The reader and the writer are not generic over this trait: each of them only uses
+/// one direction, and stays generic over [`futures::io::AsyncRead`] or
+/// [`futures::io::AsyncWrite`], so that e.g. stdin and stdout can be used on their own.
+pub trait Transport {
+    /// Read bytes into `buf`, returning how many were read. `0` is the end of the stream.
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8])
+        -> Poll<Result<usize>>;
+
+    /// Write bytes from `buf`, returning how many were written.
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>>;
+
+    /// Flush the written bytes.
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>>;
+
+    /// Flush the written bytes, and close the writing side.
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>>;
+}
+
+impl<T> Transport for T
+where
+    T: AsyncRead + AsyncWrite + ?Sized,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize>> {
+        AsyncRead::poll_read(self, cx, buf)
+    }
+
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+        AsyncWrite::poll_write(self, cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        AsyncWrite::poll_flush(self, cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        AsyncWrite::poll_close(self, cx)
+    }
+}
+
+/// Wraps a [`Transport`] so that it implements the [`futures::io`] traits.
+#[derive(Debug)]
+pub struct TransportIo<T> {
+    inner: T,
+}
+
+impl<T> TransportIo<T> {
+    /// Wrap a transport.
+    pub fn new(inner: T) -> Self {
+        Self { inner }
+    }
+
+    /// Get a reference to the wrapped transport.
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    /// Unwrap the wrapped transport.
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T> AsyncRead for TransportIo<T>
+where
+    T: Transport + Unpin,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize>> {
+        Transport::poll_read(Pin::new(&mut self.get_mut().inner), cx, buf)
+    }
+}
+
+impl<T> AsyncWrite for TransportIo<T>
+where
+    T: Transport + Unpin,
+{
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+        Transport::poll_write(Pin::new(&mut self.get_mut().inner), cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Transport::poll_flush(Pin::new(&mut self.get_mut().inner), cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Transport::poll_close(Pin::new(&mut self.get_mut().inner), cx)
+    }
+}
+
+impl<T> Channel<TransportIo<T>>
+where
+    T: Transport + Unpin,
+{
+    /// Create a new channel over any [`Transport`].
+    pub fn from_transport(transport: T) -> Self {
+        Channel::new(TransportIo::new(transport))
+    }
+}
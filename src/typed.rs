@@ -0,0 +1,90 @@
+use alloc::vec::Vec;
+
+use crate::{Message, SmcError};
+
+/// A message type with its own encoding.
+///
+/// Implement this for a type to send it with [`crate::Writer::send_typed`] and to
+/// decode it with [`Message::parse`].
+///
+/// With the `serde` feature enabled, this is implemented for all types that
+/// implement [`SerdeMessage`], encoding them with [bincode](https://docs.rs/bincode).
+pub trait TypedMessage: Sized {
+    /// The typ of messages of this type.
+    fn typ() -> u8;
+
+    /// Encode the value into a message payload.
+    fn encode(&self) -> Result<Vec<u8>, SmcError>;
+
+    /// Decode a value from a message payload.
+    fn decode(buf: &[u8]) -> Result<Self, SmcError>;
+}
+
+impl Message {
+    /// Create a new message from a typed value.
+    ///
+    /// The typ of the message is [`TypedMessage::typ`].
+    pub fn from_typed<T: TypedMessage>(channel: u64, value: &T) -> Result<Message, SmcError> {
+        Ok(Message::new(channel, T::typ(), value.encode()?))
+    }
+}
+
+impl<B> Message<B>
+where
+    B: AsRef<[u8]>,
+{
+    /// Decode the payload of this message as a typed value.
+    ///
+    /// Fails with [`SmcError::UnexpectedTyp`] if the typ of the message is not
+    /// [`TypedMessage::typ`].
+    pub fn parse<T: TypedMessage>(&self) -> Result<T, SmcError> {
+        if self.typ != T::typ() {
+            return Err(SmcError::UnexpectedTyp(self.typ));
+        }
+        T::decode(self.message.as_ref())
+    }
+}
+
+/// A message type that is encoded with [bincode](https://docs.rs/bincode).
+///
+/// Enabled with the `serde` feature.
+///
+/// # Example
+///
+/// ```
+/// use serde::{Deserialize, Serialize};
+/// use simple_message_channels::{Message, SerdeMessage};
+///
+/// #[derive(Serialize, Deserialize, Debug, PartialEq)]
+/// struct Hello {
+///     name: String,
+/// }
+///
+/// impl SerdeMessage for Hello {
+///     const TYP: u8 = 1;
+/// }
+///
+/// let hello = Hello { name: "world".into() };
+/// let message = Message::from_typed(0, &hello).unwrap();
+/// assert_eq!(message.parse::<Hello>().unwrap(), hello);
+/// ```
+#[cfg(feature = "serde")]
+pub trait SerdeMessage: serde::Serialize + serde::de::DeserializeOwned {
+    /// The typ of messages of this type.
+    const TYP: u8;
+}
+
+#[cfg(feature = "serde")]
+impl<T: SerdeMessage> TypedMessage for T {
+    fn typ() -> u8 {
+        T::TYP
+    }
+
+    fn encode(&self) -> Result<Vec<u8>, SmcError> {
+        bincode::serialize(self).map_err(|error| SmcError::Payload(error))
+    }
+
+    fn decode(buf: &[u8]) -> Result<Self, SmcError> {
+        bincode::deserialize(buf).map_err(|error| SmcError::Payload(error))
+    }
+}
@@ -0,0 +1,221 @@
+//! Varints, as used in the SMC encoding.
+//!
+//! The SMC protocol uses unsigned LEB128 varints (as implemented by the JavaScript
+//! [varint](https://github.com/chrisdickinson/varint) module) for the length prefix
+//! and the header of messages. The decoder here rejects varints that do not fit
+//! into a `u64`, and varints that are not encoded in their shortest form.
+//! The encoder always writes the shortest form.
+//!
+//! [`decode`], [`encode`] and [`length`] work with byte slices, and [`VarintDecoder`]
+//! decodes a varint one byte at a time. With the `std` feature enabled, [`read_varint`]
+//! and [`write_varint`] read and write varints with the [`futures::io`] traits, and
+//! [`sync::read_varint`](crate::sync::read_varint) and
+//! [`sync::write_varint`](crate::sync::write_varint) with the blocking [`std::io`] traits.
+
+use core::fmt;
+#[cfg(feature = "std")]
+use futures::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+#[cfg(feature = "std")]
+use crate::SmcError;
+
+/// The max length of a varint that fits into a `u64` (in bytes).
+pub const MAX_VARINT_LEN: usize = 10;
+
+/// An error when decoding a varint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VarintError {
+    /// The varint encodes a value that does not fit into a `u64`.
+    Overflow,
+    /// The varint has trailing zero bytes, i.e. it is not encoded in its shortest form.
+    NonCanonical,
+    /// The varint is longer than 10 bytes.
+    TooLong,
+    /// The buffer ended before the varint was complete.
+    Incomplete,
+}
+
+impl fmt::Display for VarintError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VarintError::Overflow => write!(f, "Varint overflows u64"),
+            VarintError::NonCanonical => write!(f, "Varint is not canonical"),
+            VarintError::TooLong => write!(f, "Varint is too long"),
+            VarintError::Incomplete => write!(f, "Varint is incomplete"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for VarintError {}
+
+/// An incremental varint decoder.
+///
+/// Bytes are pushed one at a time, which allows to decode varints from a stream
+/// without reading past their end.
+#[derive(Debug, Default, Clone)]
+pub struct VarintDecoder {
+    value: u64,
+    len: usize,
+}
+
+impl VarintDecoder {
+    /// Create a new varint decoder.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Push the next byte.
+    ///
+    /// Returns the decoded value once the last byte of the varint was pushed.
+    /// After that (or after an error), the decoder is reset and can be reused.
+    pub fn push(&mut self, byte: u8) -> Result<Option<u64>, VarintError> {
+        let result = self.push_byte(byte);
+        if !matches!(result, Ok(None)) {
+            self.reset();
+        }
+        result
+    }
+
+    /// Returns `true` if no bytes of the current varint were pushed.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // The bytes of the current varint that were pushed, which all have the continuation
+    // bit set.
+    #[cfg(feature = "std")]
+    pub(crate) fn pushed(&self) -> Vec<u8> {
+        (0..self.len)
+            .map(|i| (self.value >> (7 * i)) as u8 | 0x80)
+            .collect()
+    }
+
+    /// Reset the decoder, discarding any pushed bytes.
+    pub fn reset(&mut self) {
+        self.value = 0;
+        self.len = 0;
+    }
+
+    fn push_byte(&mut self, byte: u8) -> Result<Option<u64>, VarintError> {
+        if self.len == MAX_VARINT_LEN {
+            return Err(VarintError::TooLong);
+        }
+        let bits = (byte & 0x7f) as u64;
+        // The last byte of a u64 varint can only carry one bit.
+        if self.len == MAX_VARINT_LEN - 1 && bits > 1 {
+            return Err(VarintError::Overflow);
+        }
+        self.value |= bits << (7 * self.len);
+        self.len += 1;
+        if byte & 0x80 != 0 {
+            return Ok(None);
+        }
+        if byte == 0 && self.len > 1 {
+            return Err(VarintError::NonCanonical);
+        }
+        Ok(Some(self.value))
+    }
+}
+
+/// Decode a varint from the start of `buf`.
+///
+/// Returns the decoded value and the number of bytes it was encoded in.
+#[inline]
+pub fn decode(buf: &[u8]) -> Result<(u64, usize), VarintError> {
+    // Most varints in a stream, e.g. small headers, are a single byte.
+    if let Some(&byte) = buf.first() {
+        if byte < 0x80 {
+            return Ok((byte as u64, 1));
+        }
+    }
+    let mut decoder = VarintDecoder::new();
+    for (i, byte) in buf.iter().enumerate() {
+        if let Some(value) = decoder.push(*byte)? {
+            return Ok((value, i + 1));
+        }
+    }
+    Err(VarintError::Incomplete)
+}
+
+/// The number of bytes `value` is encoded in.
+#[inline]
+pub fn length(value: u64) -> usize {
+    let bits = 64 - (value | 1).leading_zeros() as usize;
+    bits.div_ceil(7)
+}
+
+/// Encode `value` into the start of `buf`.
+///
+/// Returns the number of bytes written. `buf` has to be at least
+/// [`length`] bytes long.
+#[inline]
+pub fn encode(mut value: u64, buf: &mut [u8]) -> usize {
+    let mut i = 0;
+    while value >= 0x80 {
+        buf[i] = (value as u8) | 0x80;
+        value >>= 7;
+        i += 1;
+    }
+    buf[i] = value as u8;
+    i + 1
+}
+
+/// Read a varint from `reader`.
+///
+/// This reads one byte at a time, and no bytes after the varint, so wrap unbuffered
+/// readers in a [`futures::io::BufReader`]. Fails with an error of kind
+/// [`std::io::ErrorKind::UnexpectedEof`] if the reader ends before the varint, and with
+/// [`VarintError::Incomplete`] if it ends in the middle of it.
+#[cfg(feature = "std")]
+pub async fn read_varint<R>(reader: &mut R) -> Result<u64, SmcError>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut decoder = VarintDecoder::new();
+    let mut byte = [0u8; 1];
+    loop {
+        match reader.read(&mut byte).await {
+            Ok(0) => return Err(eof(&decoder)),
+            Ok(_) => {}
+            Err(error) if error.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(error) => return Err(error.into()),
+        }
+        if let Some(value) = decoder.push(byte[0])? {
+            return Ok(value);
+        }
+    }
+}
+
+/// Write `value` as a varint to `writer`, returning the number of bytes written.
+///
+/// The writer is not flushed.
+#[cfg(feature = "std")]
+pub async fn write_varint<W>(writer: &mut W, value: u64) -> Result<usize, SmcError>
+where
+    W: AsyncWrite + Unpin,
+{
+    let mut buf = [0u8; MAX_VARINT_LEN];
+    let len = encode(value, &mut buf);
+    writer.write_all(&buf[..len]).await?;
+    Ok(len)
+}
+
+// The error when a reader ends before the varint in `decoder` is complete.
+#[cfg(feature = "std")]
+pub(crate) fn eof(decoder: &VarintDecoder) -> SmcError {
+    if decoder.is_empty() {
+        std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into()
+    } else {
+        VarintError::Incomplete.into()
+    }
+}
+
+// The error when a frame ends in the middle of its length prefix in `decoder`.
+#[cfg(feature = "std")]
+pub(crate) fn truncated_prefix(decoder: &VarintDecoder) -> SmcError {
+    SmcError::TruncatedFrame {
+        expected: 0,
+        got: decoder.len as u64,
+    }
+}
@@ -1,44 +1,1320 @@
-use std::io::Error;
-use futures::io::{AsyncWrite,AsyncWriteExt,BufWriter};
-use crate::Message;
+#[cfg(feature = "checksum")]
+use crate::checksum::Checksum;
+use crate::cipher::{can_seek, CipherStart, FrameCipher, NoCipher, SeekableCipher};
+use crate::clock::{system_clock, SharedClock, Sleep};
+use crate::codec::{encode_frame_into, MAX_HEADER_LEN};
+#[cfg(any(feature = "lz4", feature = "zstd"))]
+use crate::compression::{self, Codec};
+use crate::debug::{Direction, FrameObserver};
+use crate::fragment;
+use crate::lifecycle::{ConnectionEvent, EventSender};
+use crate::rate::RateLimit;
+use crate::session::StreamState;
+use crate::stats::{ChannelStats, Stats};
+use crate::varint;
+use crate::{Message, SmcError, TypedMessage, CLOSE_TYP};
+use futures::future::{poll_fn, BoxFuture, FutureExt};
+use futures::io::AsyncWrite;
+use futures::ready;
+use futures::sink::Sink;
+use futures::stream::Stream;
+use futures::task::{Context, Poll};
+use std::collections::VecDeque;
+use std::io::{Error, ErrorKind, IoSlice};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// The default max number of bytes a [`Writer`] queues before applying backpressure.
+pub const DEFAULT_MAX_BUFFERED: usize = 64 * 1024;
+
+/// The number of bytes that are moved from the priority queues to the write buffer at once.
+///
+/// The frames of a send are moved together, so a message can be delayed by this many
+/// bytes of lower priority messages, plus one lower priority send of any length, i.e. a
+/// message with all its fragments, or a batch.
+const COMMIT_LEN: usize = 8 * 1024;
+
+/// The number of bytes that are encrypted on one thread, see
+/// [`Writer::with_parallel_cipher`].
+#[cfg(feature = "rayon")]
+const PARALLEL_CHUNK_LEN: usize = 64 * 1024;
+
+/// The priority of a message, see [`Writer::send_with_priority`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Priority {
+    /// Control messages, e.g. requests, which should not wait for bulk data.
+    High,
+    /// The priority of messages sent with [`Writer::send`] and through the sink.
+    #[default]
+    Normal,
+    /// Bulk data.
+    Low,
+}
+
+/// When the sends of a [`Writer`] flush, see [`Writer::with_flush_policy`].
+///
+/// Sending with a priority or a deadline, closing the writer and flushing it with
+/// [`Writer::flush`] or through the sink always write the queued messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum FlushPolicy {
+    /// Flush after every message, also after each message of [`Writer::send_batch`].
+    EveryMessage,
+    /// Flush after every send, and once after a batch. This is the default.
+    #[default]
+    AfterBatch,
+    /// Don't flush after sends. Messages are written once more than
+    /// [`Writer::with_max_buffered`] bytes are queued, and when the writer is flushed.
+    Manual,
+    /// Flush after a send once at least this many bytes are queued.
+    BytesThreshold(usize),
+}
+
+/// A handle to replace the cipher of a [`Writer`] from elsewhere, see
+/// [`Writer::cipher_handle`].
+pub struct CipherHandle<C> {
+    next: Arc<Mutex<Option<C>>>,
+}
+
+impl<C> CipherHandle<C> {
+    /// Replace the cipher of the writer.
+    ///
+    /// The writer switches to `cipher` before it encodes the next message, so messages
+    /// that are already sent stay encrypted with the previous cipher. If this is
+    /// called again before that, only the last cipher is used.
+    pub fn set(&self, cipher: C) {
+        *self.next.lock().expect("cipher lock poisoned") = Some(cipher);
+    }
+}
+
+impl<C> Clone for CipherHandle<C> {
+    fn clone(&self) -> Self {
+        Self {
+            next: self.next.clone(),
+        }
+    }
+}
 
 /// A writer for SMC messages.
 ///
 /// Consumes an [`futures::io::AsyncWrite`] to which messages will be written.
-pub struct Writer<W> {
-    writer: BufWriter<W>,
+///
+/// The writer is a [`futures::sink::Sink`] of [`Message`]s, so it can be used with
+/// combinators like [`futures::stream::StreamExt::forward`]. Messages sent through the
+/// sink are queued in an internal buffer. Once more than
+/// [`Writer::with_max_buffered`] bytes are queued, [`futures::sink::Sink::poll_ready`]
+/// waits until the underlying writer accepted enough of them.
+///
+/// Messages with a higher [`Priority`] are written before queued messages with a
+/// lower priority.
+pub struct Writer<W, C = NoCipher> {
+    writer: W,
+    // Encrypted bytes, in the order they are written.
+    buf: Vec<u8>,
+    pos: usize,
+    // Unencrypted frames that are not yet moved to `buf`, by priority.
+    queues: [VecDeque<Vec<u8>>; 3],
+    queued: usize,
+    max_buffered: usize,
+    flush_policy: FlushPolicy,
+    // Whether a message of `Writer::poll_send` is queued, and not yet flushed.
+    sending: bool,
+    // Whether the writer was closed, see `Writer::close`.
+    closed: bool,
+    cipher: C,
+    // A cipher set with a `CipherHandle`, which replaces `cipher` before the next message.
+    next_cipher: Option<Arc<Mutex<Option<C>>>>,
+    rekey_threshold: u64,
+    // The stream offset where the cipher starts, the number of messages before it while
+    // that offset is not known yet, and the number of bytes of the stream so far.
+    cipher_offset: u64,
+    plain_messages: u64,
+    stream_offset: u64,
+    #[cfg(feature = "rayon")]
+    parallel: Option<ParallelCipher<C>>,
+    rate_limit: Option<RateLimit>,
+    coalesce_window: Option<Duration>,
+    coalesce: Coalesce,
+    clock: SharedClock,
+    fragment_len: Option<usize>,
+    stats: Option<Stats>,
+    observer: Option<FrameObserver>,
+    events: Option<EventSender>,
+    drop_check: DropCheck,
+    #[cfg(any(feature = "lz4", feature = "zstd"))]
+    compression: Option<(Codec, usize)>,
+    #[cfg(feature = "checksum")]
+    checksum: Option<Checksum>,
+}
+
+// Reports the bytes that are still queued when the writer is dropped, see
+// `Writer::set_drop_hook`.
+#[derive(Default)]
+struct DropCheck {
+    // The bytes that are queued, as of the last encode or write.
+    unsent: usize,
+    // Whether the underlying writer failed, so the bytes could not be written.
+    failed: bool,
+    hook: Option<Box<dyn FnOnce(usize) + Send>>,
+    panic: bool,
+    events: Option<EventSender>,
+}
+
+impl DropCheck {
+    // Note whether the underlying writer failed.
+    fn check<T>(&mut self, result: std::io::Result<T>) -> Result<T, SmcError> {
+        self.failed |= result.is_err();
+        result.map_err(SmcError::from)
+    }
 }
 
+impl Drop for DropCheck {
+    fn drop(&mut self) {
+        if self.unsent == 0 {
+            return;
+        }
+        if let Some(hook) = self.hook.take() {
+            hook(self.unsent);
+        }
+        if self.failed {
+            if let Some(events) = &self.events {
+                events.emit(ConnectionEvent::UnsentDropped(self.unsent));
+            }
+        } else if cfg!(debug_assertions) && self.panic && !std::thread::panicking() {
+            panic!("Writer dropped with {} unsent bytes", self.unsent);
+        }
+    }
+}
+
+// The state of the coalesce window of the queued messages, see
+// `Writer::set_coalesce_window`.
+enum Coalesce {
+    Idle,
+    Waiting(Sleep),
+    Due,
+}
+
+/// Applies a cipher in parallel, see [`Writer::with_parallel_cipher`].
+#[cfg(feature = "rayon")]
+type ParallelCipher<C> = Box<dyn Fn(&mut C, &mut [u8]) + Send + Sync>;
+
 impl<W> Writer<W>
 where
     W: AsyncWrite + Unpin,
 {
     /// Create a new message writer.
     pub fn new(writer: W) -> Self {
+        Self::with_capacity(0, writer)
+    }
+
+    /// Run `preamble` on the transport before writing messages, and encrypt the messages
+    /// with the cipher it returns.
+    ///
+    /// See [`Reader::with_preamble`](crate::Reader::with_preamble).
+    pub async fn with_preamble<F, D>(mut writer: W, preamble: F) -> Result<Writer<W, D>, SmcError>
+    where
+        F: for<'a> FnOnce(&'a mut W) -> BoxFuture<'a, Result<D, SmcError>>,
+        D: FrameCipher,
+    {
+        let cipher = preamble(&mut writer).await?;
+        Ok(Self::new(writer).with_cipher(cipher))
+    }
+
+    /// Create a new message writer whose internal buffer has room for `capacity` bytes.
+    ///
+    /// The buffer still grows if a larger message is sent.
+    pub fn with_capacity(capacity: usize, writer: W) -> Self {
         Self {
-            writer: BufWriter::new(writer),
+            writer,
+            buf: Vec::with_capacity(capacity),
+            pos: 0,
+            queues: Default::default(),
+            queued: 0,
+            max_buffered: DEFAULT_MAX_BUFFERED,
+            flush_policy: FlushPolicy::AfterBatch,
+            sending: false,
+            closed: false,
+            cipher: NoCipher,
+            next_cipher: None,
+            rekey_threshold: 0,
+            cipher_offset: 0,
+            plain_messages: 0,
+            stream_offset: 0,
+            #[cfg(feature = "rayon")]
+            parallel: None,
+            rate_limit: None,
+            coalesce_window: None,
+            coalesce: Coalesce::Idle,
+            clock: system_clock(),
+            fragment_len: None,
+            stats: None,
+            observer: None,
+            events: None,
+            drop_check: DropCheck::default(),
+            #[cfg(any(feature = "lz4", feature = "zstd"))]
+            compression: None,
+            #[cfg(feature = "checksum")]
+            checksum: None,
         }
     }
 
-    /// Send a message.
+    /// Send a message without copying its payload.
+    ///
+    /// This works like [`Writer::send`], but writes the header and the payload with
+    /// a vectored write instead of encoding them into the internal buffer. Use this
+    /// for large payloads. This is not available with a cipher, which needs to
+    /// encrypt the payload. With compression, a checksum, a rate limit or fragmentation
+    /// enabled, this is the same as [`Writer::send`].
+    pub async fn send_vectored<B: AsRef<[u8]>>(
+        &mut self,
+        message: Message<B>,
+    ) -> Result<(), SmcError> {
+        #[cfg(any(feature = "lz4", feature = "zstd"))]
+        if self.compression.is_some() {
+            return self.send(message).await;
+        }
+        #[cfg(feature = "checksum")]
+        if self.checksum.is_some() {
+            return self.send(message).await;
+        }
+        if self.rate_limit.is_some() || self.fragment_len.is_some() || self.closed {
+            return self.send(message).await;
+        }
+        let mut header = [0u8; MAX_HEADER_LEN];
+        let len_header = message.encode_header_into(&mut header)?;
+        let header = &header[..len_header];
+        let payload = message.message.as_ref();
+        poll_fn(|cx| self.poll_write_buf(cx, 0)).await?;
+        let mut written = 0;
+        poll_fn(|cx| {
+            while written < header.len() + payload.len() {
+                let n = if written < header.len() {
+                    let bufs = [IoSlice::new(&header[written..]), IoSlice::new(payload)];
+                    ready!(Pin::new(&mut self.writer).poll_write_vectored(cx, &bufs))?
+                } else {
+                    let buf = &payload[written - header.len()..];
+                    ready!(Pin::new(&mut self.writer).poll_write(cx, buf))?
+                };
+                if n == 0 {
+                    let error = Error::new(ErrorKind::WriteZero, "Failed to write message");
+                    return Poll::Ready(Err(error));
+                }
+                written += n;
+                if let Some(stats) = &mut self.stats {
+                    stats.bytes += n as u64;
+                }
+            }
+            Poll::Ready(Ok(()))
+        })
+        .await?;
+        self.stream_offset += (header.len() + payload.len()) as u64;
+        self.count_plain(self.stream_offset, message.is_keepalive());
+        if let Some(stats) = &mut self.stats {
+            stats.record(&message, self.clock.now());
+        }
+        if let Some(observer) = &mut self.observer {
+            observer(Direction::Write, &message.as_borrowed());
+        }
+        self.auto_flush().await
+    }
+}
+
+impl<W, C> Writer<W, C>
+where
+    W: AsyncWrite + Unpin,
+    C: FrameCipher,
+{
+    /// Encrypt the stream of messages with `cipher`.
+    ///
+    /// The cipher is applied to all messages that are sent from now on. This disables
+    /// [`Writer::with_parallel_cipher`].
+    pub fn with_cipher<D: FrameCipher>(mut self, cipher: D) -> Writer<W, D> {
+        self.commit(usize::MAX);
+        Writer {
+            writer: self.writer,
+            buf: self.buf,
+            pos: self.pos,
+            queues: self.queues,
+            queued: self.queued,
+            max_buffered: self.max_buffered,
+            flush_policy: self.flush_policy,
+            sending: self.sending,
+            closed: self.closed,
+            cipher,
+            next_cipher: None,
+            rekey_threshold: self.rekey_threshold,
+            cipher_offset: self.cipher_offset,
+            plain_messages: self.plain_messages,
+            stream_offset: self.stream_offset,
+            #[cfg(feature = "rayon")]
+            parallel: None,
+            rate_limit: self.rate_limit,
+            coalesce_window: self.coalesce_window,
+            coalesce: self.coalesce,
+            clock: self.clock,
+            fragment_len: self.fragment_len,
+            stats: self.stats,
+            observer: self.observer,
+            events: self.events,
+            drop_check: self.drop_check,
+            #[cfg(any(feature = "lz4", feature = "zstd"))]
+            compression: self.compression,
+            #[cfg(feature = "checksum")]
+            checksum: self.checksum,
+        }
+    }
+
+    /// Replace the cipher, e.g. with new keys after a handshake.
+    ///
+    /// The new cipher is applied to all messages that are sent from now on. Messages
+    /// that are already queued stay encrypted with the previous cipher. This disables
+    /// [`Writer::with_parallel_cipher`].
+    pub fn set_cipher(&mut self, cipher: C) {
+        self.commit(usize::MAX);
+        self.cipher = cipher;
+        if let Some(events) = &self.events {
+            events.emit(ConnectionEvent::CipherUpgraded(Direction::Write));
+        }
+        #[cfg(feature = "rayon")]
+        {
+            self.parallel = None;
+        }
+    }
+
+    /// Get a mutable reference to the cipher.
+    pub fn cipher_mut(&mut self) -> &mut C {
+        &mut self.cipher
+    }
+
+    // Send the lifecycle events of the writer to `events`.
+    pub(crate) fn set_events(&mut self, events: EventSender) {
+        self.drop_check.events = Some(events.clone());
+        self.events = Some(events);
+    }
+
+    /// Start encrypting at `start`, and send the bytes before it in plain text.
     ///
-    /// This encodes the message, writes it and flushes the writer.
-    pub async fn send(&mut self, message: Message) -> Result<(), Error> {
-        let buf = message.encode()?;
-        self.writer.write_all(&buf).await?;
-        self.writer.flush().await?;
+    /// Messages are counted in the order they are written, and the offset is counted
+    /// like [`Reader::offset`](crate::Reader::offset). Use the same start on the reader
+    /// of the peer, see [`Reader::cipher_start_after`](crate::Reader::cipher_start_after).
+    pub fn cipher_start_after(mut self, start: CipherStart) -> Self {
+        self.commit(usize::MAX);
+        (self.cipher_offset, self.plain_messages) = match start {
+            CipherStart::Messages(0) => (self.stream_offset, 0),
+            CipherStart::Messages(n) => (u64::MAX, n),
+            CipherStart::Bytes(offset) => (offset, 0),
+        };
+        self
+    }
+
+    /// Keep `threshold` bytes of keystream, to rotate the keys before it is exhausted.
+    ///
+    /// A message that would leave less than `threshold` bytes of the keystream of the
+    /// cipher, see [`FrameCipher::remaining`], is not queued, and sending it fails with
+    /// [`SmcError::RekeyNeeded`]. Replace the cipher with new keys, e.g. with
+    /// [`Writer::set_cipher`] and a [`KeyedCipher`](crate::KeyedCipher), and send the
+    /// message again. The peer has to rekey its reader at the same position. Defaults
+    /// to 0, which only fails messages that don't fit into the keystream anymore.
+    pub fn with_rekey_threshold(mut self, threshold: u64) -> Self {
+        self.rekey_threshold = threshold;
+        self
+    }
+
+    /// Returns `true` if the cipher has at most the rekey threshold of keystream left,
+    /// counting the queued messages, see [`Writer::with_rekey_threshold`].
+    pub fn needs_rekey(&self) -> bool {
+        self.cipher
+            .remaining()
+            .is_some_and(|remaining| remaining <= self.queued as u64 + self.rekey_threshold)
+    }
+
+    /// Get a handle to replace the cipher from elsewhere, e.g. from another task.
+    ///
+    /// This allows the reader of the same connection to install the cipher of the
+    /// writer once its handshake completes, e.g. from a
+    /// [`Reader::with_cipher_hook`](crate::Reader::with_cipher_hook), while the writer
+    /// is used on a different task. See [`CipherHandle::set`].
+    ///
+    /// Handles stop to work when the cipher type is changed with
+    /// [`Writer::with_cipher`].
+    pub fn cipher_handle(&mut self) -> CipherHandle<C> {
+        let next = self.next_cipher.get_or_insert_with(Default::default);
+        CipherHandle { next: next.clone() }
+    }
+
+    /// Encrypt large writes in parallel, on the rayon thread pool.
+    ///
+    /// With a CPU-bound cipher, e.g. to send large payloads or batches on a fast
+    /// connection, this spreads the encryption over multiple cores. The write buffer is
+    /// split into chunks of 64 KiB, which are encrypted by copies of the cipher that
+    /// seek to the position of their chunk, so the frames are written in order. Smaller
+    /// writes are encrypted on the current thread.
+    ///
+    /// `new_cipher` creates a copy of the cipher at the start of the stream, i.e. with
+    /// the same key and nonce. Replacing the cipher, e.g. with [`Writer::set_cipher`] or
+    /// a [`CipherHandle`], disables this again.
+    ///
+    /// Enabled with the `rayon` feature.
+    #[cfg(feature = "rayon")]
+    pub fn with_parallel_cipher<F>(mut self, new_cipher: F) -> Self
+    where
+        C: SeekableCipher,
+        F: Fn() -> C + Send + Sync + 'static,
+    {
+        self.parallel = Some(Box::new(move |cipher, buf| {
+            apply_parallel(&new_cipher, cipher, buf)
+        }));
+        self
+    }
+
+    /// Set whether [`Stats`] are collected.
+    ///
+    /// By default, they are not.
+    pub fn with_stats(mut self, stats: bool) -> Self {
+        self.stats = if stats { Some(Stats::default()) } else { None };
+        self
+    }
+
+    /// The [`Stats`] of the messages written so far, if enabled.
+    ///
+    /// Messages are counted once they are queued, bytes once they are written.
+    pub fn stats(&self) -> Option<&Stats> {
+        self.stats.as_ref()
+    }
+
+    /// The [`ChannelStats`] of the messages written on `channel`, if stats are enabled.
+    pub fn channel_stats(&self, channel: u64) -> Option<&ChannelStats> {
+        self.stats.as_ref()?.channel_stats(channel)
+    }
+
+    /// Compress the payloads of all messages that are sent from now on with `codec`.
+    ///
+    /// Payloads of less than [`DEFAULT_THRESHOLD`](compression::DEFAULT_THRESHOLD) bytes
+    /// are not compressed. The peer has to enable compression on its reader before
+    /// it reads the first message sent from now on, see the
+    /// [`compression`](crate::compression) module.
+    ///
+    /// Enabled with the `lz4` or `zstd` feature.
+    #[cfg(any(feature = "lz4", feature = "zstd"))]
+    pub fn enable_compression(&mut self, codec: Codec) {
+        self.enable_compression_with_threshold(codec, compression::DEFAULT_THRESHOLD);
+    }
+
+    /// Like [`Writer::enable_compression`], but only compress payloads of at least
+    /// `threshold` bytes.
+    #[cfg(any(feature = "lz4", feature = "zstd"))]
+    pub fn enable_compression_with_threshold(&mut self, codec: Codec, threshold: usize) {
+        self.compression = Some((codec, threshold));
+    }
+
+    /// Append a `checksum` to every frame, see [`Checksum`].
+    ///
+    /// Enabled with the `checksum` feature.
+    #[cfg(feature = "checksum")]
+    pub fn with_checksum(mut self, checksum: Checksum) -> Self {
+        self.checksum = Some(checksum);
+        self
+    }
+
+    /// Split the payloads of more than `fragment_len` bytes into fragments.
+    ///
+    /// This allows to send payloads that are larger than a frame, i.e.
+    /// [`MAX_MESSAGE_SIZE`](crate::MAX_MESSAGE_SIZE). The fragments are sent as messages
+    /// of [`FRAGMENT_TYP`](crate::FRAGMENT_TYP), see there, and the peer has to reassemble
+    /// them with [`Reader::with_fragmentation`](crate::Reader::with_fragmentation).
+    /// `fragment_len` is at most [`MAX_FRAGMENT_LEN`](crate::MAX_FRAGMENT_LEN).
+    ///
+    /// Fragments are queued together, and are not interleaved with other messages on the
+    /// same channel.
+    pub fn with_fragmentation(mut self, fragment_len: usize) -> Self {
+        self.fragment_len = Some(fragment_len.clamp(1, fragment::MAX_FRAGMENT_LEN));
+        self
+    }
+
+    /// Set the max number of bytes that are queued before applying backpressure.
+    ///
+    /// Defaults to [`DEFAULT_MAX_BUFFERED`].
+    pub fn with_max_buffered(mut self, max_buffered: usize) -> Self {
+        self.max_buffered = max_buffered;
+        self
+    }
+
+    /// Set when sends flush, e.g. [`FlushPolicy::Manual`] to write many small messages
+    /// with fewer syscalls.
+    ///
+    /// Defaults to [`FlushPolicy::AfterBatch`].
+    pub fn with_flush_policy(mut self, policy: FlushPolicy) -> Self {
+        self.flush_policy = policy;
+        self
+    }
+
+    /// Limit the bytes written to the underlying writer to `bytes_per_sec`.
+    ///
+    /// Writes are delayed once the budget is used up, so queued messages wait, and
+    /// [`futures::sink::Sink::poll_ready`] waits once more than the max buffered bytes
+    /// are queued. Bursts of up to a tenth of the rate are written at once.
+    pub fn set_rate_limit(&mut self, bytes_per_sec: u64) {
+        self.rate_limit = Some(RateLimit::new(bytes_per_sec, self.clock.clone()));
+    }
+
+    /// Remove the rate limit set with [`Writer::set_rate_limit`].
+    pub fn clear_rate_limit(&mut self) {
+        self.rate_limit = None;
+    }
+
+    /// Delay flushes by up to `window`, to write the messages that are queued
+    /// meanwhile together.
+    ///
+    /// The window starts with the first message that is queued after a flush, or while
+    /// the writer has nothing else to write.
+    /// Flushing, e.g. with [`Writer::send`] or [`futures::sink::SinkExt::flush`], waits
+    /// until it passed, so chatty exchanges are written in fewer and larger packets at
+    /// the cost of some latency. The messages of a sink, e.g. with
+    /// [`futures::stream::StreamExt::forward`], are queued while a flush waits. Closing
+    /// the writer and sending with a priority or a deadline are not delayed.
+    pub fn set_coalesce_window(&mut self, window: Duration) {
+        self.coalesce_window = Some(window);
+    }
+
+    /// Flush without delay, see [`Writer::set_coalesce_window`].
+    pub fn clear_coalesce_window(&mut self) {
+        self.coalesce_window = None;
+        self.coalesce = Coalesce::Idle;
+    }
+
+    /// Measure deadlines and the rate limit with `clock`, see
+    /// [`Reader::with_clock`](crate::Reader::with_clock).
+    #[cfg(feature = "sim")]
+    pub fn with_clock<K: crate::Clock + 'static>(mut self, clock: K) -> Self {
+        self.clock = Arc::new(clock);
+        if let Some(rate_limit) = &mut self.rate_limit {
+            rate_limit.set_clock(self.clock.clone());
+        }
+        self
+    }
+
+    /// Call `observer` with every message that is sent, see
+    /// [`Reader::set_frame_observer`](crate::Reader::set_frame_observer).
+    ///
+    /// Messages are observed once they are queued, before they are fragmented,
+    /// compressed and encrypted.
+    pub fn set_frame_observer<F>(&mut self, observer: F)
+    where
+        F: FnMut(Direction, &Message<&[u8]>) + Send + 'static,
+    {
+        self.observer = Some(Box::new(observer));
+    }
+
+    /// Remove the observer set with [`Writer::set_frame_observer`].
+    pub fn clear_frame_observer(&mut self) {
+        self.observer = None;
+    }
+
+    /// Call `hook` with the number of unsent bytes if the writer is dropped, or
+    /// [unwrapped](Writer::into_inner), while messages are queued, e.g. to log or count
+    /// the lost bytes.
+    pub fn set_drop_hook<F>(&mut self, hook: F)
+    where
+        F: FnOnce(usize) + Send + 'static,
+    {
+        self.drop_check.hook = Some(Box::new(hook));
+    }
+
+    /// Panic if the writer is dropped while messages are queued, e.g. to catch a missing
+    /// flush in tests.
+    ///
+    /// This only panics with debug assertions, and not while the thread is already
+    /// panicking. It does not panic once the underlying writer failed, since the queued
+    /// messages can't be written then: the writer emits
+    /// [`ConnectionEvent::UnsentDropped`] on the [events](crate::Channel::events) of
+    /// its channel instead.
+    pub fn with_drop_panic(mut self) -> Self {
+        self.drop_check.panic = true;
+        self
+    }
+
+    /// Consume the writer, returning the underlying writer.
+    ///
+    /// Queued messages that are not written yet are discarded, so flush the writer
+    /// first, or use [`Writer::into_shutdown`].
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    // The state of the writer for a `SessionState`, with the queued messages encrypted.
+    // The queued bytes move to the state, and the writer is closed.
+    pub(crate) fn export_state(&mut self) -> Result<StreamState, SmcError>
+    where
+        C: SeekableCipher,
+    {
+        if self.closed {
+            return Err(SmcError::Closed);
+        }
+        self.commit(usize::MAX);
+        let raw = self.buf.split_off(self.pos);
+        self.buf.clear();
+        self.pos = 0;
+        self.closed = true;
+        self.drop_check.unsent = 0;
+        Ok(StreamState {
+            offset: self.stream_offset - raw.len() as u64,
+            cipher_offset: self.cipher_offset,
+            plain_messages: self.plain_messages,
+            position: self.cipher.position(),
+            plain: Vec::new(),
+            raw,
+        })
+    }
+
+    // Check that the session of `state` can continue on this writer, which is unused.
+    pub(crate) fn check_import(&self, state: &StreamState) -> Result<(), SmcError>
+    where
+        C: SeekableCipher,
+    {
+        let used = self.stream_offset > 0 || self.buffered() > 0;
+        if used || !state.plain.is_empty() || !can_seek(&self.cipher, state.position) {
+            return Err(SmcError::InvalidSession);
+        }
         Ok(())
     }
 
+    // Continue the session of `state`, once it passed `check_import`.
+    pub(crate) fn import_state(&mut self, state: &StreamState) -> Result<(), SmcError>
+    where
+        C: SeekableCipher,
+    {
+        self.cipher
+            .seek(state.position)
+            .map_err(|_| SmcError::InvalidSession)?;
+        self.buf = state.raw.clone();
+        self.pos = 0;
+        self.stream_offset = state.offset + state.raw.len() as u64;
+        self.cipher_offset = state.cipher_offset;
+        self.plain_messages = state.plain_messages;
+        self.drop_check.unsent = self.buffered();
+        Ok(())
+    }
+
+    /// Flush all queued messages and close the writer, returning the underlying writer.
+    pub async fn into_shutdown(mut self) -> Result<W, SmcError> {
+        self.close().await?;
+        Ok(self.into_inner())
+    }
+
+    /// The number of bytes that are queued but not yet written.
+    pub fn buffered(&self) -> usize {
+        self.buf.len() - self.pos + self.queued
+    }
+
+    /// Returns `true` if the writer was closed, see [`Writer::close`].
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    /// Send a message.
+    ///
+    /// This encodes the message, writes it and flushes the writer, unless the
+    /// [`FlushPolicy`] says otherwise.
+    pub async fn send<B: AsRef<[u8]>>(&mut self, message: Message<B>) -> Result<(), SmcError> {
+        poll_fn(|cx| self.poll_ready_buf(cx)).await?;
+        self.encode(&[message], Priority::Normal)?;
+        self.auto_flush().await
+    }
+
+    /// Poll to send a message.
+    ///
+    /// This is what [`Writer::send`] does, for futures and actors that drive the writer
+    /// by hand. The message is queued on the first poll once the writer has room for it,
+    /// and the writer is flushed until this is ready. After this returned
+    /// [`Poll::Pending`], poll it again with the same message: the message is only
+    /// queued once, and is ignored while it is flushed.
+    pub fn poll_send<B: AsRef<[u8]>>(
+        &mut self,
+        cx: &mut Context<'_>,
+        message: &Message<B>,
+    ) -> Poll<Result<(), SmcError>> {
+        if !self.sending {
+            ready!(self.poll_ready_buf(cx))?;
+            self.encode(&[message.as_borrowed()], Priority::Normal)?;
+            if !self.should_flush() {
+                return Poll::Ready(Ok(()));
+            }
+            self.sending = true;
+        }
+        let result = ready!(self.poll_flush_buf(cx));
+        self.sending = false;
+        Poll::Ready(result)
+    }
+
+    /// Send a message with `priority`.
+    ///
+    /// The message is written before queued messages with a lower priority, which stay
+    /// queued until the writer is flushed. This waits until the message and all queued
+    /// messages of the same or a higher priority are written, and does not wait for
+    /// the writer to have room for the message.
+    pub async fn send_with_priority<B: AsRef<[u8]>>(
+        &mut self,
+        message: Message<B>,
+        priority: Priority,
+    ) -> Result<(), SmcError> {
+        self.encode(&[message], priority)?;
+        poll_fn(|cx| self.poll_write_priority(cx, priority)).await?;
+        poll_fn(|cx| self.poll_flush_writer(cx)).await
+    }
+
+    /// Send a message, unless it can't be written before `deadline`.
+    ///
+    /// This waits until the queued messages of the same or a higher priority are
+    /// written, like [`Writer::send_with_priority`] with [`Priority::Normal`]. If the
+    /// deadline passes before that, the message is dropped without writing any of it,
+    /// and this fails with [`SmcError::SendTimeout`]. Use this for messages that are
+    /// useless once they are late, e.g. requests that are cancelled in the meantime.
+    ///
+    /// If the message is still queued when the deadline passes, e.g. behind messages of a
+    /// lower priority while a rate limit holds back the writes, it is dropped as well, and
+    /// is neither counted in the [`Stats`] nor passed to the frame observer.
+    /// Once it is moved to the write buffer, which is right away if nothing else is
+    /// queued, it is always written in full, since a partial frame would corrupt the
+    /// stream: if the deadline passes then, this still fails with
+    /// [`SmcError::SendTimeout`], but the message is written with the next flush.
+    pub async fn send_with_deadline<B: AsRef<[u8]>>(
+        &mut self,
+        message: Message<B>,
+        deadline: Instant,
+    ) -> Result<(), SmcError> {
+        let now = self.clock.now();
+        if now >= deadline {
+            return Err(SmcError::SendTimeout);
+        }
+        let mut delay = self.clock.sleep(deadline - now);
+        poll_fn(|cx| {
+            self.poll_until(cx, &mut delay, |this, cx| {
+                this.poll_write_priority(cx, Priority::Normal)
+            })
+        })
+        .await?;
+        // The message is only recorded in the stats and passed to the observer once it
+        // is not dropped anymore.
+        let messages = [message];
+        let encoded = self.encode_frames_of(&messages, Priority::Normal);
+        if encoded.is_err() {
+            self.record(&messages, &encoded);
+            return encoded;
+        }
+        let result = poll_fn(|cx| {
+            self.poll_until(cx, &mut delay, |this, cx| {
+                ready!(this.poll_write_priority(cx, Priority::Normal))?;
+                this.poll_flush_writer(cx)
+            })
+        })
+        .await;
+        // The normal priority queue was empty before the message was encoded, so it holds
+        // nothing but the message, unless the message was moved to the write buffer.
+        if let (Err(SmcError::SendTimeout), Some(frames)) =
+            (&result, self.queues[Priority::Normal as usize].pop_back())
+        {
+            self.queued -= frames.len();
+            self.drop_check.unsent = self.buffered();
+            return result;
+        }
+        self.record(&messages, &Ok(()));
+        result
+    }
+
+    // Poll `poll` until it is ready, or fail with `SendTimeout` once `delay` fires.
+    fn poll_until<F>(
+        &mut self,
+        cx: &mut Context<'_>,
+        delay: &mut Sleep,
+        mut poll: F,
+    ) -> Poll<Result<(), SmcError>>
+    where
+        F: FnMut(&mut Self, &mut Context<'_>) -> Poll<Result<(), SmcError>>,
+    {
+        if let Poll::Ready(result) = poll(self, cx) {
+            return Poll::Ready(result);
+        }
+        ready!(delay.poll_unpin(cx));
+        Poll::Ready(Err(SmcError::SendTimeout))
+    }
+
+    /// Queue a message with `priority` without writing it.
+    ///
+    /// The message is written the next time the writer is flushed or written to, e.g.
+    /// to queue bulk data with [`Priority::Low`] that should not delay later messages.
+    /// This does not apply backpressure, see [`Writer::buffered`].
+    pub fn queue<B: AsRef<[u8]>>(
+        &mut self,
+        message: Message<B>,
+        priority: Priority,
+    ) -> Result<(), SmcError> {
+        self.encode(&[message], priority)
+    }
+
+    /// Send a typed message on `channel`.
+    ///
+    /// See [`TypedMessage`] and [`Writer::send`].
+    pub async fn send_typed<T: TypedMessage>(
+        &mut self,
+        channel: u64,
+        value: &T,
+    ) -> Result<(), SmcError> {
+        self.send(Message::from_typed(channel, value)?).await
+    }
+
+    /// Close `channel`.
+    ///
+    /// This sends an empty message with typ [`CLOSE_TYP`] on the channel, which the
+    /// peer surfaces as [`ChannelEvent::Closed`](crate::ChannelEvent::Closed).
+    pub async fn close_channel(&mut self, channel: u64) -> Result<(), SmcError> {
+        self.send(Message::new(channel, CLOSE_TYP, Vec::new()))
+            .await
+    }
+
+    /// Send a keepalive.
+    ///
+    /// This writes an empty frame and flushes the writer. See [`Message::keepalive`].
+    pub async fn send_keepalive(&mut self) -> Result<(), SmcError> {
+        self.send(Message::<Vec<u8>>::keepalive()).await
+    }
+
     /// Send a batch of messages.
     ///
-    /// This works like [`Writer::send`] but flushes after all messages are written.
-    pub async fn send_batch(&mut self, messages: Vec<Message>) -> Result<(), Error> {
-        for message in &messages {
-            let buf = message.encode()?;
-            self.writer.write_all(&buf).await?;
+    /// This works like [`Writer::send`], but all messages are encoded into a single
+    /// buffer, which is written at once and flushed after all messages are written.
+    /// With [`FlushPolicy::EveryMessage`], each message is sent on its own instead.
+    pub async fn send_batch<B: AsRef<[u8]>>(
+        &mut self,
+        messages: &[Message<B>],
+    ) -> Result<(), SmcError> {
+        if self.flush_policy == FlushPolicy::EveryMessage {
+            for message in messages {
+                self.send(message.as_borrowed()).await?;
+            }
+            return Ok(());
+        }
+        poll_fn(|cx| self.poll_ready_buf(cx)).await?;
+        self.encode(messages, Priority::Normal)?;
+        self.auto_flush().await
+    }
+
+    // Encode messages with `priority`, and record them. Nothing is queued on error.
+    fn encode<B: AsRef<[u8]>>(
+        &mut self,
+        messages: &[Message<B>],
+        priority: Priority,
+    ) -> Result<(), SmcError> {
+        let result = self.encode_frames_of(messages, priority);
+        self.record(messages, &result);
+        result
+    }
+
+    // Encode messages with `priority`, in fragments if enabled.
+    fn encode_frames_of<B: AsRef<[u8]>>(
+        &mut self,
+        messages: &[Message<B>],
+        priority: Priority,
+    ) -> Result<(), SmcError> {
+        match self.fragment_len {
+            Some(len) if messages.iter().any(|m| m.message.as_ref().len() > len) => {
+                let fragments = fragment::split(messages, len);
+                self.encode_messages(&fragments, priority)
+            }
+            _ => self.encode_messages(messages, priority),
+        }
+    }
+
+    // Record encoded messages in the stats, and pass them to the observer.
+    fn record<B: AsRef<[u8]>>(&mut self, messages: &[Message<B>], result: &Result<(), SmcError>) {
+        if let Some(stats) = &mut self.stats {
+            match result {
+                Ok(()) => {
+                    let now = self.clock.now();
+                    messages
+                        .iter()
+                        .for_each(|message| stats.record(message, now));
+                }
+                Err(_) => stats.errors += 1,
+            }
+        }
+        if let (Some(observer), Ok(())) = (&mut self.observer, result) {
+            for message in messages {
+                observer(Direction::Write, &message.as_borrowed());
+            }
+        }
+    }
+
+    // Encode messages with `priority`, compressed if enabled.
+    fn encode_messages<B: AsRef<[u8]>>(
+        &mut self,
+        messages: &[Message<B>],
+        priority: Priority,
+    ) -> Result<(), SmcError> {
+        #[cfg(any(feature = "lz4", feature = "zstd"))]
+        if let Some((codec, threshold)) = self.compression {
+            let messages = messages
+                .iter()
+                .map(|message| {
+                    if message.is_keepalive() {
+                        return Ok(Message::keepalive());
+                    }
+                    let payload =
+                        compression::compress(codec, threshold, message.message.as_ref())?;
+                    Ok(Message::new(message.channel, message.typ, payload))
+                })
+                .collect::<Result<Vec<_>, SmcError>>()?;
+            return self.encode_checked(&messages, priority);
+        }
+        self.encode_checked(messages, priority)
+    }
+
+    // Encode messages with `priority`, with a checksum if enabled.
+    fn encode_checked<B: AsRef<[u8]>>(
+        &mut self,
+        messages: &[Message<B>],
+        priority: Priority,
+    ) -> Result<(), SmcError> {
+        #[cfg(feature = "checksum")]
+        if let Some(checksum) = self.checksum {
+            return self.encode_with(priority, |buf| {
+                messages
+                    .iter()
+                    .try_for_each(|message| checksum.encode_into(message, buf))
+            });
+        }
+        self.encode_with(priority, |buf| {
+            messages
+                .iter()
+                .try_for_each(|message| message.encode_into(buf))
+        })
+    }
+
+    // Encode raw frames. Nothing is queued on error.
+    pub(crate) fn encode_frames<B: AsRef<[u8]>>(&mut self, frames: &[B]) -> Result<(), SmcError> {
+        self.encode_with(Priority::Normal, |buf| {
+            frames
+                .iter()
+                .try_for_each(|frame| encode_frame_into(frame.as_ref(), buf))
+        })
+    }
+
+    // Encode with `encode` into the write buffer if nothing is waiting in the priority
+    // queues, or into the queue of `priority` otherwise.
+    fn encode_with<F>(&mut self, priority: Priority, encode: F) -> Result<(), SmcError>
+    where
+        F: FnOnce(&mut Vec<u8>) -> Result<(), SmcError>,
+    {
+        if self.closed {
+            return Err(SmcError::Closed);
+        }
+        let next = self
+            .next_cipher
+            .as_ref()
+            .and_then(|next| next.lock().expect("cipher lock poisoned").take());
+        if let Some(cipher) = next {
+            self.set_cipher(cipher);
+        }
+        let first = self.buffered() == 0;
+        if self.queued == 0 && self.buf.len() - self.pos < COMMIT_LEN {
+            let len = self.buf.len();
+            if let Err(error) =
+                encode(&mut self.buf).and_then(|()| self.check_keystream(self.buf.len() - len))
+            {
+                self.buf.truncate(len);
+                return Err(error);
+            }
+            self.apply_cipher(len);
+        } else {
+            let mut frames = Vec::new();
+            encode(&mut frames)?;
+            self.check_keystream(frames.len())?;
+            self.queued += frames.len();
+            self.queues[priority as usize].push_back(frames);
+        }
+        // The coalesce window starts with the first message after a flush.
+        if let Some(window) = self.coalesce_window {
+            if first || matches!(self.coalesce, Coalesce::Idle) {
+                self.coalesce = Coalesce::Waiting(self.clock.sleep(window));
+            }
+        }
+        self.drop_check.unsent = self.buffered();
+        Ok(())
+    }
+
+    // Move queued frames into the write buffer, highest priority first, until it holds
+    // at least `len` bytes.
+    fn commit(&mut self, len: usize) {
+        let start = self.buf.len();
+        while self.buf.len() - self.pos < len {
+            let frames = match self.queues.iter_mut().find_map(VecDeque::pop_front) {
+                Some(frames) => frames,
+                None => break,
+            };
+            self.queued -= frames.len();
+            self.buf.extend_from_slice(&frames);
+        }
+        self.apply_cipher(start);
+    }
+
+    // Fail if the cipher can't encrypt the queued frames and `frames`, keeping the rekey
+    // threshold.
+    fn check_keystream(&self, frames: usize) -> Result<(), SmcError> {
+        match self.cipher.remaining() {
+            Some(remaining) if remaining < (self.queued + frames) as u64 + self.rekey_threshold => {
+                Err(SmcError::RekeyNeeded)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // Encrypt the write buffer from `start`, in parallel if enabled.
+    fn apply_cipher(&mut self, start: usize) {
+        let offset = self.stream_offset;
+        let mut pos = start;
+        while self.plain_messages > 0 && pos < self.buf.len() {
+            let (len, prefix) = varint::decode(&self.buf[pos..]).expect("valid length prefix");
+            pos += prefix + len as usize;
+            self.count_plain(offset + (pos - start) as u64, len == 0);
+        }
+        let len = (self.buf.len() - start) as u64;
+        self.stream_offset += len;
+        let skip = self.cipher_offset.saturating_sub(offset).min(len) as usize;
+        let buf = &mut self.buf[start + skip..];
+        #[cfg(feature = "rayon")]
+        if let Some(apply) = &self.parallel {
+            return apply(&mut self.cipher, buf);
+        }
+        self.cipher.apply(buf);
+    }
+
+    // Count a frame that is sent before the cipher starts, and ends at the stream offset
+    // `end`.
+    fn count_plain(&mut self, end: u64, keepalive: bool) {
+        if self.plain_messages > 0 && !keepalive {
+            self.plain_messages -= 1;
+            if self.plain_messages == 0 {
+                self.cipher_offset = end;
+            }
+        }
+    }
+
+    /// Flush all buffered messages to the underlying writer.
+    ///
+    /// Fails with [`SmcError::Closed`] once the writer was closed.
+    pub async fn flush(&mut self) -> Result<(), SmcError> {
+        poll_fn(|cx| self.poll_flush_buf(cx)).await
+    }
+
+    // Flush after a send, if the flush policy says so.
+    async fn auto_flush(&mut self) -> Result<(), SmcError> {
+        if self.should_flush() {
+            self.flush().await?;
         }
-        self.writer.flush().await?;
         Ok(())
     }
+
+    fn should_flush(&self) -> bool {
+        match self.flush_policy {
+            FlushPolicy::EveryMessage | FlushPolicy::AfterBatch => true,
+            FlushPolicy::Manual => false,
+            FlushPolicy::BytesThreshold(threshold) => self.buffered() >= threshold,
+        }
+    }
+
+    /// Flush all buffered messages and close the underlying writer.
+    ///
+    /// This only shuts down the writing half of the transport, a reader of the same
+    /// transport keeps reading until the peer closes its side. Sending after this fails
+    /// with [`SmcError::Closed`].
+    pub async fn close(&mut self) -> Result<(), SmcError> {
+        poll_fn(|cx| self.poll_close_buf(cx)).await
+    }
+
+    // Write once to the underlying writer, moving queued frames to the write buffer
+    // if it is empty. With a rate limit, frames stay queued until there is budget, so
+    // that later messages of a higher priority still go first.
+    fn poll_write_once(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), SmcError>> {
+        if self.pos == self.buf.len() {
+            if let Some(rate_limit) = &mut self.rate_limit {
+                ready!(rate_limit.poll_budget(cx, COMMIT_LEN));
+            }
+            self.buf.clear();
+            self.pos = 0;
+            self.commit(COMMIT_LEN);
+        }
+        let mut end = self.buf.len();
+        if let Some(rate_limit) = &mut self.rate_limit {
+            end = self.pos + ready!(rate_limit.poll_budget(cx, end - self.pos));
+        }
+        let result = ready!(Pin::new(&mut self.writer).poll_write(cx, &self.buf[self.pos..end]));
+        let n = self.drop_check.check(result)?;
+        if n == 0 {
+            self.drop_check.failed = true;
+            let error = Error::new(ErrorKind::WriteZero, "Failed to write message");
+            return Poll::Ready(Err(error.into()));
+        }
+        if let Some(rate_limit) = &mut self.rate_limit {
+            rate_limit.consume(n);
+        }
+        self.pos += n;
+        self.drop_check.unsent = self.buffered();
+        if let Some(stats) = &mut self.stats {
+            stats.bytes += n as u64;
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    // Write queued bytes to the underlying writer until at most `limit` bytes are left.
+    fn poll_write_buf(&mut self, cx: &mut Context<'_>, limit: usize) -> Poll<Result<(), SmcError>> {
+        while self.buffered() > limit {
+            ready!(self.poll_write_once(cx))?;
+        }
+        self.buf.drain(..self.pos);
+        self.pos = 0;
+        Poll::Ready(Ok(()))
+    }
+
+    // Write queued bytes until no messages of `priority` or a higher priority are left.
+    fn poll_write_priority(
+        &mut self,
+        cx: &mut Context<'_>,
+        priority: Priority,
+    ) -> Poll<Result<(), SmcError>> {
+        let queues = ..=priority as usize;
+        while self.pos < self.buf.len() || self.queues[queues].iter().any(|q| !q.is_empty()) {
+            ready!(self.poll_write_once(cx))?;
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    pub(crate) fn poll_ready_buf(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), SmcError>> {
+        if self.closed {
+            return Poll::Ready(Err(SmcError::Closed));
+        }
+        let limit = self.max_buffered;
+        self.poll_write_buf(cx, limit)
+    }
+
+    fn poll_flush_buf(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), SmcError>> {
+        if self.closed {
+            return Poll::Ready(Err(SmcError::Closed));
+        }
+        ready!(self.poll_coalesce(cx));
+        ready!(self.poll_write_buf(cx, 0))?;
+        self.coalesce = Coalesce::Idle;
+        self.poll_flush_writer(cx)
+    }
+
+    // Flush the underlying writer.
+    fn poll_flush_writer(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), SmcError>> {
+        let result = ready!(Pin::new(&mut self.writer).poll_flush(cx));
+        Poll::Ready(self.drop_check.check(result))
+    }
+
+    // Wait until the coalesce window of the queued messages passed.
+    fn poll_coalesce(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        let window = match self.coalesce_window {
+            Some(window) if self.buffered() > 0 => window,
+            _ => {
+                self.coalesce = Coalesce::Idle;
+                return Poll::Ready(());
+            }
+        };
+        loop {
+            match &mut self.coalesce {
+                Coalesce::Idle => self.coalesce = Coalesce::Waiting(self.clock.sleep(window)),
+                Coalesce::Waiting(timer) => {
+                    ready!(timer.poll_unpin(cx));
+                    self.coalesce = Coalesce::Due;
+                }
+                Coalesce::Due => return Poll::Ready(()),
+            }
+        }
+    }
+
+    fn poll_close_buf(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), SmcError>> {
+        self.closed = true;
+        ready!(self.poll_write_buf(cx, 0))?;
+        let result = ready!(Pin::new(&mut self.writer).poll_close(cx));
+        Poll::Ready(self.drop_check.check(result))
+    }
+}
+
+impl<W, B, C> Sink<Message<B>> for Writer<W, C>
+where
+    W: AsyncWrite + Unpin,
+    B: AsRef<[u8]>,
+    C: FrameCipher + Unpin,
+{
+    type Error = SmcError;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), SmcError>> {
+        self.get_mut().poll_ready_buf(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, message: Message<B>) -> Result<(), SmcError> {
+        self.get_mut().encode(&[message], Priority::Normal)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), SmcError>> {
+        self.get_mut().poll_flush_buf(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), SmcError>> {
+        self.get_mut().poll_close_buf(cx)
+    }
+}
+
+// Apply `cipher` to `buf`, in chunks on the rayon thread pool, with copies of the
+// cipher created by `new_cipher`.
+#[cfg(feature = "rayon")]
+fn apply_parallel<C, F>(new_cipher: &F, cipher: &mut C, buf: &mut [u8])
+where
+    C: SeekableCipher,
+    F: Fn() -> C + Send + Sync,
+{
+    use rayon::prelude::*;
+
+    if buf.len() < 2 * PARALLEL_CHUNK_LEN {
+        return cipher.apply(buf);
+    }
+    let start = cipher.position();
+    buf.par_chunks_mut(PARALLEL_CHUNK_LEN)
+        .enumerate()
+        .for_each_init(new_cipher, |cipher, (i, chunk)| {
+            // The keystream was checked to be long enough for `buf`.
+            cipher
+                .seek(start + (i * PARALLEL_CHUNK_LEN) as u64)
+                .expect("seek within the keystream");
+            cipher.apply(chunk);
+        });
+    cipher
+        .seek(start + buf.len() as u64)
+        .expect("seek within the keystream");
+}
+
+/// A stream of keepalive messages, one for every `interval`.
+///
+/// Merge this with a stream of outgoing messages to send keepalives
+/// automatically, e.g. with [`futures::stream::select`].
+pub fn keepalives(interval: Duration) -> impl Stream<Item = Message> {
+    ticks(interval, system_clock())
+}
+
+/// A stream of keepalive messages, one for every `interval` of `clock`.
+///
+/// See [`keepalives`] and [`SimClock`](crate::SimClock).
+#[cfg(feature = "sim")]
+pub fn keepalives_with_clock<K: crate::Clock + 'static>(
+    interval: Duration,
+    clock: K,
+) -> impl Stream<Item = Message> {
+    ticks(interval, Arc::new(clock))
+}
+
+fn ticks(interval: Duration, clock: SharedClock) -> impl Stream<Item = Message> {
+    futures::stream::unfold(clock, move |clock| async move {
+        clock.sleep(interval).await;
+        Some((Message::keepalive(), clock))
+    })
 }
@@ -0,0 +1,194 @@
+//! WebSocket transports.
+//!
+//! Enabled with the `ws` feature. The adapters work with any stream and sink of
+//! [`tungstenite::Message`]s, e.g. a `WebSocketStream` of async-tungstenite or
+//! tokio-tungstenite. There are two ways to carry SMC over WebSocket:
+//!
+//! * [`WsFrames`] sends every SMC message as its own binary WebSocket message.
+//! * [`WsStream`] is an [`AsyncRead`] and [`AsyncWrite`] byte stream of binary WebSocket
+//!   messages, to use SMC framing inside of it, e.g. with [`Channel`](crate::Channel).
+//!   This is compatible with peers that treat the WebSocket as a byte stream.
+
+use bytes::Bytes;
+use futures::io::{AsyncRead, AsyncWrite};
+use futures::ready;
+use futures::sink::Sink;
+use futures::stream::Stream;
+use futures::task::{Context, Poll};
+use std::io::{self, Error, ErrorKind};
+use std::pin::Pin;
+use tungstenite::{Error as WsError, Message as WsMessage};
+
+use crate::codec::decode_frame;
+use crate::{Message, SmcError};
+
+fn ws_error(error: WsError) -> Error {
+    Error::other(error)
+}
+
+/// Poll the next binary WebSocket message, skipping other messages.
+fn poll_binary<S>(stream: Pin<&mut S>, cx: &mut Context<'_>) -> Poll<Option<io::Result<Bytes>>>
+where
+    S: Stream<Item = Result<WsMessage, WsError>>,
+{
+    let mut stream = stream;
+    loop {
+        match ready!(stream.as_mut().poll_next(cx)) {
+            Some(Ok(WsMessage::Binary(buf))) => return Poll::Ready(Some(Ok(buf))),
+            Some(Ok(WsMessage::Close(_))) | None => return Poll::Ready(None),
+            Some(Ok(_)) => {}
+            Some(Err(error)) => return Poll::Ready(Some(Err(ws_error(error)))),
+        }
+    }
+}
+
+/// SMC messages over WebSocket, one message per binary WebSocket message.
+///
+/// This is a [`Stream`] of received [`Message`]s and a [`Sink`] for outgoing
+/// messages. Text, ping and pong messages are skipped.
+pub struct WsFrames<S> {
+    stream: S,
+}
+
+impl<S> WsFrames<S> {
+    /// Create a new adapter from a stream and sink of WebSocket messages.
+    pub fn new(stream: S) -> Self {
+        Self { stream }
+    }
+
+    /// Unwrap the wrapped WebSocket.
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+}
+
+impl<S> Stream for WsFrames<S>
+where
+    S: Stream<Item = Result<WsMessage, WsError>> + Unpin,
+{
+    type Item = Result<Message, SmcError>;
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Message, SmcError>>> {
+        let buf = match ready!(poll_binary(Pin::new(&mut self.stream), cx)) {
+            Some(Ok(buf)) => buf,
+            Some(Err(error)) => return Poll::Ready(Some(Err(error.into()))),
+            None => return Poll::Ready(None),
+        };
+        let result = match decode_frame(&buf) {
+            Ok(Some((message, len))) if len == buf.len() => Ok(message),
+            Ok(_) => {
+                let error = Error::new(ErrorKind::InvalidData, "Not a single SMC frame");
+                Err(error.into())
+            }
+            Err(error) => Err(error),
+        };
+        Poll::Ready(Some(result))
+    }
+}
+
+impl<S, B> Sink<Message<B>> for WsFrames<S>
+where
+    S: Sink<WsMessage, Error = WsError> + Unpin,
+    B: AsRef<[u8]>,
+{
+    type Error = SmcError;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), SmcError>> {
+        Pin::new(&mut self.stream)
+            .poll_ready(cx)
+            .map_err(|error| ws_error(error).into())
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, message: Message<B>) -> Result<(), SmcError> {
+        let buf = message.encode()?;
+        Pin::new(&mut self.stream)
+            .start_send(WsMessage::Binary(buf.into()))
+            .map_err(|error| ws_error(error).into())
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), SmcError>> {
+        Pin::new(&mut self.stream)
+            .poll_flush(cx)
+            .map_err(|error| ws_error(error).into())
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), SmcError>> {
+        Pin::new(&mut self.stream)
+            .poll_close(cx)
+            .map_err(|error| ws_error(error).into())
+    }
+}
+
+/// A byte stream over WebSocket.
+///
+/// Reads the payloads of received binary WebSocket messages, and sends every write
+/// as a binary WebSocket message. Text, ping and pong messages are skipped.
+pub struct WsStream<S> {
+    stream: S,
+    buf: Bytes,
+}
+
+impl<S> WsStream<S> {
+    /// Create a new byte stream from a stream and sink of WebSocket messages.
+    pub fn new(stream: S) -> Self {
+        Self {
+            stream,
+            buf: Bytes::new(),
+        }
+    }
+
+    /// Unwrap the wrapped WebSocket.
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+}
+
+impl<S> AsyncRead for WsStream<S>
+where
+    S: Stream<Item = Result<WsMessage, WsError>> + Unpin,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        while self.buf.is_empty() {
+            match ready!(poll_binary(Pin::new(&mut self.stream), cx)) {
+                Some(Ok(next)) => self.buf = next,
+                Some(Err(error)) => return Poll::Ready(Err(error)),
+                None => return Poll::Ready(Ok(0)),
+            }
+        }
+        let n = buf.len().min(self.buf.len());
+        buf[..n].copy_from_slice(&self.buf.split_to(n));
+        Poll::Ready(Ok(n))
+    }
+}
+
+impl<S> AsyncWrite for WsStream<S>
+where
+    S: Sink<WsMessage, Error = WsError> + Unpin,
+{
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut stream = Pin::new(&mut self.stream);
+        ready!(stream.as_mut().poll_ready(cx)).map_err(ws_error)?;
+        stream
+            .start_send(WsMessage::Binary(Bytes::copy_from_slice(buf)))
+            .map_err(ws_error)?;
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx).map_err(ws_error)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_close(cx).map_err(ws_error)
+    }
+}
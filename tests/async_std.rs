@@ -0,0 +1,42 @@
+//! async-std IO types implement the `futures::io` traits, so they need no adapter, only
+//! the `std` feature.
+#![cfg(feature = "std")]
+
+use async_std::net::{TcpListener, TcpStream};
+use async_std::task;
+use futures::stream::StreamExt;
+use simple_message_channels::{Channel, Message, SmcError};
+use std::net::Shutdown;
+
+#[test]
+fn echo_over_tcp() {
+    task::block_on(async {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let address = listener.local_addr()?;
+        let server = task::spawn(async move {
+            let (stream, _) = listener.accept().await?;
+            let mut channel = Channel::new(stream);
+            while let Some(message) = channel.next().await {
+                channel.send(message?).await?;
+            }
+            Ok::<(), SmcError>(())
+        });
+
+        let stream = TcpStream::connect(address).await?;
+        let mut channel = Channel::new(stream.clone());
+        let messages: Vec<Message> = (0..10)
+            .map(|i| Message::new(i, 1, vec![i as u8; 1000 * i as usize]))
+            .collect();
+        channel.writer().send_batch(&messages).await?;
+        for message in &messages {
+            assert_eq!(&channel.next().await.unwrap()?, message);
+        }
+        channel.close().await?;
+        // Closing an async-std stream only flushes it, shut it down for the peer to see
+        // the end of the stream.
+        stream.shutdown(Shutdown::Write)?;
+        assert!(channel.next().await.is_none());
+        server.await
+    })
+    .unwrap();
+}
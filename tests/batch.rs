@@ -0,0 +1,76 @@
+#![cfg(feature = "std")]
+
+use futures::executor::block_on;
+use futures::io::AsyncWrite;
+use futures::task::{Context, Poll};
+use simple_message_channels::{codec, Message, Writer};
+use std::io::Result;
+use std::pin::Pin;
+
+// A writer that counts the calls to its methods.
+#[derive(Default)]
+struct Counting {
+    written: Vec<u8>,
+    writes: usize,
+    flushes: usize,
+}
+
+impl AsyncWrite for Counting {
+    fn poll_write(self: Pin<&mut Self>, _: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+        let this = self.get_mut();
+        this.writes += 1;
+        this.written.extend_from_slice(buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<()>> {
+        self.get_mut().flushes += 1;
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+fn messages(n: u64) -> Vec<Message> {
+    (0..n)
+        .map(|i| Message::new(i, (i % 16) as u8, vec![i as u8; i as usize]))
+        .collect()
+}
+
+#[test]
+fn batch_is_written_and_flushed_once() {
+    block_on(async {
+        for n in [1, 2, 10, 100] {
+            let messages = messages(n);
+            let mut writer = Writer::new(Counting::default());
+            writer.send_batch(&messages).await.unwrap();
+            let counting = writer.into_inner();
+            assert_eq!(
+                (counting.writes, counting.flushes),
+                (1, 1),
+                "{} messages",
+                n
+            );
+
+            let mut expected = Vec::new();
+            for message in &messages {
+                codec::encode_message_into(message, &mut expected).unwrap();
+            }
+            assert_eq!(counting.written, expected);
+        }
+    });
+}
+
+#[test]
+fn messages_are_written_and_flushed_each() {
+    block_on(async {
+        let mut writer = Writer::new(Counting::default());
+        for message in messages(10) {
+            writer.send(message).await.unwrap();
+        }
+        let counting = writer.into_inner();
+        assert_eq!((counting.writes, counting.flushes), (10, 10));
+    });
+}
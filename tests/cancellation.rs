@@ -0,0 +1,61 @@
+#![cfg(feature = "std")]
+
+use futures::executor::block_on;
+use futures::future::FutureExt;
+use futures::io::AsyncWriteExt;
+use futures::stream::StreamExt;
+use futures::task::{noop_waker, Context};
+use simple_message_channels::{Message, MessageHeader, PipeStream, Reader, Writer};
+
+async fn encode(messages: &[Message]) -> Vec<u8> {
+    let mut writer = Writer::new(Vec::new());
+    writer.send_batch(messages).await.unwrap();
+    writer.into_inner()
+}
+
+// A reader that has read the first `len` bytes of `bytes` in a `next` that was dropped,
+// and the peer end of its stream.
+async fn cancelled_next(bytes: &[u8], len: usize) -> (Reader<PipeStream>, PipeStream) {
+    let (stream, mut peer) = PipeStream::pair(1024);
+    let mut reader = Reader::new(stream);
+    peer.write_all(&bytes[..len]).await.unwrap();
+    let waker = noop_waker();
+    let mut cx = Context::from_waker(&waker);
+    assert!(reader.next().poll_unpin(&mut cx).is_pending());
+    (reader, peer)
+}
+
+#[test]
+fn peek_after_cancelled_next() {
+    block_on(async {
+        let message = Message::new(3, 2, vec![7; 100]);
+        let bytes = encode(std::slice::from_ref(&message)).await;
+        for len in 1..bytes.len() {
+            let (mut reader, mut peer) = cancelled_next(&bytes, len).await;
+            peer.write_all(&bytes[len..]).await.unwrap();
+            let header = reader.peek().await.unwrap().unwrap();
+            let expected = MessageHeader {
+                channel: 3,
+                typ: 2,
+                len: 100,
+            };
+            assert_eq!(header, expected, "cancelled after {} bytes", len);
+            assert_eq!(reader.peek().await.unwrap().unwrap(), expected);
+            assert_eq!(reader.next().await.unwrap().unwrap(), message);
+        }
+    });
+}
+
+#[test]
+fn skip_peeked_after_cancelled_next() {
+    block_on(async {
+        let first = Message::new(1, 1, vec![1; 50]);
+        let second = Message::new(2, 1, b"second".to_vec());
+        let bytes = encode(&[first, second.clone()]).await;
+        let (mut reader, mut peer) = cancelled_next(&bytes, 20).await;
+        peer.write_all(&bytes[20..]).await.unwrap();
+        assert_eq!(reader.peek().await.unwrap().unwrap().channel, 1);
+        reader.skip_peeked();
+        assert_eq!(reader.next().await.unwrap().unwrap(), second);
+    });
+}
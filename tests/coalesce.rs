@@ -0,0 +1,38 @@
+#![cfg(feature = "sim")]
+
+use futures::future::FutureExt;
+use futures::task::{noop_waker, Context};
+use simple_message_channels::{Message, Priority, SimClock, Writer};
+use std::time::Duration;
+
+const WINDOW: Duration = Duration::from_millis(10);
+
+#[test]
+fn window_starts_with_the_first_queued_message() {
+    let waker = noop_waker();
+    let mut cx = Context::from_waker(&waker);
+    let clock = SimClock::new();
+    let mut writer = Writer::new(Vec::new()).with_clock(clock.clone());
+    writer.set_coalesce_window(WINDOW);
+
+    for _ in 0..3 {
+        writer
+            .queue(Message::new(1, 1, b"hi".to_vec()), Priority::Normal)
+            .unwrap();
+        clock.advance(WINDOW - Duration::from_millis(2));
+        writer
+            .queue(Message::new(1, 2, b"hi".to_vec()), Priority::Normal)
+            .unwrap();
+
+        // The flush waits for the rest of the window of the first message.
+        let mut flush = Box::pin(writer.flush());
+        assert!(flush.poll_unpin(&mut cx).is_pending());
+        clock.advance(Duration::from_millis(2));
+        assert!(flush.poll_unpin(&mut cx).is_ready());
+        drop(flush);
+        assert_eq!(writer.buffered(), 0);
+
+        // The next window starts with the next message.
+        clock.advance(WINDOW * 2);
+    }
+}
@@ -0,0 +1,28 @@
+use simple_message_channels::{FrameCipher, SeekableCipher, SmcError};
+
+// A cipher whose keystream only depends on the position, to detect bytes that are
+// decrypted twice or not at all.
+#[derive(Default)]
+pub struct Xor {
+    position: u64,
+}
+
+impl FrameCipher for Xor {
+    fn apply(&mut self, buf: &mut [u8]) {
+        for byte in buf {
+            *byte ^= (self.position.wrapping_mul(0x9e37_79b9_7f4a_7c15) >> 56) as u8 | 1;
+            self.position += 1;
+        }
+    }
+}
+
+impl SeekableCipher for Xor {
+    fn position(&self) -> u64 {
+        self.position
+    }
+
+    fn seek(&mut self, position: u64) -> Result<(), SmcError> {
+        self.position = position;
+        Ok(())
+    }
+}
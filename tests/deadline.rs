@@ -0,0 +1,93 @@
+#![cfg(feature = "sim")]
+
+use futures::executor::block_on;
+use futures::future::FutureExt;
+use futures::task::{noop_waker, Context, Poll};
+use simple_message_channels::{codec, Clock, Message, Priority, SimClock, SmcError, Writer};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+fn encode(messages: &[&Message]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    for message in messages {
+        codec::encode_message_into(message, &mut buf).unwrap();
+    }
+    buf
+}
+
+// A writer that is out of rate budget, after writing `first`.
+fn writer(clock: &SimClock, first: &Message) -> Writer<Vec<u8>> {
+    let mut writer = Writer::new(Vec::new())
+        .with_clock(clock.clone())
+        .with_stats(true);
+    // 10 bytes per 100µs, and a burst of 10_000 bytes.
+    writer.set_rate_limit(100_000);
+    writer.queue(first.clone(), Priority::Low).unwrap();
+    writer
+}
+
+// Send `message` with a deadline that passes before the writer has budget again.
+fn send_late(clock: &SimClock, writer: &mut Writer<Vec<u8>>, message: Message) {
+    let waker = noop_waker();
+    let mut cx = Context::from_waker(&waker);
+    let deadline = clock.now() + Duration::from_micros(5);
+    let mut send = Box::pin(writer.send_with_deadline(message, deadline));
+    assert!(send.poll_unpin(&mut cx).is_pending());
+    clock.advance(Duration::from_micros(6));
+    match send.poll_unpin(&mut cx) {
+        Poll::Ready(Err(SmcError::SendTimeout)) => {}
+        _ => panic!("expected a timeout"),
+    }
+}
+
+// The channels of the messages that `writer` passes to its observer from now on.
+fn observe(writer: &mut Writer<Vec<u8>>) -> Arc<Mutex<Vec<u64>>> {
+    let channels = Arc::new(Mutex::new(Vec::new()));
+    let observed = channels.clone();
+    writer.set_frame_observer(move |_, message| observed.lock().unwrap().push(message.channel));
+    channels
+}
+
+// The frame of 2 length bytes, 1 header byte and the payload uses up the burst.
+fn burst() -> Message {
+    Message::new(0, 0, vec![0; 9_997])
+}
+
+#[test]
+fn late_message_is_dropped_while_queued() {
+    let clock = SimClock::new();
+    let (first, second) = (burst(), Message::new(0, 1, vec![1; 10]));
+    let mut writer = writer(&clock, &first);
+    writer.queue(second.clone(), Priority::Low).unwrap();
+    let observed = observe(&mut writer);
+
+    send_late(&clock, &mut writer, Message::new(1, 1, b"late".to_vec()));
+    assert_eq!(writer.buffered(), encode(&[&second]).len());
+    // The late message is not counted.
+    assert_eq!(writer.stats().unwrap().messages, 2);
+    assert!(writer.channel_stats(1).is_none());
+    assert!(observed.lock().unwrap().is_empty());
+
+    writer.clear_rate_limit();
+    block_on(writer.flush()).unwrap();
+    assert_eq!(writer.into_inner(), encode(&[&first, &second]));
+}
+
+#[test]
+fn late_message_is_written_once_buffered() {
+    let clock = SimClock::new();
+    let first = burst();
+    let mut writer = writer(&clock, &first);
+    let observed = observe(&mut writer);
+
+    // Nothing else is queued, so the message goes straight to the write buffer.
+    let late = Message::new(1, 1, b"late".to_vec());
+    send_late(&clock, &mut writer, late.clone());
+    assert_eq!(writer.buffered(), encode(&[&late]).len());
+    assert_eq!(writer.stats().unwrap().messages, 2);
+    assert_eq!(*observed.lock().unwrap(), [1]);
+
+    writer.clear_rate_limit();
+    block_on(writer.flush()).unwrap();
+    assert_eq!(writer.into_inner(), encode(&[&first, &late]));
+}
@@ -0,0 +1,108 @@
+#![cfg(feature = "sim")]
+
+use futures::channel::mpsc;
+use futures::stream::StreamExt;
+use futures::task::{noop_waker, Context, Poll};
+use simple_message_channels::{ConnectionEvent, Demux, Message, SimClock, SmcError};
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+fn message(channel: u64) -> Result<Message, SmcError> {
+    Ok(Message::new(channel, 1, vec![channel as u8]))
+}
+
+#[test]
+fn idle_channels_are_closed() {
+    let waker = noop_waker();
+    let mut cx = Context::from_waker(&waker);
+    let clock = SimClock::new();
+    let (messages, stream) = mpsc::unbounded();
+    let (demux, handle) = Demux::new(stream);
+    let mut demux = demux.with_idle_timeout(TIMEOUT).with_clock(clock.clone());
+    let mut events = demux.events();
+    let mut one = handle.receiver(1);
+    let mut two = handle.receiver(2);
+
+    messages.unbounded_send(message(1)).unwrap();
+    messages.unbounded_send(message(2)).unwrap();
+    assert!(demux.poll_next_unpin(&mut cx).is_pending());
+    clock.advance(TIMEOUT / 2);
+    messages.unbounded_send(message(2)).unwrap();
+    assert!(demux.poll_next_unpin(&mut cx).is_pending());
+
+    // Channel 1 is idle, its queued message is dropped.
+    clock.advance(TIMEOUT / 2);
+    assert!(demux.poll_next_unpin(&mut cx).is_pending());
+    assert_eq!(
+        events.poll_next_unpin(&mut cx),
+        Poll::Ready(Some(ConnectionEvent::ChannelIdle(1)))
+    );
+    assert_eq!(one.poll_next_unpin(&mut cx), Poll::Ready(None));
+    assert_eq!(two.queued(), 2);
+
+    // Later messages on channel 1 have no receiver.
+    messages.unbounded_send(message(1)).unwrap();
+    match demux.poll_next_unpin(&mut cx) {
+        Poll::Ready(Some(Ok(message))) => assert_eq!(message.channel, 1),
+        _ => panic!("expected the message of channel 1"),
+    }
+
+    clock.advance(TIMEOUT / 2);
+    assert!(demux.poll_next_unpin(&mut cx).is_pending());
+    assert_eq!(
+        events.poll_next_unpin(&mut cx),
+        Poll::Ready(Some(ConnectionEvent::ChannelIdle(2)))
+    );
+    assert!(events.poll_next_unpin(&mut cx).is_pending());
+    // Queued messages are dropped, the receiver ends.
+    assert_eq!(two.queued(), 0);
+    assert_eq!(two.poll_next_unpin(&mut cx), Poll::Ready(None));
+}
+
+#[test]
+fn dropped_receivers_are_not_idle() {
+    let waker = noop_waker();
+    let mut cx = Context::from_waker(&waker);
+    let clock = SimClock::new();
+    let (messages, stream) = mpsc::unbounded();
+    let (demux, handle) = Demux::new(stream);
+    let mut demux = demux.with_idle_timeout(TIMEOUT).with_clock(clock.clone());
+    let mut events = demux.events();
+    let receiver = handle.receiver(1);
+
+    messages.unbounded_send(message(1)).unwrap();
+    assert!(demux.poll_next_unpin(&mut cx).is_pending());
+    drop(receiver);
+    clock.advance(TIMEOUT);
+    assert!(demux.poll_next_unpin(&mut cx).is_pending());
+    drop(demux);
+    assert_eq!(events.poll_next_unpin(&mut cx), Poll::Ready(None));
+}
+
+#[test]
+fn receivers_without_messages_are_idle() {
+    let waker = noop_waker();
+    let mut cx = Context::from_waker(&waker);
+    let clock = SimClock::new();
+    let (_messages, stream) = mpsc::unbounded::<Result<Message, SmcError>>();
+    let (demux, handle) = Demux::new(stream);
+    // The clock can be set before the timeout.
+    let mut demux = demux.with_clock(clock.clone()).with_idle_timeout(TIMEOUT);
+    let mut events = demux.events();
+    assert!(demux.poll_next_unpin(&mut cx).is_pending());
+
+    clock.advance(TIMEOUT / 2);
+    let mut one = handle.receiver(1);
+    clock.advance(TIMEOUT / 2);
+    assert!(demux.poll_next_unpin(&mut cx).is_pending());
+    assert!(events.poll_next_unpin(&mut cx).is_pending());
+
+    clock.advance(TIMEOUT / 2);
+    assert!(demux.poll_next_unpin(&mut cx).is_pending());
+    assert_eq!(
+        events.poll_next_unpin(&mut cx),
+        Poll::Ready(Some(ConnectionEvent::ChannelIdle(1)))
+    );
+    assert_eq!(one.poll_next_unpin(&mut cx), Poll::Ready(None));
+}
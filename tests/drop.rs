@@ -0,0 +1,66 @@
+#![cfg(feature = "std")]
+
+use futures::executor::block_on;
+use futures::io::{AsyncRead, AsyncWrite};
+use futures::stream::StreamExt;
+use futures::task::{Context, Poll};
+use simple_message_channels::{codec, Channel, ConnectionEvent, Message, Priority, Writer};
+use std::io::{ErrorKind, Result};
+use std::pin::Pin;
+
+// A transport whose writes fail.
+struct Broken;
+
+impl AsyncRead for Broken {
+    fn poll_read(self: Pin<&mut Self>, _: &mut Context<'_>, _: &mut [u8]) -> Poll<Result<usize>> {
+        Poll::Ready(Ok(0))
+    }
+}
+
+impl AsyncWrite for Broken {
+    fn poll_write(self: Pin<&mut Self>, _: &mut Context<'_>, _: &[u8]) -> Poll<Result<usize>> {
+        Poll::Ready(Err(ErrorKind::BrokenPipe.into()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+fn message() -> Message {
+    Message::new(1, 1, b"hello".to_vec())
+}
+
+#[test]
+fn drop_after_a_failed_write_does_not_panic() {
+    let mut writer = Writer::new(Broken).with_drop_panic();
+    assert!(block_on(writer.send(message())).is_err());
+    assert!(writer.buffered() > 0);
+    drop(writer);
+}
+
+#[test]
+#[should_panic(expected = "unsent bytes")]
+fn drop_with_unflushed_messages_panics() {
+    let mut writer = Writer::new(futures::io::sink()).with_drop_panic();
+    writer.queue(message(), Priority::Normal).unwrap();
+    drop(writer);
+}
+
+#[test]
+fn drop_after_a_failed_write_is_an_event() {
+    let mut channel = Channel::new(Broken);
+    let events = channel.events();
+    let error = block_on(channel.send(message())).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::BrokenPipe);
+    drop(channel);
+
+    let mut frame = Vec::new();
+    codec::encode_message_into(&message(), &mut frame).unwrap();
+    let events: Vec<_> = block_on(events.collect());
+    assert_eq!(events, [ConnectionEvent::UnsentDropped(frame.len())]);
+}
@@ -0,0 +1,30 @@
+#![cfg(feature = "std")]
+
+use futures::executor::block_on;
+use futures::stream::StreamExt;
+use simple_message_channels::{FlowControl, Message, Mux, PipeStream, Reader, Writer, WINDOW_TYP};
+
+// The messages the mux sends for `flow`, once nothing else is sent.
+fn grants(flow: FlowControl) -> Vec<Message> {
+    let (stream, peer) = PipeStream::pair(1 << 16);
+    let (mux, handle) = Mux::new(Writer::new(stream));
+    drop(handle);
+    block_on(async {
+        mux.with_flow_control(flow).await.unwrap();
+        Reader::new(peer)
+            .map(Result::unwrap)
+            .collect::<Vec<_>>()
+            .await
+    })
+}
+
+#[test]
+fn window_of_one_byte_is_granted_again() {
+    let flow = FlowControl::new(1);
+    flow.open(1);
+    // Nothing is consumed, so nothing is granted.
+    flow.consume(1, 0);
+    flow.consume(1, 1);
+    let grant = Message::new(1, WINDOW_TYP, vec![1]);
+    assert_eq!(grants(flow), [grant.clone(), grant]);
+}
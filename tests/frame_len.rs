@@ -0,0 +1,84 @@
+#![cfg(feature = "std")]
+
+use futures::executor::block_on;
+use futures::io::Cursor;
+use futures::stream::StreamExt;
+use simple_message_channels::codec::{decode_frame, encode_message_into};
+use simple_message_channels::{sync, varint, Message, Reader, SmcError, MAX_MESSAGE_SIZE};
+
+// A frame with a body of `len` bytes: the header of channel 0 and typ 1, and the payload.
+fn frame(len: u64) -> Vec<u8> {
+    let mut frame = vec![0; varint::MAX_VARINT_LEN];
+    let n = varint::encode(len, &mut frame);
+    frame.truncate(n);
+    frame.push(1);
+    frame.resize(n + len as usize, 7);
+    frame
+}
+
+// A length prefix of `len`, without the frame.
+fn prefix(len: u64) -> Vec<u8> {
+    let mut prefix = vec![0; varint::MAX_VARINT_LEN];
+    let n = varint::encode(len, &mut prefix);
+    prefix.truncate(n);
+    prefix
+}
+
+fn read(wire: &[u8]) -> Result<Message, SmcError> {
+    let mut reader = Reader::new(Cursor::new(wire.to_vec()));
+    block_on(reader.next()).expect("a message or an error")
+}
+
+fn assert_too_long(result: Result<Message, SmcError>, len: u64) {
+    match result {
+        Err(SmcError::MessageTooLong(l)) => assert_eq!(l, len),
+        Err(error) => panic!("expected MessageTooLong({}), got {}", len, error),
+        Ok(_) => panic!("expected MessageTooLong({})", len),
+    }
+}
+
+#[test]
+fn frame_of_the_max_size_is_decoded() {
+    let frame = frame(MAX_MESSAGE_SIZE);
+    let (message, len) = decode_frame(&frame).unwrap().unwrap();
+    assert_eq!(len, frame.len());
+    assert_eq!(message.message.len() as u64, MAX_MESSAGE_SIZE - 1);
+    assert_eq!(read(&frame).unwrap(), message);
+    assert_eq!(
+        sync::Reader::new(&frame[..]).read::<Vec<u8>>().unwrap(),
+        message
+    );
+
+    // The encoder counts the length prefix too, so it refuses this frame.
+    let mut buf = Vec::new();
+    match encode_message_into(&message, &mut buf) {
+        Err(SmcError::MessageTooLong(len)) => assert_eq!(len, frame.len() as u64),
+        _ => panic!("expected the encoder to refuse the frame"),
+    }
+}
+
+#[test]
+fn frame_longer_than_the_max_size_is_rejected() {
+    let frame = frame(MAX_MESSAGE_SIZE + 1);
+    assert!(matches!(
+        decode_frame(&frame),
+        Err(SmcError::MessageTooLong(len)) if len == MAX_MESSAGE_SIZE + 1
+    ));
+    assert_too_long(read(&frame), MAX_MESSAGE_SIZE + 1);
+    assert_too_long(sync::Reader::new(&frame[..]).read(), MAX_MESSAGE_SIZE + 1);
+}
+
+#[test]
+fn long_length_varints_are_rejected() {
+    for len in [1 << 28, 1 << 32, 1 << 35, 1 << 49, u64::MAX] {
+        let prefix = prefix(len);
+        assert!(prefix.len() >= 5);
+        // The length is rejected before the body is read.
+        assert!(matches!(
+            decode_frame(&prefix),
+            Err(SmcError::MessageTooLong(l)) if l == len
+        ));
+        assert_too_long(read(&prefix), len);
+        assert_too_long(sync::Reader::new(&prefix[..]).read(), len);
+    }
+}
@@ -0,0 +1,153 @@
+//! Interop tests against the JS implementation of simple-message-channels.
+//!
+//! Each test spawns `interop/peer.js` with node, which decodes the frames sent over its
+//! stdin with the reference implementation, and encodes the messages again on its
+//! stdout. The frames that come back must be byte for byte the frames that were sent.
+//!
+//! The tests need node and the JS package, so they are ignored by default. Run them with
+//! `interop/run.sh`, which installs the package and runs the ignored tests. Set `NODE`
+//! to the node binary if it is not on the `PATH`.
+//!
+//! The JS implementation computes the header with 32 bit integers, so the largest
+//! channel it supports is 2^27 - 1.
+#![cfg(feature = "std")]
+
+use simple_message_channels::codec::{encode_message_into, Decoder};
+use simple_message_channels::sync::Writer;
+use simple_message_channels::Message;
+use std::env;
+use std::io::Read;
+use std::process::{Command, Stdio};
+use std::thread;
+
+/// The largest channel of the JS implementation.
+const MAX_JS_CHANNEL: u64 = (1 << 27) - 1;
+
+// The message that asks the peer to send a keepalive.
+fn keepalive_request() -> Message {
+    Message::new(0, 15, vec![])
+}
+
+// The bytes the peer sends back for `messages`.
+fn expected(messages: &[Message]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    for message in messages {
+        if *message == keepalive_request() {
+            buf.push(0);
+        } else if !message.is_keepalive() {
+            encode_message_into(message, &mut buf).unwrap();
+        }
+    }
+    buf
+}
+
+// Send `messages` to the JS peer, one by one or with a single `send_batch`, and check
+// the frames that it sends back.
+fn echo(messages: Vec<Message>, batch: bool) {
+    let node = env::var("NODE").unwrap_or_else(|_| "node".to_string());
+    let peer = concat!(env!("CARGO_MANIFEST_DIR"), "/interop/peer.js");
+    let mut child = Command::new(node)
+        .arg(peer)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("spawn node");
+    let stdin = child.stdin.take().unwrap();
+    let mut stdout = child.stdout.take().unwrap();
+
+    // Send from another thread, so that the echo is read while large messages are sent.
+    let sent = messages.clone();
+    let sender = thread::spawn(move || {
+        let mut writer = Writer::new(stdin);
+        match batch {
+            true => writer.send_batch(&sent),
+            false => sent.into_iter().try_for_each(|m| writer.send(m)),
+        }
+        .unwrap();
+        // Dropping the writer closes stdin, and the peer exits.
+    });
+
+    let mut echo = Vec::new();
+    stdout.read_to_end(&mut echo).unwrap();
+    sender.join().unwrap();
+    assert!(child.wait().unwrap().success(), "peer failed");
+
+    let expected = expected(&messages);
+    assert_eq!(echo.len(), expected.len());
+    if let Some(i) = (0..echo.len()).find(|&i| echo[i] != expected[i]) {
+        panic!("frames differ at byte {}", i);
+    }
+
+    let decoded = Decoder::new().with_keepalives(true).push(&echo).unwrap();
+    let messages: Vec<_> = messages
+        .into_iter()
+        .filter(|message| !message.is_keepalive())
+        .map(|message| match message == keepalive_request() {
+            true => Message::keepalive(),
+            false => message,
+        })
+        .collect();
+    assert_eq!(decoded, messages);
+}
+
+#[test]
+#[ignore = "needs node, run interop/run.sh"]
+fn messages() {
+    echo(
+        (0..16)
+            .map(|typ| Message::new(typ as u64, typ, format!("hi {}", typ).into_bytes()))
+            .collect(),
+        false,
+    );
+}
+
+#[test]
+#[ignore = "needs node, run interop/run.sh"]
+fn empty_payloads() {
+    echo(
+        vec![Message::new(1, 0, vec![]), Message::new(0, 1, vec![])],
+        false,
+    );
+}
+
+#[test]
+#[ignore = "needs node, run interop/run.sh"]
+fn keepalives() {
+    echo(
+        vec![
+            Message::keepalive(),
+            Message::new(1, 1, b"after a keepalive".to_vec()),
+            Message::keepalive(),
+            Message::keepalive(),
+            Message::new(2, 2, b"after two".to_vec()),
+            keepalive_request(),
+            Message::new(3, 3, b"after a keepalive of the peer".to_vec()),
+        ],
+        false,
+    );
+}
+
+#[test]
+#[ignore = "needs node, run interop/run.sh"]
+fn large_varints() {
+    echo(
+        vec![
+            Message::new(MAX_JS_CHANNEL, 15, vec![1; 10]),
+            Message::new(1 << 14, 7, vec![2; 200]),
+            Message::new(1, 1, vec![3; 20_000]),
+            Message::new(2, 2, vec![4; 4 << 20]),
+        ],
+        false,
+    );
+}
+
+#[test]
+#[ignore = "needs node, run interop/run.sh"]
+fn batch() {
+    echo(
+        (0..100)
+            .map(|i| Message::new(i % 7, (i % 16) as u8, vec![i as u8; i as usize * 10]))
+            .collect(),
+        true,
+    );
+}
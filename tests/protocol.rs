@@ -0,0 +1,50 @@
+#![cfg(feature = "protocol")]
+
+use futures::executor::block_on;
+use futures::io::Cursor;
+use simple_message_channels::protocol::{discovery_key, Event, Protocol};
+use simple_message_channels::{codec, Message, MessageType, Reader, Writer};
+use std::io::ErrorKind;
+
+// The `Open` message for `key`, on the remote `channel`.
+fn open(channel: u64, key: &[u8]) -> Message {
+    // The discovery key is field 1 of the protobuf payload.
+    let mut payload = vec![0x0a, 32];
+    payload.extend(discovery_key(key).unwrap());
+    Message::with_type(channel, MessageType::Open, payload)
+}
+
+#[test]
+fn remote_channel_opened_twice_is_rejected() {
+    let mut wire = Vec::new();
+    for message in [open(7, b"first"), open(7, b"second")] {
+        codec::encode_message_into(&message, &mut wire).unwrap();
+    }
+    let reader = Reader::new(Cursor::new(wire));
+    let mut protocol = Protocol::new(reader, Writer::new(Vec::new()));
+
+    block_on(async {
+        match protocol.next().await {
+            Some(Ok(Event::DiscoveryKey(key))) => assert_eq!(key, discovery_key(b"first").unwrap()),
+            _ => panic!("expected the discovery key of the first channel"),
+        }
+        match protocol.next().await {
+            Some(Err(error)) => assert_eq!(error.kind(), ErrorKind::InvalidData),
+            _ => panic!("expected an error for the second open"),
+        }
+
+        // The remote id still belongs to the first channel.
+        let channel = protocol.open(b"first").await.unwrap();
+        match protocol.next().await {
+            Some(Ok(Event::ChannelOpened {
+                discovery_key: key,
+                channel: opened,
+            })) => {
+                assert_eq!(key, discovery_key(b"first").unwrap());
+                assert_eq!(opened, channel);
+            }
+            _ => panic!("expected the first channel to open"),
+        }
+        assert!(protocol.is_opened(channel));
+    });
+}
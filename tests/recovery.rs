@@ -0,0 +1,32 @@
+#![cfg(feature = "std")]
+
+mod common;
+
+use common::Xor;
+use futures::executor::block_on;
+use futures::io::Cursor;
+use futures::stream::StreamExt;
+use simple_message_channels::{CipherStart, Message, OnError, Reader, SmcError, Writer};
+
+#[test]
+fn resync_keeps_the_offset_and_the_cipher_in_sync() {
+    block_on(async {
+        let message = Message::new(1, 1, b"after the garbage".to_vec());
+        let mut writer = Writer::new(Vec::new()).with_cipher(Xor::default());
+        writer.send(message.clone()).await.unwrap();
+        // An invalid length prefix in plain text, before the cipher starts.
+        let mut wire = vec![0xff; 10];
+        wire.extend(writer.into_inner());
+        let mut reader = Reader::new(Cursor::new(wire.clone()))
+            .with_cipher(Xor::default())
+            .cipher_start_after(CipherStart::Bytes(10))
+            .with_on_error(OnError::Resync);
+        assert!(matches!(
+            reader.next().await,
+            Some(Err(SmcError::Varint(_)))
+        ));
+        assert_eq!(reader.offset(), 10);
+        assert_eq!(reader.next().await.unwrap().unwrap(), message);
+        assert_eq!(reader.offset(), wire.len() as u64);
+    });
+}
@@ -0,0 +1,200 @@
+#![cfg(feature = "std")]
+
+use futures::executor::block_on;
+use futures::io::{AsyncReadExt, AsyncWriteExt};
+use futures::stream::StreamExt;
+use futures::task::{noop_waker, Context, Poll};
+mod common;
+
+use common::Xor;
+use simple_message_channels::{
+    Channel, FlushPolicy, Message, PipeStream, SessionState, SmcError, Writer,
+};
+
+fn messages() -> Vec<Message> {
+    (0..4)
+        .map(|i| Message::new(i, 1, vec![i as u8; 300 + i as usize]))
+        .collect()
+}
+
+async fn wire(messages: &[Message]) -> Vec<u8> {
+    let mut writer = Writer::new(Vec::new()).with_cipher(Xor::default());
+    writer.send_batch(messages).await.unwrap();
+    writer.into_inner()
+}
+
+fn channel() -> (Channel<PipeStream, Xor>, PipeStream) {
+    let (stream, peer) = PipeStream::pair(1 << 16);
+    let channel = Channel::new(stream).with_cipher(Xor::default(), Xor::default());
+    (channel, peer)
+}
+
+// Read the messages the channel can read without waiting.
+fn read_ready(channel: &mut Channel<PipeStream, Xor>, messages: &mut Vec<Message>) {
+    let waker = noop_waker();
+    let mut cx = Context::from_waker(&waker);
+    while let Poll::Ready(Some(message)) = channel.poll_next_unpin(&mut cx) {
+        messages.push(message.unwrap());
+    }
+}
+
+fn migrate(old: &mut Channel<PipeStream, Xor>) -> (Channel<PipeStream, Xor>, PipeStream) {
+    let state = old.export_state().unwrap();
+    let state = SessionState::from_bytes(&state.to_bytes()).unwrap();
+    let (channel, peer) = channel();
+    (channel.import_state(&state).unwrap(), peer)
+}
+
+#[test]
+fn migrate_twice_without_reading() {
+    block_on(async {
+        let expected = messages();
+        let wire = wire(&expected).await;
+        for cut in [0, 1, 2, 3, 150, 303, 304, 305, 600, 900, wire.len() - 1] {
+            let (mut first, mut peer) = channel();
+            peer.write_all(&wire[..cut]).await.unwrap();
+            // Read at most one message, the other bytes are read ahead.
+            let waker = noop_waker();
+            let mut received = Vec::new();
+            if let Poll::Ready(Some(message)) =
+                first.poll_next_unpin(&mut Context::from_waker(&waker))
+            {
+                received.push(message.unwrap());
+            }
+            let (mut second, _) = migrate(&mut first);
+            let (mut third, mut peer) = migrate(&mut second);
+            peer.write_all(&wire[cut..]).await.unwrap();
+            peer.close().await.unwrap();
+            while let Some(message) = third.next().await {
+                received.push(message.unwrap());
+            }
+            assert_eq!(received, expected, "cut at {}", cut);
+        }
+    });
+}
+
+#[test]
+fn migrate_twice_after_reading_part_of_the_prefix() {
+    block_on(async {
+        let expected = messages();
+        let wire = wire(&expected).await;
+        let cut = 700;
+        let (mut first, mut peer) = channel();
+        peer.write_all(&wire[..cut]).await.unwrap();
+        // The other bytes are read ahead.
+        let mut received = vec![first.next().await.unwrap().unwrap()];
+        let (mut second, _second_peer) = migrate(&mut first);
+        read_ready(&mut second, &mut received);
+        assert_eq!(received.len(), 2);
+        let (mut third, mut peer) = migrate(&mut second);
+        peer.write_all(&wire[cut..]).await.unwrap();
+        peer.close().await.unwrap();
+        while let Some(message) = third.next().await {
+            received.push(message.unwrap());
+        }
+        assert_eq!(received, expected);
+    });
+}
+
+#[test]
+fn migrate_queued_messages() {
+    block_on(async {
+        let expected = messages();
+        let wire = wire(&expected).await;
+        let (first, mut peer) = channel();
+        let mut first = first.with_flush_policy(FlushPolicy::Manual);
+        first.send(expected[0].clone()).await.unwrap();
+        first.flush().await.unwrap();
+        first.send(expected[1].clone()).await.unwrap();
+        first.send(expected[2].clone()).await.unwrap();
+        let (second, mut second_peer) = migrate(&mut first);
+        let mut second = second.with_flush_policy(FlushPolicy::Manual);
+        second.send(expected[3].clone()).await.unwrap();
+        second.close().await.unwrap();
+        let mut written = vec![0; 303];
+        peer.read_exact(&mut written).await.unwrap();
+        second_peer.read_to_end(&mut written).await.unwrap();
+        assert_eq!(written, wire);
+    });
+}
+
+#[test]
+fn failed_export_keeps_the_channel() {
+    block_on(async {
+        let (mut channel, mut peer) = channel();
+        channel.close().await.unwrap();
+        assert!(matches!(channel.export_state(), Err(SmcError::Closed)));
+        let message = Message::new(1, 1, b"still there".to_vec());
+        let wire = wire(std::slice::from_ref(&message)).await;
+        peer.write_all(&wire).await.unwrap();
+        assert_eq!(channel.next().await.unwrap().unwrap(), message);
+    });
+}
+
+#[test]
+fn exported_channel_stops() {
+    block_on(async {
+        let expected = messages();
+        let wire = wire(&expected).await;
+        let (first, mut peer) = channel();
+        let mut first = first.with_flush_policy(FlushPolicy::Manual);
+        first.send(expected[0].clone()).await.unwrap();
+        peer.write_all(&wire).await.unwrap();
+        // The other messages are read ahead.
+        assert_eq!(first.next().await.unwrap().unwrap(), expected[0]);
+        let (mut second, mut second_peer) = migrate(&mut first);
+
+        // The queued message and the bytes that were read ahead moved to the state.
+        assert!(matches!(first.flush().await, Err(SmcError::Closed)));
+        assert!(matches!(
+            first.send(expected[1].clone()).await,
+            Err(SmcError::Closed)
+        ));
+        assert!(first.next().await.is_none());
+        let (transport, buffered) = first.into_inner();
+        assert!(buffered.is_empty());
+        drop(transport);
+        assert_eq!(peer.read(&mut [0; 16]).await.unwrap(), 0);
+
+        let mut received = Vec::new();
+        read_ready(&mut second, &mut received);
+        assert_eq!(received, expected[1..]);
+        second.close().await.unwrap();
+        let mut written = Vec::new();
+        second_peer.read_to_end(&mut written).await.unwrap();
+        assert_eq!(written, wire[..303]);
+    });
+}
+
+// A state of two fresh streams, whose ciphers are at `read` and `write`.
+#[cfg(feature = "chacha20")]
+fn state_at(read: u64, write: u64) -> SessionState {
+    use simple_message_channels::varint::{self, MAX_VARINT_LEN};
+
+    let mut buf = vec![1];
+    for position in [read, write] {
+        let mut varint = [0; MAX_VARINT_LEN];
+        let len = varint::encode(position, &mut varint);
+        buf.extend_from_slice(&[0, 0, 0]);
+        buf.extend_from_slice(&varint[..len]);
+        buf.extend_from_slice(&[0, 0]);
+    }
+    SessionState::from_bytes(&buf).unwrap()
+}
+
+#[cfg(feature = "chacha20")]
+#[test]
+fn position_past_the_keystream_is_invalid() {
+    use chacha20::ChaCha20;
+    use simple_message_channels::KeyedCipher;
+
+    let cipher = || ChaCha20::with_key_nonce(&[0; 32], &[0; 12]).unwrap();
+    for state in [state_at(u64::MAX, 0), state_at(0, u64::MAX)] {
+        let (stream, _peer) = PipeStream::pair(1 << 16);
+        let channel = Channel::new(stream).with_cipher(cipher(), cipher());
+        assert!(matches!(
+            channel.import_state(&state),
+            Err(SmcError::InvalidSession)
+        ));
+    }
+}
@@ -0,0 +1,36 @@
+//! smol IO types implement the `futures::io` traits, so they need no adapter, only
+//! the `std` feature.
+#![cfg(feature = "std")]
+
+use futures::stream::StreamExt;
+use simple_message_channels::{Channel, Message, SmcError};
+use smol::net::{TcpListener, TcpStream};
+
+#[test]
+fn echo_over_tcp() {
+    smol::block_on(async {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let address = listener.local_addr()?;
+        let server = smol::spawn(async move {
+            let (stream, _) = listener.accept().await?;
+            let mut channel = Channel::new(stream);
+            while let Some(message) = channel.next().await {
+                channel.send(message?).await?;
+            }
+            Ok::<(), SmcError>(())
+        });
+
+        let mut channel = Channel::new(TcpStream::connect(address).await?);
+        let messages: Vec<Message> = (0..10)
+            .map(|i| Message::new(i, 1, vec![i as u8; 1000 * i as usize]))
+            .collect();
+        channel.writer().send_batch(&messages).await?;
+        for message in &messages {
+            assert_eq!(&channel.next().await.unwrap()?, message);
+        }
+        channel.close().await?;
+        assert!(channel.next().await.is_none());
+        server.await
+    })
+    .unwrap();
+}
@@ -0,0 +1,33 @@
+#![cfg(feature = "tokio")]
+
+use futures::stream::StreamExt;
+use simple_message_channels::{Message, Reader, SmcError, Writer};
+use tokio::net::{TcpListener, TcpStream};
+
+#[tokio::test]
+async fn echo_over_tcp() -> Result<(), SmcError> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let address = listener.local_addr()?;
+    let server = tokio::spawn(async move {
+        let (stream, _) = listener.accept().await?;
+        let (read, write) = stream.into_split();
+        let (mut reader, mut writer) = (Reader::from_tokio(read), Writer::from_tokio(write));
+        while let Some(message) = reader.next().await {
+            writer.send(message?).await?;
+        }
+        Ok::<(), SmcError>(())
+    });
+
+    let (read, write) = TcpStream::connect(address).await?.into_split();
+    let (mut reader, mut writer) = (Reader::from_tokio(read), Writer::from_tokio(write));
+    let messages: Vec<Message> = (0..10)
+        .map(|i| Message::new(i, 1, vec![i as u8; 1000 * i as usize]))
+        .collect();
+    writer.send_batch(&messages).await?;
+    for message in &messages {
+        assert_eq!(&reader.next().await.unwrap()?, message);
+    }
+    writer.close().await?;
+    assert!(reader.next().await.is_none());
+    server.await.expect("server panicked")
+}
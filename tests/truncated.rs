@@ -0,0 +1,49 @@
+#![cfg(feature = "std")]
+
+use futures::executor::block_on;
+use futures::io::Cursor;
+use futures::stream::StreamExt;
+use simple_message_channels::{codec, sync, Message, Reader, SmcError, StreamingReader};
+
+// A frame of a message with a 2 byte length prefix, followed by the first byte of the
+// next one.
+fn wire() -> (Message, Vec<u8>) {
+    let message = Message::new(1, 1, vec![1; 200]);
+    let mut wire = Vec::new();
+    codec::encode_message_into(&message, &mut wire).unwrap();
+    let next = wire[0];
+    assert!(next & 0x80 != 0);
+    wire.push(next);
+    (message, wire)
+}
+
+fn assert_truncated_prefix<T>(result: Result<T, SmcError>) {
+    match result {
+        Err(SmcError::TruncatedFrame {
+            expected: 0,
+            got: 1,
+        }) => {}
+        Err(error) => panic!("expected a truncated frame, got {}", error),
+        Ok(_) => panic!("expected a truncated frame"),
+    }
+}
+
+#[test]
+fn end_in_the_length_prefix_is_a_truncated_frame() {
+    let (message, wire) = wire();
+
+    block_on(async {
+        let mut reader = Reader::new(Cursor::new(wire.clone()));
+        assert_eq!(reader.next().await.unwrap().unwrap(), message);
+        assert_truncated_prefix(reader.next().await.unwrap());
+        assert!(reader.next().await.is_none());
+
+        let mut reader = StreamingReader::new(Cursor::new(wire.clone()));
+        reader.next().await.unwrap();
+        assert_truncated_prefix(reader.next().await.map(|_| ()));
+    });
+
+    let mut reader = sync::Reader::new(&wire[..]);
+    assert_eq!(reader.read::<Vec<u8>>().unwrap(), message);
+    assert_truncated_prefix(reader.read::<Vec<u8>>());
+}
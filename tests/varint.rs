@@ -0,0 +1,143 @@
+use simple_message_channels::varint::{self, VarintDecoder, MAX_VARINT_LEN};
+use simple_message_channels::VarintError;
+
+const CASES: usize = 10_000;
+
+// Run `property` with `CASES` random inputs, from a fixed seed so failures reproduce.
+fn check(property: impl Fn(&mut fastrand::Rng)) {
+    let mut rng = fastrand::Rng::with_seed(0x5eed);
+    for _ in 0..CASES {
+        property(&mut rng);
+    }
+}
+
+// A value of a random bit width, so that all encoded lengths are covered.
+fn value(rng: &mut fastrand::Rng) -> u64 {
+    rng.u64(..) >> rng.u32(0..64)
+}
+
+fn encode(value: u64) -> Vec<u8> {
+    let mut buf = [0u8; MAX_VARINT_LEN];
+    let len = varint::encode(value, &mut buf);
+    buf[..len].to_vec()
+}
+
+// Bytes with the continuation bit set, and random value bits.
+fn continuation(rng: &mut fastrand::Rng, len: usize) -> Vec<u8> {
+    (0..len).map(|_| rng.u8(..) | 0x80).collect()
+}
+
+#[test]
+fn round_trip() {
+    check(|rng| {
+        let value = value(rng);
+        let mut buf = encode(value);
+        let len = buf.len();
+        assert_eq!(len, varint::length(value), "{}", value);
+        // Bytes after the varint are not read.
+        buf.extend((0..rng.usize(..4)).map(|_| rng.u8(..)));
+        assert_eq!(varint::decode(&buf), Ok((value, len)), "{:x?}", buf);
+
+        let mut decoder = VarintDecoder::new();
+        for byte in &buf[..len - 1] {
+            assert_eq!(decoder.push(*byte), Ok(None), "{:x?}", buf);
+        }
+        assert_eq!(decoder.push(buf[len - 1]), Ok(Some(value)), "{:x?}", buf);
+        assert!(decoder.is_empty());
+    });
+}
+
+#[test]
+fn prefix_is_incomplete() {
+    check(|rng| {
+        let buf = encode(value(rng));
+        let prefix = &buf[..rng.usize(..buf.len())];
+        assert_eq!(
+            varint::decode(prefix),
+            Err(VarintError::Incomplete),
+            "{:x?}",
+            prefix
+        );
+    });
+}
+
+#[test]
+fn padding_is_non_canonical() {
+    check(|rng| {
+        let mut buf = encode(value(rng));
+        if buf.len() == MAX_VARINT_LEN {
+            return;
+        }
+        // Pad the varint with zero value bits, up to the max length.
+        *buf.last_mut().unwrap() |= 0x80;
+        let padding = rng.usize(..MAX_VARINT_LEN - buf.len());
+        buf.extend(std::iter::repeat_n(0x80, padding));
+        buf.push(0);
+        assert_eq!(
+            varint::decode(&buf),
+            Err(VarintError::NonCanonical),
+            "{:x?}",
+            buf
+        );
+    });
+}
+
+#[test]
+fn too_many_bits_overflow() {
+    check(|rng| {
+        let mut buf = continuation(rng, MAX_VARINT_LEN - 1);
+        // The last byte of a u64 can only carry one bit.
+        buf.push(rng.u8(2..=0x7f) | (rng.u8(..) & 0x80));
+        assert_eq!(
+            varint::decode(&buf),
+            Err(VarintError::Overflow),
+            "{:x?}",
+            buf
+        );
+    });
+}
+
+#[test]
+fn too_many_bytes_are_too_long() {
+    check(|rng| {
+        let mut buf = continuation(rng, MAX_VARINT_LEN - 1);
+        buf.push(0x80 | rng.u8(..2));
+        buf.extend((0..rng.usize(1..4)).map(|_| rng.u8(..)));
+        assert_eq!(
+            varint::decode(&buf),
+            Err(VarintError::TooLong),
+            "{:x?}",
+            buf
+        );
+    });
+}
+
+#[test]
+fn random_bytes_decode_canonically_or_fail() {
+    check(|rng| {
+        let buf: Vec<u8> = (0..rng.usize(..12)).map(|_| rng.u8(..)).collect();
+        match varint::decode(&buf) {
+            Ok((value, len)) => assert_eq!(encode(value), &buf[..len], "{:x?}", buf),
+            Err(VarintError::Incomplete) => assert!(buf.iter().all(|b| b & 0x80 != 0)),
+            Err(_) => {}
+        }
+    });
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn read_write_round_trip() {
+    futures::executor::block_on(async {
+        let mut rng = fastrand::Rng::with_seed(0x5eed);
+        let values: Vec<u64> = (0..1000).map(|_| value(&mut rng)).collect();
+        let mut buf = Vec::new();
+        for value in &values {
+            let len = varint::write_varint(&mut buf, *value).await.unwrap();
+            assert_eq!(len, varint::length(*value));
+        }
+        let mut reader = futures::io::Cursor::new(buf);
+        for value in &values {
+            assert_eq!(varint::read_varint(&mut reader).await.unwrap(), *value);
+        }
+    });
+}